@@ -1,17 +1,330 @@
-#[derive(Debug)]
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, sync::Arc, time::Duration};
+
+use super::token_siphon::{self, GatewayKeyConfig, GatewayKeyError};
+use crate::{
+    environment::Environment,
+    forgerock::{self, ForgeRockError, Jwt},
+    http::{self, HttpClient, RetryPolicy},
+    redact,
+    storage::CredentialStore,
+};
+
 pub struct ApiClient {
+    /// Our shared HTTP client.
+    http: HttpClient,
+    /// The hosts we communicate with.
+    environment: Environment,
     /// The internal access token across API requests.
     access_token: String,
     /// The parsed GUID from the access token.
     guid: String,
+    /// How to obtain the API gateway key once more, should it be rotated.
+    gateway_key_config: GatewayKeyConfig,
+    /// Where our API gateway key is persisted.
+    store: Arc<dyn CredentialStore>,
+}
+
+impl fmt::Debug for ApiClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Our access token should never be printed.
+        f.debug_struct("ApiClient")
+            .field("environment", &self.environment)
+            .field("access_token", &redact::REDACTED)
+            .field("guid", &self.guid)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A request against the OneApp API.
+#[derive(Debug, Clone)]
+pub struct ApiRequest {
+    pub method: Method,
+    /// The path of this request, relative to the API's base URL.
+    pub path: String,
+    /// Additional headers, such as the VIN this request concerns.
+    pub headers: Vec<(String, String)>,
+    /// A JSON body to send alongside this request.
+    pub body: Option<serde_json::Value>,
+    /// How to retry this request, rather than the client's default.
+    pub retry: Option<RetryPolicy>,
 }
 
+impl ApiRequest {
+    /// Creates a GET request for the given path.
+    pub fn get(path: &str) -> Self {
+        Self {
+            method: Method::GET,
+            path: path.to_string(),
+            headers: Vec::new(),
+            body: None,
+            retry: None,
+        }
+    }
+
+    /// Creates a POST request for the given path with a JSON body.
+    pub fn post(path: &str, body: serde_json::Value) -> Self {
+        Self {
+            method: Method::POST,
+            path: path.to_string(),
+            headers: Vec::new(),
+            body: Some(body),
+            retry: None,
+        }
+    }
+
+    /// Adds the given header to this request.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Retries this request via the given policy, rather than the client's default.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+}
+
+/// A request as it would be sent, with secrets redacted, such as for `--dry-run`.
+#[derive(Serialize, Debug, Clone)]
+pub struct PreparedRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<serde_json::Value>,
+}
+
+impl PreparedRequest {
+    fn new(request: &reqwest::Request) -> Self {
+        let headers = request
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                let value = if redact::is_secret_header(name.as_str()) {
+                    redact::REDACTED.to_string()
+                } else {
+                    redact::redact(&String::from_utf8_lossy(value.as_bytes()))
+                };
+                (name.to_string(), value)
+            })
+            .collect();
+        let body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .and_then(|body| serde_json::from_slice(body).ok())
+            .map(|body| redact::redact_json(&body));
+
+        Self {
+            method: request.method().to_string(),
+            url: redact::redact_url(request.url().as_str()),
+            headers,
+            body,
+        }
+    }
+}
+
+/// Possible errors while working with the OneApp API.
 #[derive(Debug)]
-pub enum ApiError {}
+pub enum ApiError {
+    Reqwest(reqwest::Error),
+    Parse(serde_json::Error),
+    /// We were unable to obtain a usable API gateway key.
+    GatewayKey(GatewayKeyError),
+    /// The API gateway rejected our key, even after obtaining it once more.
+    InvalidGatewayKey,
+    /// The API responded with an unexpected status code and body.
+    Status(StatusCode, String),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Reqwest(err) => write!(f, "request failed: {err}"),
+            ApiError::Parse(err) => write!(f, "unable to parse response: {err}"),
+            ApiError::GatewayKey(err) => write!(f, "{err}"),
+            ApiError::InvalidGatewayKey => write!(f, "the API gateway rejected our key"),
+            ApiError::Status(status, body) => write!(f, "unexpected response {status}: {body}"),
+        }
+    }
+}
+
+impl ApiError {
+    /// Whether trying again later might succeed, as with connection errors or 5xx responses.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ApiError::Reqwest(_) => true,
+            ApiError::Status(status, _) => http::is_transient(*status),
+            _ => false,
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// The error format returned by the API gateway itself.
+#[derive(Deserialize)]
+struct GatewayErrorFormat {
+    message: String,
+}
+
+/// Whether the given response indicates that our API gateway key was rejected.
+///
+/// The gateway responds with 403 Forbidden, and a message such as
+/// `Forbidden` or `Invalid API Key`, prior to the request reaching Toyota.
+fn is_invalid_gateway_key(status: StatusCode, body: &str) -> bool {
+    if status != StatusCode::FORBIDDEN {
+        return false;
+    }
+
+    let Ok(error) = serde_json::from_str::<GatewayErrorFormat>(body) else {
+        return false;
+    };
+    let message = error.message.to_lowercase();
+    message == "forbidden" || message.contains("api key")
+}
 
 impl ApiClient {
     /// Creates a new API client around the given access token and GUID.
-    pub fn new(access_token: String, guid: String) -> Self {
-        Self { access_token, guid }
+    /// The given store is used to persist our API gateway key, should it be refreshed.
+    pub fn new(
+        http: HttpClient,
+        environment: Environment,
+        access_token: String,
+        guid: String,
+        store: Arc<dyn CredentialStore>,
+    ) -> Self {
+        Self {
+            http,
+            environment,
+            access_token,
+            guid,
+            gateway_key_config: GatewayKeyConfig::default(),
+            store,
+        }
+    }
+
+    /// The environment this client communicates with.
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    /// The GUID of the account this client is authenticated as.
+    pub fn guid(&self) -> &str {
+        &self.guid
+    }
+
+    /// How long until our access token expires, if it has not already.
+    pub fn expires_in(&self) -> Option<Duration> {
+        Jwt::decode(&self.access_token).ok()?.expires_in()
+    }
+
+    /// Specifies how to obtain the API gateway key should it need to be refreshed.
+    pub fn with_gateway_key_config(mut self, config: GatewayKeyConfig) -> Self {
+        self.gateway_key_config = config;
+        self
+    }
+
+    /// A new client around freshly refreshed tokens, such as before ours expire.
+    pub async fn refreshed(&self) -> Result<ApiClient, ForgeRockError> {
+        let client = forgerock::refresh(&self.http, &self.environment, self.store.clone()).await?;
+        Ok(client.with_gateway_key_config(self.gateway_key_config.clone()))
+    }
+
+    /// Builds the given request with our credentials, obtaining the API gateway key if necessary.
+    async fn build(&self, request: &ApiRequest) -> Result<RequestBuilder, ApiError> {
+        let gateway_key = match token_siphon::api_gateway_key() {
+            Some(gateway_key) => gateway_key,
+            None => {
+                token_siphon::ensure_gateway_key(
+                    &self.gateway_key_config,
+                    &self.http,
+                    &self.environment,
+                    self.store.as_ref(),
+                )
+                .await
+                .map_err(ApiError::GatewayKey)?;
+                token_siphon::api_gateway_key().ok_or(ApiError::InvalidGatewayKey)?
+            }
+        };
+
+        let mut builder = self
+            .http
+            .request(
+                request.method.clone(),
+                &self.environment.api_url(&request.path),
+            )
+            .bearer_auth(&self.access_token)
+            .header("X-API-KEY", gateway_key)
+            .header("X-GUID", &self.guid)
+            .header("X-CHANNEL", "ONEAPP")
+            .header("X-BRAND", &self.environment.brand);
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(body) = &request.body {
+            builder = builder.json(body);
+        }
+        Ok(builder)
+    }
+
+    /// Builds the given request exactly as ``ApiClient::send`` would, but never sends it.
+    pub async fn prepare(&self, request: &ApiRequest) -> Result<PreparedRequest, ApiError> {
+        let request = self
+            .build(request)
+            .await?
+            .build()
+            .map_err(ApiError::Reqwest)?;
+        Ok(PreparedRequest::new(&request))
+    }
+
+    /// Performs a request against the OneApp API, parsing its JSON response.
+    ///
+    /// If the API gateway rejects our key, we assume it has been rotated.
+    /// We'll obtain it once more from our configured sources and retry once,
+    /// unless the key was explicitly provided.
+    #[tracing::instrument(
+        name = "api_request",
+        skip_all,
+        fields(method = %request.method, path = %request.path, status)
+    )]
+    pub async fn send<T: DeserializeOwned>(&self, request: ApiRequest) -> Result<T, ApiError> {
+        let mut refreshed_key = false;
+        loop {
+            let builder = self.build(&request).await?;
+            let result = match request.retry {
+                Some(policy) => self.http.send_with(builder, policy).await,
+                None => self.http.send(builder).await,
+            }
+            .map_err(ApiError::Reqwest)?;
+
+            let status = result.status();
+            tracing::Span::current().record("status", status.as_u16());
+            let response_text = result.text().await.map_err(ApiError::Reqwest)?;
+            if status.is_success() {
+                return serde_json::from_str(&response_text).map_err(ApiError::Parse);
+            }
+
+            if !is_invalid_gateway_key(status, &response_text) {
+                return Err(ApiError::Status(status, response_text));
+            }
+
+            // Our key appears to have been rotated. We'll only try again once,
+            // and never if we were explicitly told which key to use.
+            if refreshed_key || self.gateway_key_config.key.is_some() {
+                return Err(ApiError::InvalidGatewayKey);
+            }
+            token_siphon::refresh_gateway_key(
+                &self.gateway_key_config,
+                &self.http,
+                &self.environment,
+                self.store.as_ref(),
+            )
+            .await
+            .map_err(ApiError::GatewayKey)?;
+            refreshed_key = true;
+        }
     }
 }
@@ -1,5 +1,16 @@
 mod client;
+mod shared;
 mod token_siphon;
+mod vehicle;
 
-pub use client::{ApiClient, ApiError};
-pub use token_siphon::{api_gateway_key, ensure_gateway_key};
+pub use client::{ApiClient, ApiError, ApiRequest, PreparedRequest};
+pub use shared::SharedClient;
+pub use token_siphon::{
+    api_gateway_key, ensure_gateway_key, invalidate_gateway_key, refresh_gateway_key,
+    GatewayKeyConfig, GatewayKeyError, GatewayKeySource,
+};
+pub use vehicle::{
+    remote_command_request, CommandState, Location, Measurement, Payload, RemoteCapabilities,
+    RemoteCommand, RemoteCommandResult, RemoteCommandStatus, StatusCategory, StatusSection,
+    StatusValue, Telemetry, Units, Vehicle, VehicleStatus,
+};
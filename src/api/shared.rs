@@ -0,0 +1,48 @@
+use std::time::Duration;
+use tokio::sync::{RwLock, RwLockReadGuard};
+
+use super::ApiClient;
+use crate::forgerock::ForgeRockError;
+
+/// How close to expiry our access token may get before we refresh it.
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// An ``ApiClient`` shared between long-running tasks, such as within the daemon.
+///
+/// Its access token is refreshed as it nears expiry, so it never needs logging in again
+/// so long as its refresh token remains valid.
+#[derive(Debug)]
+pub struct SharedClient {
+    client: RwLock<ApiClient>,
+}
+
+impl SharedClient {
+    pub fn new(client: ApiClient) -> Self {
+        Self {
+            client: RwLock::new(client),
+        }
+    }
+
+    /// Our client, refreshing its access token should it be about to expire.
+    pub async fn get(&self) -> Result<RwLockReadGuard<'_, ApiClient>, ForgeRockError> {
+        let fresh = |client: &ApiClient| {
+            client
+                .expires_in()
+                .is_some_and(|remaining| remaining > REFRESH_MARGIN)
+        };
+        {
+            let client = self.client.read().await;
+            if fresh(&client) {
+                return Ok(client);
+            }
+        }
+
+        let mut client = self.client.write().await;
+        // Another task may have refreshed it while we waited.
+        if !fresh(&client) {
+            *client = client.refreshed().await?;
+            tracing::info!("refreshed access token");
+        }
+        Ok(client.downgrade())
+    }
+}
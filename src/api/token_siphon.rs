@@ -1,34 +1,253 @@
+use blake2::{digest::consts::U32, Blake2b, Digest};
 use flate2::read::GzDecoder;
-use keyring::Entry;
-use std::{io::{Cursor, Read}, path::Path, str, sync::OnceLock};
+use sha2::Sha256;
+use std::{
+    fmt, fs, io,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    str,
+    sync::{PoisonError, RwLock},
+};
 use tar::Archive;
 
-const PYPI_PACKAGE_URL: &str = "https://files.pythonhosted.org/packages/0c/57/45e0db16e4f8d2f1fe864205ee90adcc8a9b8451eec045f69f9a4b42acf3/toyota-na-2.1.1.tar.gz";
+use crate::{
+    environment::Environment,
+    http::HttpClient,
+    redact::{self, Secret},
+    storage::{CredentialStore, StorageError, StorageKey},
+};
 
-static API_GATEWAY_KEY: OnceLock<String> = OnceLock::new();
+static API_GATEWAY_KEY: RwLock<Option<String>> = RwLock::new(None);
 
-/// If necessary, downloads and obtains the API gateway key necessary.
-/// There's very little error handling because this is a hack to itself.
-pub async fn ensure_gateway_key() {
-    let key_entry = Entry::new("toyotactl", "API Gateway Key")
-        .expect("should be able to retrieve OAuth2 credentials");
-    if let Ok(gateway_key) = key_entry.get_password() {
-        API_GATEWAY_KEY
-            .set(gateway_key)
-            .expect("should be able to set API gateway key");
-        return;
+/// Possible errors while obtaining the API gateway key.
+#[derive(Debug)]
+pub enum GatewayKeyError {
+    Storage(StorageError),
+    Io(io::Error),
+    Reqwest(reqwest::Error),
+    /// The archive did not contain `toyota_na/client.py`.
+    MissingClientPy,
+    /// `client.py` did not contain an `API_KEY`.
+    MissingKey,
+    /// The extracted `API_KEY` contained unexpected characters.
+    InvalidKey,
+    /// The sdist did not match a trusted digest. Its SHA-256 digest is provided.
+    UntrustedDigest(String),
+    /// Every configured source failed, alongside their reasons.
+    Exhausted(Vec<(GatewayKeySource, GatewayKeyError)>),
+    /// The key was explicitly provided, so there's nothing to refresh it from.
+    ExplicitKey,
+}
+
+impl fmt::Display for GatewayKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GatewayKeyError::Storage(err) => write!(f, "{err}"),
+            GatewayKeyError::Io(err) => write!(f, "unable to read package: {err}"),
+            GatewayKeyError::Reqwest(err) => write!(f, "unable to download package: {err}"),
+            GatewayKeyError::MissingClientPy => {
+                write!(f, "unable to find toyota_na/client.py within archive")
+            }
+            GatewayKeyError::MissingKey => write!(f, "unable to find API_KEY within client.py"),
+            GatewayKeyError::InvalidKey => write!(f, "API_KEY within client.py is malformed"),
+            GatewayKeyError::UntrustedDigest(sha256) => {
+                write!(f, "package has untrusted SHA-256 digest {sha256}")
+            }
+            GatewayKeyError::Exhausted(failures) => {
+                write!(f, "unable to obtain API gateway key from any source")?;
+                for (source, err) in failures {
+                    write!(f, "\n  {source}: {err}")?;
+                }
+                Ok(())
+            }
+            GatewayKeyError::ExplicitKey => {
+                write!(f, "an explicitly provided key cannot be refreshed")
+            }
+        }
     }
+}
 
-    // We'll need to download the package off of PyPI.
-    let package_bytes = reqwest::get(PYPI_PACKAGE_URL)
-        .await
-        .expect("should be able to download package off PyPI");
-    let tar_gz_archive = package_bytes
-        .bytes()
+impl std::error::Error for GatewayKeyError {}
+
+/// A location the API gateway key can be obtained from.
+#[derive(Debug, Clone)]
+pub enum GatewayKeySource {
+    /// A local sdist tarball of the `toyota-na` package.
+    Sdist(PathBuf),
+    /// The `toyota-na` package, downloaded from PyPI (or the environment's package source).
+    PyPi,
+}
+
+impl fmt::Display for GatewayKeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GatewayKeySource::Sdist(path) => write!(f, "sdist at {}", path.display()),
+            GatewayKeySource::PyPi => write!(f, "PyPI download"),
+        }
+    }
+}
+
+/// How the API gateway key should be obtained.
+#[derive(Debug, Clone, Default)]
+pub struct GatewayKeyConfig {
+    /// A key to use as-is, bypassing all other sources.
+    pub key: Option<String>,
+    /// A local sdist tarball to extract the key from.
+    pub sdist_path: Option<PathBuf>,
+    /// The expected SHA-256 digest of the local sdist, if not one we already trust.
+    pub sdist_sha256: Option<String>,
+}
+
+/// If necessary, obtains the API gateway key.
+///
+/// We prefer an explicitly provided key, and then one previously persisted
+/// within the given store. Otherwise, we'll extract it from a local sdist
+/// if one was provided, and lastly resort to downloading the package off of PyPI.
+pub async fn ensure_gateway_key(
+    config: &GatewayKeyConfig,
+    http: &HttpClient,
+    environment: &Environment,
+    store: &dyn CredentialStore,
+) -> Result<(), GatewayKeyError> {
+    // An explicitly provided key takes precedence over all else.
+    // We don't persist it, as the user may not want it to stick around.
+    if let Some(gateway_key) = &config.key {
+        set_gateway_key(gateway_key.clone());
+        return Ok(());
+    }
+
+    if let Some(gateway_key) = store
+        .load(StorageKey::GatewayKey)
+        .map_err(GatewayKeyError::Storage)?
+    {
+        set_gateway_key(gateway_key);
+        return Ok(());
+    }
+
+    let api_key = obtain_gateway_key(config, http, environment).await?;
+
+    // We can finally update our credential store, and persist it globally.
+    store
+        .store(StorageKey::GatewayKey, &api_key)
+        .map_err(GatewayKeyError::Storage)?;
+    set_gateway_key(api_key);
+    Ok(())
+}
+
+/// Obtains the API gateway key from our sdist or PyPI, without persisting it.
+async fn obtain_gateway_key(
+    config: &GatewayKeyConfig,
+    http: &HttpClient,
+    environment: &Environment,
+) -> Result<String, GatewayKeyError> {
+    obtain_pinned_gateway_key(config, http, environment, PINNED_SDISTS).await
+}
+
+/// Obtains the API gateway key, trusting the given pinned releases.
+///
+/// We'll attempt every source in order, keeping track of why each failed.
+/// A user-provided digest only vouches for their own sdist: whatever we
+/// download must always match a pinned release.
+async fn obtain_pinned_gateway_key(
+    config: &GatewayKeyConfig,
+    http: &HttpClient,
+    environment: &Environment,
+    pinned: &[PinnedSdist],
+) -> Result<String, GatewayKeyError> {
+    let mut sources = Vec::new();
+    if let Some(sdist_path) = &config.sdist_path {
+        sources.push(GatewayKeySource::Sdist(sdist_path.clone()));
+    }
+    sources.push(GatewayKeySource::PyPi);
+
+    let mut failures = Vec::new();
+    for source in sources {
+        let result = match &source {
+            GatewayKeySource::Sdist(path) => read_sdist(path).and_then(|archive| {
+                verify_sdist(&archive, config.sdist_sha256.as_deref(), pinned)?;
+                Ok(archive)
+            }),
+            GatewayKeySource::PyPi => download_sdist(http, environment).await.and_then(|archive| {
+                verify_sdist(&archive, None, pinned)?;
+                Ok(archive)
+            }),
+        }
+        .and_then(|archive| extract_key(&archive));
+
+        match result {
+            Ok(api_key) => return Ok(api_key),
+            Err(err) => failures.push((source, err)),
+        }
+    }
+
+    Err(GatewayKeyError::Exhausted(failures))
+}
+
+/// Reads a local sdist tarball.
+fn read_sdist(path: &Path) -> Result<Vec<u8>, GatewayKeyError> {
+    fs::read(path).map_err(GatewayKeyError::Io)
+}
+
+/// Downloads the package off of PyPI.
+async fn download_sdist(
+    http: &HttpClient,
+    environment: &Environment,
+) -> Result<Vec<u8>, GatewayKeyError> {
+    let request = http.get(environment.package_source.as_str());
+    let response = http
+        .send(request)
         .await
-        .expect("should be able to download package contents");
+        .and_then(|response| response.error_for_status())
+        .map_err(GatewayKeyError::Reqwest)?;
+    let package_bytes = response.bytes().await.map_err(GatewayKeyError::Reqwest)?;
+    Ok(package_bytes.to_vec())
+}
+
+/// A `toyota-na` sdist we trust without any further configuration.
+struct PinnedSdist {
+    version: &'static str,
+    /// PyPI derives its file paths from the BLAKE2b-256 digest of each file,
+    /// so this is the same digest visible within our production package source URL.
+    blake2b: &'static str,
+}
 
-    // We could stream this request to a decoder,
+const PINNED_SDISTS: &[PinnedSdist] = &[PinnedSdist {
+    version: "2.1.1",
+    blake2b: "0c5745e0db16e4f8d2f1fe864205ee90adcc8a9b8451eec045f69f9a4b42acf3",
+}];
+
+/// Ensures the given sdist is one we trust.
+///
+/// If the user provided a SHA-256 digest for their own sdist, it must match exactly.
+/// Otherwise, the sdist must match a pinned release. Either way, we report the
+/// sdist's SHA-256 digest upon failure, as that's what users can pass along.
+fn verify_sdist(
+    tar_gz_archive: &[u8],
+    expected_sha256: Option<&str>,
+    pinned: &[PinnedSdist],
+) -> Result<(), GatewayKeyError> {
+    let sha256 = hex::encode(Sha256::digest(tar_gz_archive));
+    if let Some(expected_sha256) = expected_sha256 {
+        return if sha256.eq_ignore_ascii_case(expected_sha256.trim()) {
+            Ok(())
+        } else {
+            Err(GatewayKeyError::UntrustedDigest(sha256))
+        };
+    }
+
+    let blake2b = hex::encode(Blake2b::<U32>::digest(tar_gz_archive));
+    match pinned.iter().find(|pinned| pinned.blake2b == blake2b) {
+        Some(pinned) => {
+            tracing::debug!(version = pinned.version, "sdist matches pinned release");
+            Ok(())
+        }
+        None => Err(GatewayKeyError::UntrustedDigest(sha256)),
+    }
+}
+
+/// Extracts the API key from the contents of a `toyota-na` sdist tarball.
+fn extract_key(tar_gz_archive: &[u8]) -> Result<String, GatewayKeyError> {
+    // We could stream this to a decoder,
     // but this is small enough that it's okay.
     //
     // (Hopefully.)
@@ -37,9 +256,7 @@ pub async fn ensure_gateway_key() {
 
     // We now have a raw tar archive. Let's read the contents of `toyota_na/client.py`.
     let mut tar_archive = Archive::new(decoder);
-    let tar_entries = tar_archive
-        .entries()
-        .expect("should be able to read tar archive entires");
+    let tar_entries = tar_archive.entries().map_err(GatewayKeyError::Io)?;
 
     // Per the `tar` docs, we must process all entries in-order or contents may be corrupted.
     // This is true! Here, we'll use a for loop and figure it out from there.
@@ -55,43 +272,384 @@ pub async fn ensure_gateway_key() {
             continue;
         };
 
-        // We're looking for this specific location.
-        if current_path != Path::new("toyota-na-2.1.1/toyota_na/client.py") {
+        // We're looking for `toyota_na/client.py`, regardless of
+        // whatever versioned directory the package has it within.
+        if !current_path.ends_with("toyota_na/client.py") {
             continue;
         }
 
         // We should now have our file.
-        current_entry.read_to_string(&mut client_py_contents).expect("should be able to read client.py contents");
+        current_entry
+            .read_to_string(&mut client_py_contents)
+            .map_err(GatewayKeyError::Io)?;
         break;
     }
 
     // If we were unable to find that file, we'll just give up.
-    if client_py_contents == "" {
-        panic!("unable to find client.py within archive")
+    if client_py_contents.is_empty() {
+        return Err(GatewayKeyError::MissingClientPy);
     }
 
     // We now have our file in string form!
-    // We could use regex, but let's just hack this together.
-    // We tack on 11 characters to skip over the literal string `API_KEY = "`.
-    let key_start_index = client_py_contents.find("API_KEY = \"").expect("should be able to find API_KEY in client.py") + 11;
-    // Our API key is 40 characters in length.
-    let key_end_index = key_start_index + 40;
+    let api_key = client_py_contents
+        .lines()
+        .find_map(parse_api_key_line)
+        .ok_or(GatewayKeyError::MissingKey)?;
 
-    // Finally, obtain the key.
-    let api_key = client_py_contents[key_start_index..key_end_index].to_string();
+    // Gateway keys are alphanumeric. Anything else means
+    // we've extracted something we shouldn't trust.
+    if !api_key.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(GatewayKeyError::InvalidKey);
+    }
 
-    // We can finally update our credential store, and persist it globally.
-    key_entry.set_password(&api_key).expect("should be able to update credential store to have API key");
+    Ok(api_key.to_string())
+}
 
-    API_GATEWAY_KEY
-        .set(api_key)
-        .expect("should be able to set API gateway key");
+/// Parses a line of the form `API_KEY = "..."`, with either quote style.
+fn parse_api_key_line(line: &str) -> Option<&str> {
+    let value = line.trim().strip_prefix("API_KEY")?;
+    let value = value.trim_start().strip_prefix('=')?.trim();
+
+    // Our value must open and close with the same quote.
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let value = &value[1..];
+    let end_index = value.find(quote)?;
+
+    let api_key = &value[..end_index];
+    (!api_key.is_empty()).then_some(api_key)
 }
 
-/// Obtains the API gateway key loaded at the start of this program.
-pub fn api_gateway_key() -> String {
+/// Persists the given key globally for the remainder of this program.
+fn set_gateway_key(gateway_key: String) {
+    redact::register_secret(Secret::GatewayKey, &gateway_key);
+    *API_GATEWAY_KEY
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = Some(gateway_key);
+}
+
+/// Forgets our current API gateway key, both globally and within the given store.
+///
+/// This is necessary should Toyota ever rotate their key, as we'd otherwise
+/// continue using our stale key forever.
+pub fn invalidate_gateway_key(store: &dyn CredentialStore) -> Result<(), GatewayKeyError> {
+    *API_GATEWAY_KEY
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = None;
+
+    store
+        .delete(StorageKey::GatewayKey)
+        .map_err(GatewayKeyError::Storage)
+}
+
+/// Obtains the API gateway key once more, replacing our current one.
+///
+/// Our stored key is only overwritten once a verified replacement is in hand,
+/// so a failed refresh (such as while offline) leaves it be.
+/// An explicitly provided key can't be refreshed, as we were told to use it as-is.
+pub async fn refresh_gateway_key(
+    config: &GatewayKeyConfig,
+    http: &HttpClient,
+    environment: &Environment,
+    store: &dyn CredentialStore,
+) -> Result<(), GatewayKeyError> {
+    if config.key.is_some() {
+        return Err(GatewayKeyError::ExplicitKey);
+    }
+
+    let api_key = obtain_gateway_key(config, http, environment).await?;
+    store
+        .store(StorageKey::GatewayKey, &api_key)
+        .map_err(GatewayKeyError::Storage)?;
+    set_gateway_key(api_key);
+    Ok(())
+}
+
+/// Obtains the API gateway key, if one has been loaded.
+pub fn api_gateway_key() -> Option<String> {
     API_GATEWAY_KEY
-        .get()
-        .expect("should have gateway API key")
-        .to_string()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use tar::{Builder, Header};
+    use url::Url;
+
+    use crate::{
+        http::{HttpConfig, RetryPolicy},
+        storage::MemoryStore,
+    };
+
+    /// Builds a gzipped tarball containing the given files.
+    fn sdist(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, contents) in files {
+            let mut header = Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn client_py(api_key_line: &str) -> String {
+        format!("import requests\n\nclass ToyotaOneClient:\n    {api_key_line}\n")
+    }
+
+    #[test]
+    fn version_prefixed_path() {
+        let archive = sdist(&[
+            ("toyota-na-2.1.1/PKG-INFO", "Name: toyota-na\n"),
+            (
+                "toyota-na-2.1.1/toyota_na/client.py",
+                &client_py(r#"API_KEY = "abcDEF123""#),
+            ),
+        ]);
+        assert_eq!(extract_key(&archive).unwrap(), "abcDEF123");
+    }
+
+    #[test]
+    fn quote_styles() {
+        for line in [
+            r#"API_KEY = "abc123""#,
+            "API_KEY = 'abc123'",
+            "API_KEY='abc123'  # trailing comment",
+        ] {
+            let archive = sdist(&[("toyota_na/client.py", &client_py(line))]);
+            assert_eq!(extract_key(&archive).unwrap(), "abc123", "{line}");
+        }
+
+        // Mismatched quotes are not a value.
+        assert_eq!(parse_api_key_line(r#"API_KEY = "abc123'"#), None);
+        assert_eq!(parse_api_key_line(r#"API_KEY = """#), None);
+        assert_eq!(parse_api_key_line(r#"OTHER_API_KEY = "abc123""#), None);
+    }
+
+    #[test]
+    fn key_lengths() {
+        for length in [1, 20, 40, 128] {
+            let key = "a1".repeat(length)[..length].to_string();
+            let archive = sdist(&[(
+                "toyota-na-2.1.1/toyota_na/client.py",
+                &client_py(&format!(r#"API_KEY = "{key}""#)),
+            )]);
+            assert_eq!(extract_key(&archive).unwrap(), key);
+        }
+    }
+
+    #[test]
+    fn invalid_charset() {
+        for key in ["abc-123", "abc 123", "abc\\123", "ключ"] {
+            let archive = sdist(&[(
+                "toyota_na/client.py",
+                &client_py(&format!(r#"API_KEY = "{key}""#)),
+            )]);
+            assert!(
+                matches!(extract_key(&archive), Err(GatewayKeyError::InvalidKey)),
+                "{key}"
+            );
+        }
+    }
+
+    #[test]
+    fn missing_client_py() {
+        let archive = sdist(&[
+            ("toyota-na-2.1.1/PKG-INFO", "Name: toyota-na\n"),
+            // Close, but not the file we're after.
+            (
+                "toyota-na-2.1.1/toyota_na/client_py.txt",
+                "API_KEY = 'abc'\n",
+            ),
+        ]);
+        assert!(matches!(
+            extract_key(&archive),
+            Err(GatewayKeyError::MissingClientPy)
+        ));
+    }
+
+    #[test]
+    fn missing_key() {
+        let archive = sdist(&[("toyota_na/client.py", &client_py("pass"))]);
+        assert!(matches!(
+            extract_key(&archive),
+            Err(GatewayKeyError::MissingKey)
+        ));
+    }
+
+    #[test]
+    fn digest_mismatch() {
+        let archive = sdist(&[("toyota_na/client.py", &client_py("API_KEY = 'abc'"))]);
+        let sha256 = hex::encode(Sha256::digest(&archive));
+
+        // Our own tarball is pinned nowhere.
+        match verify_sdist(&archive, None, PINNED_SDISTS) {
+            Err(GatewayKeyError::UntrustedDigest(digest)) => assert_eq!(digest, sha256),
+            other => panic!("unexpected result: {other:?}"),
+        }
+
+        // A user-provided digest must match exactly, regardless of case or whitespace.
+        verify_sdist(
+            &archive,
+            Some(&format!(" {} ", sha256.to_uppercase())),
+            PINNED_SDISTS,
+        )
+        .unwrap();
+        let wrong = "0".repeat(64);
+        assert!(matches!(
+            verify_sdist(&archive, Some(&wrong), PINNED_SDISTS),
+            Err(GatewayKeyError::UntrustedDigest(_))
+        ));
+    }
+
+    /// An environment whose package source is unreachable,
+    /// and a client that won't retry reaching it.
+    fn offline() -> (HttpClient, Environment) {
+        let http = HttpClient::new(&HttpConfig {
+            retry: RetryPolicy {
+                max_retries: 0,
+                ..RetryPolicy::default()
+            },
+            ..HttpConfig::default()
+        })
+        .unwrap();
+        let mut environment = Environment::production();
+        environment.package_source = Url::parse("http://127.0.0.1:9/toyota-na.tar.gz").unwrap();
+        (http, environment)
+    }
+
+    fn store_with_key(key: &str) -> MemoryStore {
+        let store = MemoryStore::new();
+        store.store(StorageKey::GatewayKey, key).unwrap();
+        store
+    }
+
+    #[tokio::test]
+    async fn refresh_replaces_stored_key() {
+        let archive = sdist(&[("toyota_na/client.py", &client_py("API_KEY = 'rotated'"))]);
+        let path = std::env::temp_dir().join(format!(
+            "toyotactl-sdist-{}-refresh.tar.gz",
+            std::process::id()
+        ));
+        fs::write(&path, &archive).unwrap();
+        let config = GatewayKeyConfig {
+            sdist_path: Some(path.clone()),
+            sdist_sha256: Some(hex::encode(Sha256::digest(&archive))),
+            ..GatewayKeyConfig::default()
+        };
+
+        let (http, environment) = offline();
+        let store = store_with_key("stale");
+        let result = refresh_gateway_key(&config, &http, &environment, &store).await;
+        let _ = fs::remove_file(&path);
+        result.unwrap();
+        assert_eq!(
+            store.load(StorageKey::GatewayKey).unwrap().as_deref(),
+            Some("rotated")
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_refresh_keeps_stored_key() {
+        let config = GatewayKeyConfig {
+            sdist_path: Some(PathBuf::from("/nonexistent/toyota-na.tar.gz")),
+            ..GatewayKeyConfig::default()
+        };
+
+        let (http, environment) = offline();
+        let store = store_with_key("stale");
+        let result = refresh_gateway_key(&config, &http, &environment, &store).await;
+        match result {
+            Err(GatewayKeyError::Exhausted(failures)) => assert_eq!(failures.len(), 2),
+            other => panic!("unexpected result: {other:?}"),
+        }
+        assert_eq!(
+            store.load(StorageKey::GatewayKey).unwrap().as_deref(),
+            Some("stale")
+        );
+    }
+
+    #[tokio::test]
+    async fn explicit_key_is_never_refreshed() {
+        let config = GatewayKeyConfig {
+            key: Some("explicit".to_string()),
+            ..GatewayKeyConfig::default()
+        };
+
+        let (http, environment) = offline();
+        let store = store_with_key("stale");
+        let result = refresh_gateway_key(&config, &http, &environment, &store).await;
+        assert!(matches!(result, Err(GatewayKeyError::ExplicitKey)));
+        assert_eq!(
+            store.load(StorageKey::GatewayKey).unwrap().as_deref(),
+            Some("stale")
+        );
+    }
+
+    /// Serves the given package once over plain HTTP, returning its URL.
+    async fn serve_package(archive: Vec<u8>) -> Url {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).await;
+            let head = format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                archive.len()
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            stream.write_all(&archive).await.unwrap();
+        });
+        Url::parse(&format!("http://{address}/toyota-na.tar.gz")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn download_ignores_user_digest() {
+        let downloaded = sdist(&[("toyota_na/client.py", &client_py("API_KEY = 'pinned'"))]);
+        let pinned = [PinnedSdist {
+            version: "test",
+            blake2b: hex::encode(Blake2b::<U32>::digest(&downloaded)).leak(),
+        }];
+
+        // The user vouched for a local sdist that is no longer there,
+        // so we fall back to PyPI, whose package must match a pin instead.
+        let config = GatewayKeyConfig {
+            sdist_path: Some(PathBuf::from("/nonexistent/toyota-na.tar.gz")),
+            sdist_sha256: Some("0".repeat(64)),
+            ..GatewayKeyConfig::default()
+        };
+        let (http, mut environment) = offline();
+        environment.package_source = serve_package(downloaded.clone()).await;
+        let api_key = obtain_pinned_gateway_key(&config, &http, &environment, &pinned)
+            .await
+            .unwrap();
+        assert_eq!(api_key, "pinned");
+
+        // Conversely, the user's digest never vouches for a download.
+        let config = GatewayKeyConfig {
+            sdist_sha256: Some(hex::encode(Sha256::digest(&downloaded))),
+            ..config
+        };
+        environment.package_source = serve_package(downloaded).await;
+        let result = obtain_pinned_gateway_key(&config, &http, &environment, &[]).await;
+        match result {
+            Err(GatewayKeyError::Exhausted(failures)) => assert!(matches!(
+                failures.as_slice(),
+                [
+                    (GatewayKeySource::Sdist(_), GatewayKeyError::Io(_)),
+                    (GatewayKeySource::PyPi, GatewayKeyError::UntrustedDigest(_)),
+                ]
+            )),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
 }
@@ -0,0 +1,364 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{fmt, str::FromStr};
+
+use super::{client::ApiRequest, ApiClient, ApiError, PreparedRequest};
+use crate::http::RetryPolicy;
+
+/// The common envelope around most API responses.
+#[derive(Deserialize, Debug)]
+pub struct Payload<T> {
+    pub payload: T,
+}
+
+/// A vehicle associated with the user's account.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Vehicle {
+    pub vin: String,
+    /// The user-provided name for this vehicle, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nick_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_year: Option<String>,
+    /// Which remote commands this vehicle supports, if reported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_service_capabilities: Option<RemoteCapabilities>,
+}
+
+/// The remote commands a vehicle supports, as reported within the vehicle list.
+///
+/// Each capability is only known if reported. Vehicles report many more,
+/// such as for their trunk or windows, but we've no commands for those yet.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteCapabilities {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dlock_unlock_capable: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub estart_stop_capable: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hazard_capable: Option<bool>,
+}
+
+impl RemoteCapabilities {
+    /// Whether the given command is supported, if known.
+    pub fn supports(&self, command: RemoteCommand) -> Option<bool> {
+        match command {
+            RemoteCommand::DoorLock | RemoteCommand::DoorUnlock => self.dlock_unlock_capable,
+            RemoteCommand::EngineStart | RemoteCommand::EngineStop => self.estart_stop_capable,
+            RemoteCommand::HazardOn | RemoteCommand::HazardOff => self.hazard_capable,
+        }
+    }
+}
+
+/// The remote status of a vehicle, such as its doors and windows.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VehicleStatus {
+    /// When this status was last reported by the vehicle.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub occurrence_date: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    /// Status is grouped by category (e.g. "Driver Side"), and then by section (e.g. "Door").
+    #[serde(default)]
+    pub vehicle_status: Vec<StatusCategory>,
+}
+
+impl VehicleStatus {
+    /// Whether every door is locked, if any report it.
+    pub fn locked(&self) -> Option<bool> {
+        let mut doors = self
+            .vehicle_status
+            .iter()
+            .flat_map(|category| &category.sections)
+            .filter(|section| section.section.eq_ignore_ascii_case("door"))
+            .flat_map(|section| &section.values)
+            .map(|value| value.value.to_lowercase())
+            .peekable();
+        doors.peek()?;
+        let mut locked = true;
+        for door in doors {
+            match door.as_str() {
+                "locked" => {}
+                "unlocked" => locked = false,
+                _ => return None,
+            }
+        }
+        Some(locked)
+    }
+}
+
+/// A category of status values, such as "Driver Side".
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StatusCategory {
+    pub category: String,
+    #[serde(default)]
+    pub sections: Vec<StatusSection>,
+}
+
+/// A section within a status category, such as "Door".
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StatusSection {
+    pub section: String,
+    #[serde(default)]
+    pub values: Vec<StatusValue>,
+}
+
+/// An individual status value, such as "Locked".
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StatusValue {
+    pub value: String,
+    /// Observed to be `0` when normal, and non-zero when requiring attention.
+    #[serde(default)]
+    pub status: i32,
+}
+
+/// A measurement alongside its unit, such as `12345 mi`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Measurement {
+    pub value: f64,
+    pub unit: String,
+}
+
+/// Units of measurement we can present distances in.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Units {
+    /// Leave measurements as the vehicle reported them.
+    #[default]
+    Reported,
+    Imperial,
+    Metric,
+}
+
+/// Kilometres within a mile.
+const KILOMETRES_PER_MILE: f64 = 1.609344;
+
+impl Measurement {
+    /// This measurement in the given units, if we know how to convert it.
+    pub fn convert(&self, units: Units) -> Measurement {
+        let (value, unit) = match (units, self.unit.as_str()) {
+            (Units::Metric, "mi") => (self.value * KILOMETRES_PER_MILE, "km"),
+            (Units::Imperial, "km") => (self.value / KILOMETRES_PER_MILE, "mi"),
+            _ => return self.clone(),
+        };
+        Measurement {
+            value: value.round(),
+            unit: unit.to_string(),
+        }
+    }
+}
+
+/// A location reported by the vehicle.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Telemetry reported by a vehicle, such as its fuel level and odometer.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Telemetry {
+    /// The fuel level, as a percentage.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fuel_level: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub odometer: Option<Measurement>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distance_to_empty: Option<Measurement>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vehicle_location: Option<Location>,
+    /// When this telemetry was last reported by the vehicle.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_timestamp: Option<String>,
+}
+
+impl Telemetry {
+    /// This telemetry with its distances in the given units.
+    pub fn convert(mut self, units: Units) -> Telemetry {
+        self.odometer = self.odometer.map(|odometer| odometer.convert(units));
+        self.distance_to_empty = self
+            .distance_to_empty
+            .map(|distance| distance.convert(units));
+        self
+    }
+}
+
+/// Commands that can be remotely issued to a vehicle.
+#[derive(
+    Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum RemoteCommand {
+    DoorLock,
+    DoorUnlock,
+    EngineStart,
+    EngineStop,
+    HazardOn,
+    HazardOff,
+}
+
+impl RemoteCommand {
+    pub const ALL: [RemoteCommand; 6] = [
+        RemoteCommand::DoorLock,
+        RemoteCommand::DoorUnlock,
+        RemoteCommand::EngineStart,
+        RemoteCommand::EngineStop,
+        RemoteCommand::HazardOn,
+        RemoteCommand::HazardOff,
+    ];
+
+    /// The name of this command as understood by the API.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RemoteCommand::DoorLock => "door-lock",
+            RemoteCommand::DoorUnlock => "door-unlock",
+            RemoteCommand::EngineStart => "engine-start",
+            RemoteCommand::EngineStop => "engine-stop",
+            RemoteCommand::HazardOn => "hazard-on",
+            RemoteCommand::HazardOff => "hazard-off",
+        }
+    }
+}
+
+impl fmt::Display for RemoteCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for RemoteCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RemoteCommand::ALL
+            .into_iter()
+            .find(|command| command.as_str() == s)
+            .ok_or_else(|| format!("unknown remote command: {s}"))
+    }
+}
+
+/// The API's acknowledgement of a remote command.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteCommandResult {
+    /// An identifier for this request, usable to track its progress.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_request_no: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_code: Option<String>,
+}
+
+/// Where a remote command stands, once the API has accepted it.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommandState {
+    /// The vehicle has yet to respond.
+    Pending,
+    /// The vehicle carried out the command.
+    Completed,
+    /// The vehicle failed to carry out the command, or it was superseded.
+    Failed,
+    /// Anything else the API may report, which we treat as pending.
+    #[serde(other)]
+    Unknown,
+}
+
+impl CommandState {
+    /// Whether this command has finished, one way or another.
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, CommandState::Completed | CommandState::Failed)
+    }
+}
+
+/// The progress of a previously accepted remote command.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteCommandStatus {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_request_no: Option<String>,
+    pub status: CommandState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_code: Option<String>,
+}
+
+impl ApiClient {
+    /// Lists all vehicles associated with the user's account.
+    pub async fn vehicles(&self) -> Result<Vec<Vehicle>, ApiError> {
+        let response: Payload<Vec<Vehicle>> = self.send(ApiRequest::get("v3/vehicle/guid")).await?;
+        Ok(response.payload)
+    }
+
+    /// Obtains the remote status of the given vehicle.
+    pub async fn vehicle_status(&self, vin: &str) -> Result<VehicleStatus, ApiError> {
+        let request = ApiRequest::get("v1/global/remote/status").header("VIN", vin);
+        let response: Payload<VehicleStatus> = self.send(request).await?;
+        Ok(response.payload)
+    }
+
+    /// Obtains telemetry for the given vehicle.
+    pub async fn telemetry(&self, vin: &str) -> Result<Telemetry, ApiError> {
+        let request = ApiRequest::get("v2/telemetry")
+            .header("VIN", vin)
+            .header("GENERATION", "17CYPLUS")
+            .header("X-REGION", &self.environment().region_code);
+        let response: Payload<Telemetry> = self.send(request).await?;
+        Ok(response.payload)
+    }
+
+    /// Remotely issues the given command to the given vehicle.
+    pub async fn remote_command(
+        &self,
+        vin: &str,
+        command: RemoteCommand,
+    ) -> Result<RemoteCommandResult, ApiError> {
+        let response: Payload<RemoteCommandResult> =
+            self.send(remote_command_request(vin, command)).await?;
+        Ok(response.payload)
+    }
+
+    /// Obtains the progress of a remote command, per the request number it was accepted with.
+    ///
+    /// Our environment must know where to ask, per ``Environment::command_status_path``.
+    pub async fn remote_command_status(
+        &self,
+        vin: &str,
+        app_request_no: &str,
+    ) -> Result<Option<RemoteCommandStatus>, ApiError> {
+        let Some(path) = &self.environment().command_status_path else {
+            return Ok(None);
+        };
+        let request = ApiRequest::get(path)
+            .header("VIN", vin)
+            .header("APP-REQUEST-NO", app_request_no);
+        let response: Payload<RemoteCommandStatus> = self.send(request).await?;
+        Ok(Some(response.payload))
+    }
+
+    /// Builds the request ``ApiClient::remote_command`` would send, without sending it.
+    pub async fn prepare_remote_command(
+        &self,
+        vin: &str,
+        command: RemoteCommand,
+    ) -> Result<PreparedRequest, ApiError> {
+        self.prepare(&remote_command_request(vin, command)).await
+    }
+}
+
+/// The request issuing the given command to the given vehicle.
+///
+/// Commands are never retried: a failure may have still reached the vehicle,
+/// and we'd rather not (for example) start its engine twice.
+pub fn remote_command_request(vin: &str, command: RemoteCommand) -> ApiRequest {
+    ApiRequest::post("v1/global/remote/command", json!({ "command": command }))
+        .header("VIN", vin)
+        .retry(RetryPolicy::NEVER)
+}
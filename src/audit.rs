@@ -0,0 +1,395 @@
+//! An append-only record of every remote command we've issued.
+//!
+//! Each line of `$XDG_DATA_HOME/toyotactl/audit.jsonl` (or the platform's equivalent)
+//! is a single JSON entry, so that the log can be shipped or queried with other tools.
+//! Each command is appended once the API responds, and again should we learn how it
+//! turned out. We never rewrite or truncate it: if it must be rotated, that's up to the user.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+use crate::{
+    api::{self, ApiClient, ApiError, CommandState, Payload, RemoteCommand, RemoteCommandResult},
+    redact,
+};
+
+/// How often we'll ask whether an accepted command has been carried out.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long we'll wait for the vehicle to carry out an accepted command.
+pub const DEFAULT_POLL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Possible errors while reading or appending to the audit log.
+#[derive(Debug)]
+pub enum AuditError {
+    Io(io::Error),
+    Serialize(serde_json::Error),
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditError::Io(err) => write!(f, "unable to access audit log: {err}"),
+            AuditError::Serialize(err) => write!(f, "unable to serialize audit entry: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for AuditError {}
+
+/// How a remote command turned out.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum Outcome {
+    /// The API accepted the command, but we were unable to learn more.
+    Accepted {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        return_code: Option<String>,
+    },
+    /// The vehicle carried out the command.
+    Completed {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        return_code: Option<String>,
+    },
+    /// The API accepted the command, but the vehicle had yet to respond when we gave up.
+    TimedOut {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        return_code: Option<String>,
+    },
+    /// The command failed, although it may have still reached the vehicle.
+    Failed { error: String },
+}
+
+impl Outcome {
+    /// A failure, with any secrets within the error redacted.
+    pub fn failed(err: &impl fmt::Display) -> Self {
+        Outcome::Failed {
+            error: redact::redact(&err.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Accepted {
+                return_code: Some(return_code),
+            } => write!(f, "accepted ({return_code})"),
+            Outcome::Accepted { return_code: None } => write!(f, "accepted"),
+            Outcome::Completed {
+                return_code: Some(return_code),
+            } => write!(f, "completed ({return_code})"),
+            Outcome::Completed { return_code: None } => write!(f, "completed"),
+            Outcome::TimedOut { .. } => write!(f, "timed out awaiting the vehicle"),
+            Outcome::Failed { error } => write!(f, "failed: {error}"),
+        }
+    }
+}
+
+/// A single remote command, and who issued it.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub profile: String,
+    /// The GUID of the account the command was issued as.
+    pub guid: String,
+    pub vin: String,
+    pub command: RemoteCommand,
+    /// The body of the request, as sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+    /// The API's identifier for this request, should it have provided one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    pub outcome: Outcome,
+    /// The local user who invoked us.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+}
+
+impl AuditEntry {
+    /// The user and host we're running as, for attributing entries.
+    pub fn invoker() -> (Option<String>, Option<String>) {
+        let user = std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .ok()
+            .filter(|user| !user.is_empty());
+        let host = gethostname::gethostname()
+            .into_string()
+            .ok()
+            .filter(|host| !host.is_empty());
+        (user, host)
+    }
+}
+
+/// An open audit log, ready to be appended to.
+#[derive(Debug)]
+pub struct AuditLog {
+    file: File,
+    poll_interval: Duration,
+    poll_timeout: Duration,
+}
+
+impl AuditLog {
+    /// Where our audit log lives by default, within the user's data directory.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(dirs::data_dir()?.join("toyotactl").join("audit.jsonl"))
+    }
+
+    /// Opens the log at the given path for appending, creating it if necessary.
+    ///
+    /// We'll open it before issuing anything, so that we never issue a command we can't record.
+    pub fn open(path: &Path) -> Result<Self, AuditError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(AuditError::Io)?;
+        }
+        let mut options = fs::OpenOptions::new();
+        options.append(true).create(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(path).map_err(AuditError::Io)?;
+        Ok(Self {
+            file,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            poll_timeout: DEFAULT_POLL_TIMEOUT,
+        })
+    }
+
+    /// Sets how often, and for how long, we'll poll accepted commands for their outcome.
+    /// A zero timeout records them as merely accepted.
+    pub fn with_polling(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.poll_interval = interval;
+        self.poll_timeout = timeout;
+        self
+    }
+
+    /// Issues the given command via the given client, recording it whatever the outcome.
+    ///
+    /// We'll record the command as soon as the API responds, timestamped from when we sent it,
+    /// so that it's never lost should we be interrupted. Once accepted, we'll wait for the
+    /// vehicle to carry it out (or fail to) and record how it turned out in a follow-up entry.
+    /// The command has already been issued by the time we record it, so failing to do so
+    /// is only logged.
+    pub async fn issue(
+        &self,
+        client: &ApiClient,
+        profile: &str,
+        vin: &str,
+        command: RemoteCommand,
+    ) -> Result<RemoteCommandResult, ApiError> {
+        // We'll record the body exactly as sent.
+        let request = api::remote_command_request(vin, command);
+        let parameters = request.body.clone();
+        let timestamp = Utc::now();
+        let result = client
+            .send::<Payload<RemoteCommandResult>>(request)
+            .await
+            .map(|response| response.payload);
+
+        let (user, host) = AuditEntry::invoker();
+        let mut entry = AuditEntry {
+            timestamp,
+            profile: profile.to_string(),
+            guid: client.guid().to_string(),
+            vin: vin.to_string(),
+            command,
+            parameters,
+            request_id: result
+                .as_ref()
+                .ok()
+                .and_then(|result| result.app_request_no.clone()),
+            outcome: match &result {
+                Ok(accepted) => Outcome::Accepted {
+                    return_code: accepted.return_code.clone(),
+                },
+                Err(err) => Outcome::failed(err),
+            },
+            user,
+            host,
+        };
+        self.record(&entry);
+
+        if let Ok(accepted) = &result {
+            let outcome = self.await_outcome(client, vin, accepted).await;
+            if let Outcome::Failed { .. } | Outcome::TimedOut { .. } = outcome {
+                tracing::warn!(%vin, "{command} was accepted, but {outcome}");
+            }
+            if !matches!(outcome, Outcome::Accepted { .. }) {
+                entry.outcome = outcome;
+                self.record(&entry);
+            }
+        }
+        result
+    }
+
+    /// Appends the given entry, logging should we be unable to.
+    fn record(&self, entry: &AuditEntry) {
+        if let Err(err) = self.append(entry) {
+            let (vin, command) = (&entry.vin, entry.command);
+            tracing::error!(%vin, "unable to record {command} within audit log: {err}");
+        }
+    }
+
+    /// Polls an accepted command until it has completed or failed, or until we time out.
+    ///
+    /// Should we be unable to ask, or the API respond with anything but a transient error,
+    /// it's merely accepted: polling again would never tell us more.
+    async fn await_outcome(
+        &self,
+        client: &ApiClient,
+        vin: &str,
+        accepted: &RemoteCommandResult,
+    ) -> Outcome {
+        let return_code = accepted.return_code.clone();
+        let Some(app_request_no) = &accepted.app_request_no else {
+            return Outcome::Accepted { return_code };
+        };
+        if self.poll_timeout.is_zero() {
+            return Outcome::Accepted { return_code };
+        }
+
+        let deadline = Instant::now() + self.poll_timeout;
+        loop {
+            match client.remote_command_status(vin, app_request_no).await {
+                Ok(Some(status)) => {
+                    let return_code = status.return_code.or_else(|| return_code.clone());
+                    match status.status {
+                        CommandState::Completed => return Outcome::Completed { return_code },
+                        CommandState::Failed => {
+                            let error = match return_code {
+                                Some(code) => format!("the vehicle did not carry it out ({code})"),
+                                None => "the vehicle did not carry it out".to_string(),
+                            };
+                            return Outcome::Failed { error };
+                        }
+                        CommandState::Pending | CommandState::Unknown => {}
+                    }
+                }
+                Ok(None) => return Outcome::Accepted { return_code },
+                Err(err) if err.is_transient() => {
+                    tracing::debug!(%vin, %app_request_no, "unable to poll command: {err}")
+                }
+                Err(err) => {
+                    tracing::warn!(%vin, %app_request_no, "unable to poll command: {err}");
+                    return Outcome::Accepted { return_code };
+                }
+            }
+
+            if Instant::now() + self.poll_interval >= deadline {
+                return Outcome::TimedOut { return_code };
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    /// Appends the given entry.
+    ///
+    /// Each entry is written at once, so concurrent invocations won't interleave lines.
+    pub fn append(&self, entry: &AuditEntry) -> Result<(), AuditError> {
+        let mut line = serde_json::to_vec(entry).map_err(AuditError::Serialize)?;
+        line.push(b'\n');
+        (&self.file).write_all(&line).map_err(AuditError::Io)
+    }
+
+    /// Reads every command within the log at the given path, oldest first.
+    ///
+    /// Follow-up entries, sharing a request ID with an earlier one, replace its outcome.
+    /// A missing log has no entries. Lines we can't parse are skipped with a warning.
+    pub fn entries(path: &Path) -> Result<Vec<AuditEntry>, AuditError> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(AuditError::Io(err)),
+        };
+        let mut entries: Vec<AuditEntry> = vec![];
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(AuditError::Io)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: AuditEntry = match serde_json::from_str(&line) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    tracing::warn!(line = index + 1, "unable to parse audit entry: {err}");
+                    continue;
+                }
+            };
+            let original = entry.request_id.as_ref().and_then(|request_id| {
+                entries.iter_mut().rev().find(|original| {
+                    original.vin == entry.vin && original.request_id.as_ref() == Some(request_id)
+                })
+            });
+            match original {
+                Some(original) => original.outcome = entry.outcome,
+                None => entries.push(entry),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(request_id: Option<&str>, outcome: Outcome) -> AuditEntry {
+        AuditEntry {
+            timestamp: Utc::now(),
+            profile: "default".to_string(),
+            guid: "guid".to_string(),
+            vin: "JTMOCK00000000001".to_string(),
+            command: RemoteCommand::DoorLock,
+            parameters: None,
+            request_id: request_id.map(str::to_string),
+            outcome,
+            user: None,
+            host: None,
+        }
+    }
+
+    #[test]
+    fn follow_ups_replace_outcomes() {
+        let path = std::env::temp_dir().join(format!(
+            "toyotactl-audit-{}-follow-ups.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let audit = AuditLog::open(&path).unwrap();
+        let accepted = || Outcome::Accepted { return_code: None };
+        let completed = || Outcome::Completed { return_code: None };
+        audit.append(&entry(Some("first"), accepted())).unwrap();
+        audit.append(&entry(None, accepted())).unwrap();
+        audit.append(&entry(Some("second"), accepted())).unwrap();
+        audit.append(&entry(Some("first"), completed())).unwrap();
+        // Entries without a request ID never follow up on one another.
+        audit
+            .append(&entry(None, Outcome::failed(&"unreachable")))
+            .unwrap();
+
+        let entries = AuditLog::entries(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let outcomes: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.request_id.as_deref(), entry.outcome.to_string()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                (Some("first"), "completed".to_string()),
+                (None, "accepted".to_string()),
+                (Some("second"), "accepted".to_string()),
+                (None, "failed: unreachable".to_string()),
+            ]
+        );
+    }
+}
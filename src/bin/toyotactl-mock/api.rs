@@ -0,0 +1,288 @@
+//! Our emulation of the OneApp API against our fake garage.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use toyotactl::api::{
+    CommandState, Location, Measurement, RemoteCommand, RemoteCommandResult, RemoteCommandStatus,
+    StatusCategory, StatusSection, StatusValue, Telemetry, Vehicle, VehicleStatus,
+};
+use uuid::Uuid;
+
+use super::{MockRequest, MockState, MockVehicle};
+
+/// Reasons we'd refuse a request.
+pub enum Refusal {
+    /// The gateway rejected our key.
+    InvalidGatewayKey,
+    /// The access token is unknown or expired.
+    Unauthorized,
+    /// No such vehicle exists within our garage.
+    VehicleNotFound,
+    /// We never accepted a command with this request number.
+    RequestNotFound,
+}
+
+impl IntoResponse for Refusal {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            // The gateway responds prior to anything reaching Toyota, and as such differs in format.
+            Refusal::InvalidGatewayKey => {
+                return (
+                    StatusCode::FORBIDDEN,
+                    Json(json!({ "message": "Forbidden" })),
+                )
+                    .into_response()
+            }
+            Refusal::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized"),
+            Refusal::VehicleNotFound => (StatusCode::NOT_FOUND, "Vehicle not found"),
+            Refusal::RequestNotFound => (StatusCode::NOT_FOUND, "Request not found"),
+        };
+        let body = json!({ "status": { "messages": [{ "description": body }] } });
+        (status, Json(body)).into_response()
+    }
+}
+
+/// Ensures the request bears our gateway key and a valid access token.
+fn check_authorization(state: &MockState, headers: &HeaderMap) -> Result<(), Refusal> {
+    let gateway_key = headers.get("X-API-KEY").and_then(|key| key.to_str().ok());
+    if gateway_key != Some(state.config.gateway_key.as_str()) {
+        return Err(Refusal::InvalidGatewayKey);
+    }
+
+    let access_token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !state.lock().access_tokens.contains(access_token) {
+        return Err(Refusal::Unauthorized);
+    }
+    Ok(())
+}
+
+/// The vehicle this request concerns, per its VIN header.
+fn requested_vehicle(state: &MockState, headers: &HeaderMap) -> Result<MockVehicle, Refusal> {
+    let vin = headers
+        .get("VIN")
+        .and_then(|vin| vin.to_str().ok())
+        .unwrap_or_default();
+    state
+        .lock()
+        .garage
+        .get(vin)
+        .cloned()
+        .ok_or(Refusal::VehicleNotFound)
+}
+
+/// Wraps the given payload within the API's usual envelope.
+fn payload<T: serde::Serialize>(payload: T) -> Response {
+    Json(json!({ "payload": payload })).into_response()
+}
+
+/// A status section holding a single value.
+fn section(section: &str, value: &str, needs_attention: bool) -> StatusSection {
+    StatusSection {
+        section: section.to_string(),
+        values: vec![StatusValue {
+            value: value.to_string(),
+            status: i32::from(needs_attention),
+        }],
+    }
+}
+
+pub async fn vehicles(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+) -> Result<Response, Refusal> {
+    check_authorization(&state, &headers)?;
+
+    let mut vehicles: Vec<Vehicle> = state
+        .lock()
+        .garage
+        .values()
+        .map(|vehicle| Vehicle {
+            vin: vehicle.vin.clone(),
+            nick_name: vehicle.nick_name.clone(),
+            model_name: vehicle.model_name.clone(),
+            model_year: vehicle.model_year.clone(),
+            remote_service_capabilities: Some(vehicle.remote_service_capabilities.clone()),
+        })
+        .collect();
+    vehicles.sort_by(|a, b| a.vin.cmp(&b.vin));
+    Ok(payload(vehicles))
+}
+
+pub async fn status(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+) -> Result<Response, Refusal> {
+    check_authorization(&state, &headers)?;
+    let vehicle = requested_vehicle(&state, &headers)?;
+
+    let lock_state = if vehicle.locked { "Locked" } else { "Unlocked" };
+    let door = |category: &str| StatusCategory {
+        category: category.to_string(),
+        sections: vec![section("Door", lock_state, !vehicle.locked)],
+    };
+    let engine_state = if vehicle.engine_running {
+        "Running"
+    } else {
+        "Off"
+    };
+    let hazard_state = if vehicle.hazards_on { "On" } else { "Off" };
+
+    Ok(payload(VehicleStatus {
+        occurrence_date: Some(time_now()),
+        latitude: Some(vehicle.latitude),
+        longitude: Some(vehicle.longitude),
+        vehicle_status: vec![
+            door("Driver Side"),
+            door("Passenger Side"),
+            StatusCategory {
+                category: "Other".to_string(),
+                sections: vec![
+                    section("Engine", engine_state, false),
+                    section("Hazard Lights", hazard_state, vehicle.hazards_on),
+                ],
+            },
+        ],
+    }))
+}
+
+pub async fn telemetry(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+) -> Result<Response, Refusal> {
+    check_authorization(&state, &headers)?;
+    let vehicle = requested_vehicle(&state, &headers)?;
+
+    Ok(payload(Telemetry {
+        fuel_level: Some(vehicle.fuel_level),
+        odometer: Some(Measurement {
+            value: vehicle.odometer,
+            unit: "mi".to_string(),
+        }),
+        // We'll assume a fairly efficient 20 gallon tank at 25 MPG.
+        distance_to_empty: Some(Measurement {
+            value: (vehicle.fuel_level / 100.0 * 20.0 * 25.0).round(),
+            unit: "mi".to_string(),
+        }),
+        vehicle_location: Some(Location {
+            latitude: vehicle.latitude,
+            longitude: vehicle.longitude,
+        }),
+        last_timestamp: Some(time_now()),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct CommandRequest {
+    command: RemoteCommand,
+}
+
+pub async fn command(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+    Json(request): Json<CommandRequest>,
+) -> Result<Response, Refusal> {
+    check_authorization(&state, &headers)?;
+    let vehicle = requested_vehicle(&state, &headers)?;
+    let vin = vehicle.vin;
+
+    let mut inner = state.lock();
+    let vehicle = inner.garage.get_mut(&vin).ok_or(Refusal::VehicleNotFound)?;
+    let app_request_no = Uuid::new_v4().to_string();
+    let mut status = CommandState::Completed;
+    match request.command {
+        RemoteCommand::DoorLock => vehicle.locked = true,
+        RemoteCommand::DoorUnlock => vehicle.locked = false,
+        RemoteCommand::HazardOn => vehicle.hazards_on = true,
+        RemoteCommand::HazardOff => vehicle.hazards_on = false,
+        RemoteCommand::EngineStop => {
+            vehicle.engine_running = false;
+            // Any start still pending is cancelled.
+            *inner.engine_generations.entry(vin.clone()).or_default() += 1;
+        }
+        RemoteCommand::EngineStart => {
+            let generation = inner.engine_generations.entry(vin.clone()).or_default();
+            *generation += 1;
+            let started = *generation;
+            status = CommandState::Pending;
+
+            // Much like an actual vehicle, our engine takes a moment to start.
+            let state = state.clone();
+            let vin = vin.clone();
+            let app_request_no = app_request_no.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(state.config.engine_delay).await;
+                let mut inner = state.lock();
+                let status = if inner.engine_generations.get(&vin) == Some(&started) {
+                    if let Some(vehicle) = inner.garage.get_mut(&vin) {
+                        vehicle.engine_running = true;
+                    }
+                    CommandState::Completed
+                } else {
+                    CommandState::Failed
+                };
+                if let Some(request) = inner.requests.get_mut(&app_request_no) {
+                    request.status = status;
+                }
+            });
+        }
+    }
+
+    inner
+        .requests
+        .insert(app_request_no.clone(), MockRequest { vin, status });
+    Ok(payload(RemoteCommandResult {
+        app_request_no: Some(app_request_no),
+        return_code: Some("000000".to_string()),
+    }))
+}
+
+/// The progress of a command we accepted.
+///
+/// We've yet to find how the app learns this, so we serve it beneath a path of our own,
+/// which clients must opt into via their `command_status_path`.
+pub async fn command_status(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+) -> Result<Response, Refusal> {
+    check_authorization(&state, &headers)?;
+    let vehicle = requested_vehicle(&state, &headers)?;
+    let app_request_no = headers
+        .get("APP-REQUEST-NO")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    let status = state
+        .lock()
+        .requests
+        .get(app_request_no)
+        .filter(|request| request.vin == vehicle.vin)
+        .ok_or(Refusal::RequestNotFound)?
+        .status;
+    let return_code = match status {
+        CommandState::Failed => "400001",
+        _ => "000000",
+    };
+    Ok(payload(RemoteCommandStatus {
+        app_request_no: Some(app_request_no.to_string()),
+        status,
+        return_code: Some(return_code.to_string()),
+    }))
+}
+
+/// The current time, formatted as the API does.
+fn time_now() -> String {
+    time::OffsetDateTime::now_utc()
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap_or_default()
+}
@@ -0,0 +1,314 @@
+//! Our emulation of ForgeRock AM's authentication tree and OAuth2 endpoints.
+
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use p256::ecdsa::{signature::Signer, Signature};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+use super::{MockState, MOCK_GUID};
+
+/// The name of our sole account.
+const MOCK_NAME: &str = "Mock User";
+
+/// How long issued access tokens are valid for, in seconds.
+const ACCESS_TOKEN_LIFETIME: u64 = 3600;
+
+/// How long issued refresh tokens are valid for, in seconds.
+const REFRESH_TOKEN_LIFETIME: u64 = 90 * 24 * 3600;
+
+/// The stages of our authentication tree, in order.
+/// This mirrors what's been observed from Toyota's `OneAppSignIn` tree.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Locale,
+    Username,
+    Password,
+    OneTimePassword,
+    DevicePrint,
+}
+
+impl Stage {
+    fn from_index(index: u64) -> Option<Self> {
+        match index {
+            0 => Some(Stage::Locale),
+            1 => Some(Stage::Username),
+            2 => Some(Stage::Password),
+            3 => Some(Stage::OneTimePassword),
+            4 => Some(Stage::DevicePrint),
+            _ => None,
+        }
+    }
+
+    /// The callback presented to the client for this stage.
+    fn callback(&self) -> Value {
+        let (callback_type, prompt) = match self {
+            Stage::Locale => ("NameCallback", "ui_locales"),
+            Stage::Username => ("NameCallback", "User Name"),
+            Stage::Password => ("PasswordCallback", "Password"),
+            Stage::OneTimePassword => ("PasswordCallback", "One Time Password"),
+            Stage::DevicePrint => {
+                return json!({
+                    "type": "HiddenValueCallback",
+                    "output": [
+                        { "name": "value", "value": "" },
+                        { "name": "id", "value": "devicePrint" }
+                    ],
+                    "input": [{ "name": "IDToken1", "value": "devicePrint" }]
+                });
+            }
+        };
+        json!({
+            "type": callback_type,
+            "output": [{ "name": "prompt", "value": prompt }],
+            "input": [{ "name": "IDToken1", "value": "" }]
+        })
+    }
+}
+
+/// The current Unix timestamp.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Creates a JWT around the given claims, signed with our ES256 key.
+fn jwt(state: &MockState, claims: Value) -> String {
+    let header = json!({ "alg": "ES256", "typ": "JWT", "kid": state.key_id });
+    let header = URL_SAFE_NO_PAD.encode(header.to_string());
+    let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+    let signing_input = format!("{header}.{payload}");
+
+    // JWS signatures are the raw r and s values, rather than DER.
+    let signature: Signature = state.signing_key.sign(signing_input.as_bytes());
+    let signature = URL_SAFE_NO_PAD.encode(signature.to_bytes());
+    format!("{signing_input}.{signature}")
+}
+
+/// Publishes the key we sign tokens with.
+pub async fn jwks(State(state): State<Arc<MockState>>) -> Json<Value> {
+    let point = state.signing_key.verifying_key().to_encoded_point(false);
+    let coordinate = |bytes: Option<&[u8]>| URL_SAFE_NO_PAD.encode(bytes.unwrap_or_default());
+    Json(json!({
+        "keys": [{
+            "kty": "EC",
+            "kid": state.key_id,
+            "use": "sig",
+            "alg": "ES256",
+            "crv": "P-256",
+            "x": coordinate(point.x().map(|x| x.as_slice())),
+            "y": coordinate(point.y().map(|y| y.as_slice())),
+        }]
+    }))
+}
+
+/// The response ForgeRock gives upon any authentication failure.
+fn login_failure() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(json!({
+            "code": 401,
+            "reason": "Unauthorized",
+            "message": "Login failure"
+        })),
+    )
+        .into_response()
+}
+
+/// Presents the given stage to the client.
+fn present(stage: Stage) -> Response {
+    Json(json!({
+        "authId": format!("mock-auth-{}", stage as u64),
+        "callbacks": [stage.callback()]
+    }))
+    .into_response()
+}
+
+/// The first input value within the given callback body.
+fn input_value(body: &Value) -> Option<&str> {
+    body.get("callbacks")?
+        .get(0)?
+        .get("input")?
+        .get(0)?
+        .get("value")?
+        .as_str()
+}
+
+/// Walks the client through our authentication tree, one callback at a time.
+pub async fn authenticate(State(state): State<Arc<MockState>>, body: String) -> Response {
+    // An empty body begins authentication.
+    let Ok(body) = serde_json::from_str::<Value>(&body) else {
+        return present(Stage::Locale);
+    };
+    let Some(stage) = body
+        .get("authId")
+        .and_then(Value::as_str)
+        .and_then(|auth_id| auth_id.strip_prefix("mock-auth-"))
+        .and_then(|index| index.parse().ok())
+        .and_then(Stage::from_index)
+    else {
+        return present(Stage::Locale);
+    };
+
+    let value = input_value(&body).unwrap_or_default();
+    let config = &state.config;
+    let next = match stage {
+        Stage::Locale => Stage::Username,
+        Stage::Username if value == config.username => Stage::Password,
+        Stage::Password if value == config.password => Stage::OneTimePassword,
+        Stage::OneTimePassword if value == config.otp => Stage::DevicePrint,
+        Stage::DevicePrint if !value.is_empty() => {
+            let token_id = format!("AQIC5w{}", Uuid::new_v4().simple());
+            state.lock().token_ids.insert(token_id.clone());
+            return Json(json!({
+                "tokenId": token_id,
+                "successUrl": "/console",
+                "realm": "/tmna-native"
+            }))
+            .into_response();
+        }
+        _ => return login_failure(),
+    };
+    present(next)
+}
+
+/// Issues an authorization code given a valid `iPlanetDirectoryPro` cookie.
+pub async fn authorize(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let token_id = headers
+        .get(header::COOKIE)
+        .and_then(|cookie| cookie.to_str().ok())
+        .and_then(|cookie| {
+            cookie
+                .split(';')
+                .find_map(|pair| pair.trim().strip_prefix("iPlanetDirectoryPro="))
+        })
+        .unwrap_or_default();
+
+    let mut inner = state.lock();
+    if !inner.token_ids.contains(token_id) {
+        return login_failure();
+    }
+    let Some(redirect_uri) = query.get("redirect_uri") else {
+        return (StatusCode::BAD_REQUEST, "missing redirect_uri").into_response();
+    };
+
+    let code = Uuid::new_v4().simple().to_string();
+    inner.codes.insert(code.clone(), MOCK_GUID.to_string());
+    let location = format!("{redirect_uri}?code={code}&iss=mock&client_id=oneappsdkclient");
+    (StatusCode::FOUND, [(header::LOCATION, location)]).into_response()
+}
+
+/// Exchanges an authorization code or refresh token for new tokens.
+pub async fn access_token(
+    State(state): State<Arc<MockState>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let mut inner = state.lock();
+    let guid = match query.get("grant_type").map(String::as_str) {
+        Some("authorization_code") => query.get("code").and_then(|code| inner.codes.remove(code)),
+        Some("refresh_token") => query
+            .get("refresh_token")
+            .and_then(|token| inner.refresh_tokens.remove(token)),
+        _ => None,
+    };
+    let Some(guid) = guid else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "invalid_grant",
+                "error_description": "The provided access grant is invalid, expired, or revoked."
+            })),
+        )
+            .into_response();
+    };
+
+    let issued_at = now();
+    let claims = json!({
+        "sub": guid,
+        "iss": "mock",
+        "aud": "oneappsdkclient",
+        "iat": issued_at,
+        "exp": issued_at + ACCESS_TOKEN_LIFETIME,
+        "jti": Uuid::new_v4().to_string(),
+    });
+    let access_token = jwt(&state, claims);
+    let id_token = jwt(
+        &state,
+        json!({
+            "sub": guid,
+            "iss": "mock",
+            "aud": "oneappsdkclient",
+            "iat": issued_at,
+            "exp": issued_at + ACCESS_TOKEN_LIFETIME,
+            "name": MOCK_NAME,
+            "email": state.config.username,
+        }),
+    );
+    // Toyota's refresh tokens are JWTs as well, which our client relies upon.
+    let refresh_token = jwt(
+        &state,
+        json!({
+            "sub": guid,
+            "iss": "mock",
+            "iat": issued_at,
+            "exp": issued_at + REFRESH_TOKEN_LIFETIME,
+            "jti": Uuid::new_v4().to_string(),
+        }),
+    );
+    inner.access_tokens.insert(access_token.clone());
+    inner.refresh_tokens.insert(refresh_token.clone(), guid);
+
+    Json(json!({
+        "access_token": access_token,
+        "refresh_token": refresh_token,
+        "id_token": id_token,
+        "scope": "openid profile write",
+        "token_type": "Bearer",
+        "expires_in": ACCESS_TOKEN_LIFETIME,
+    }))
+    .into_response()
+}
+
+/// Describes the user a valid access token was issued for.
+pub async fn userinfo(State(state): State<Arc<MockState>>, headers: HeaderMap) -> Response {
+    let access_token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !state.lock().access_tokens.contains(access_token) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({
+                "error": "invalid_token",
+                "error_description": "The access token provided is expired, revoked, malformed, or invalid for other reasons."
+            })),
+        )
+            .into_response();
+    }
+
+    Json(json!({
+        "sub": MOCK_GUID,
+        "name": MOCK_NAME,
+        "given_name": "Mock",
+        "family_name": "User",
+        "email": state.config.username,
+    }))
+    .into_response()
+}
@@ -0,0 +1,270 @@
+//! A stand-in for Toyota's services, for local development and CI.
+//!
+//! We emulate just enough of ForgeRock AM and the OneApp API for every
+//! `toyotactl` command to function against a fake garage. For example:
+//! ```sh
+//! toyotactl-mock --listen 127.0.0.1:8080 &
+//! toyotactl --forgerock-url http://127.0.0.1:8080/ --api-url http://127.0.0.1:8080/oneapi/ \
+//!     --gateway-key mock-gateway-key vehicles
+//! ```
+
+mod api;
+mod forgerock;
+
+use axum::{
+    routing::{get, post},
+    Router,
+};
+use clap::Parser;
+use p256::ecdsa::SigningKey;
+use rand::rngs::OsRng;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    net::SocketAddr,
+    path::PathBuf,
+    process,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+use toyotactl::api::{CommandState, RemoteCapabilities};
+
+#[derive(Parser)]
+#[command(version, about = "A stand-in for Toyota's services")]
+struct Cli {
+    /// The address to listen on.
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    /// A JSON file describing the vehicles within our garage.
+    /// By default, we'll have a single vehicle.
+    #[arg(long)]
+    garage: Option<PathBuf>,
+
+    /// The username accepted when logging in.
+    #[arg(long, default_value = "user@example.com")]
+    username: String,
+
+    /// The password accepted when logging in.
+    #[arg(long, default_value = "password")]
+    password: String,
+
+    /// The one-time password accepted when logging in.
+    #[arg(long, default_value = "123456")]
+    otp: String,
+
+    /// The API gateway key we require.
+    #[arg(long, default_value = "mock-gateway-key")]
+    gateway_key: String,
+
+    /// How many seconds it takes for a remotely started engine to begin running.
+    #[arg(long, default_value_t = 5)]
+    engine_delay: u64,
+}
+
+/// A vehicle within our fake garage, alongside its simulated state.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MockVehicle {
+    pub vin: String,
+    #[serde(default)]
+    pub nick_name: Option<String>,
+    #[serde(default)]
+    pub model_name: Option<String>,
+    #[serde(default)]
+    pub model_year: Option<String>,
+    #[serde(default = "default_true")]
+    pub locked: bool,
+    #[serde(default)]
+    pub engine_running: bool,
+    #[serde(default)]
+    pub hazards_on: bool,
+    #[serde(default = "default_fuel_level")]
+    pub fuel_level: f64,
+    #[serde(default)]
+    pub odometer: f64,
+    /// Which remote commands this vehicle reports supporting.
+    #[serde(default = "default_capabilities")]
+    pub remote_service_capabilities: RemoteCapabilities,
+    #[serde(default)]
+    pub latitude: f64,
+    #[serde(default)]
+    pub longitude: f64,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_fuel_level() -> f64 {
+    75.0
+}
+
+/// By default, our vehicles support every command.
+fn default_capabilities() -> RemoteCapabilities {
+    RemoteCapabilities {
+        dlock_unlock_capable: Some(true),
+        estart_stop_capable: Some(true),
+        hazard_capable: Some(true),
+    }
+}
+
+impl Default for MockVehicle {
+    fn default() -> Self {
+        Self {
+            vin: "JTMOCK00000000001".to_string(),
+            nick_name: Some("Mock Tacoma".to_string()),
+            model_name: Some("Tacoma".to_string()),
+            model_year: Some("2024".to_string()),
+            locked: true,
+            engine_running: false,
+            hazards_on: false,
+            fuel_level: default_fuel_level(),
+            odometer: 12345.0,
+            remote_service_capabilities: default_capabilities(),
+            latitude: 33.0841,
+            longitude: -96.8381,
+        }
+    }
+}
+
+/// Everything shared across requests.
+pub struct MockState {
+    pub config: MockConfig,
+    /// The key we sign tokens with, generated anew every run and published via our JWKS.
+    pub signing_key: SigningKey,
+    pub key_id: String,
+    pub inner: Mutex<Inner>,
+}
+
+/// How our server was configured.
+pub struct MockConfig {
+    pub username: String,
+    pub password: String,
+    pub otp: String,
+    pub gateway_key: String,
+    pub engine_delay: Duration,
+}
+
+/// Our mutable state: issued tokens, and the garage itself.
+#[derive(Default)]
+pub struct Inner {
+    /// Token IDs issued after authentication, usable as `iPlanetDirectoryPro`.
+    pub token_ids: HashSet<String>,
+    /// Authorization codes, and the GUID they were issued for.
+    pub codes: HashMap<String, String>,
+    /// Refresh tokens, and the GUID they were issued for.
+    pub refresh_tokens: HashMap<String, String>,
+    /// Access tokens currently valid.
+    pub access_tokens: HashSet<String>,
+    /// Vehicles, keyed by their VIN.
+    pub garage: HashMap<String, MockVehicle>,
+    /// How many times each vehicle's engine has been started or stopped.
+    /// A pending start only takes effect if nothing has happened since.
+    pub engine_generations: HashMap<String, u64>,
+    /// Commands we've accepted, keyed by their request number.
+    pub requests: HashMap<String, MockRequest>,
+}
+
+/// A command we've accepted, so that its progress can be polled.
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub vin: String,
+    /// Everything but an engine start takes effect immediately.
+    /// A start remains pending until the engine runs, or fails once cancelled.
+    pub status: CommandState,
+}
+
+impl MockState {
+    pub fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The GUID of our sole account.
+pub const MOCK_GUID: &str = "00000000-0000-4000-8000-000000000000";
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+
+    let vehicles = match &cli.garage {
+        Some(path) => {
+            let contents = fs::read_to_string(path).unwrap_or_else(|err| {
+                eprintln!("Unable to read garage: {err}");
+                process::exit(1);
+            });
+            serde_json::from_str::<Vec<MockVehicle>>(&contents).unwrap_or_else(|err| {
+                eprintln!("Unable to parse garage: {err}");
+                process::exit(1);
+            })
+        }
+        None => vec![MockVehicle::default()],
+    };
+
+    let state = Arc::new(MockState {
+        config: MockConfig {
+            username: cli.username,
+            password: cli.password,
+            otp: cli.otp,
+            gateway_key: cli.gateway_key,
+            engine_delay: Duration::from_secs(cli.engine_delay),
+        },
+        signing_key: SigningKey::random(&mut OsRng),
+        key_id: uuid::Uuid::new_v4().to_string(),
+        inner: Mutex::new(Inner {
+            garage: vehicles
+                .into_iter()
+                .map(|vehicle| (vehicle.vin.clone(), vehicle))
+                .collect(),
+            ..Inner::default()
+        }),
+    });
+
+    let app = Router::new()
+        .route(
+            "/json/realms/root/realms/:realm/authenticate",
+            post(forgerock::authenticate),
+        )
+        .route(
+            "/oauth2/realms/root/realms/:realm/authorize",
+            get(forgerock::authorize),
+        )
+        .route(
+            "/oauth2/realms/root/realms/:realm/access_token",
+            post(forgerock::access_token),
+        )
+        .route(
+            "/oauth2/realms/root/realms/:realm/userinfo",
+            get(forgerock::userinfo),
+        )
+        .route(
+            "/oauth2/realms/root/realms/:realm/connect/jwk_uri",
+            get(forgerock::jwks),
+        )
+        .route("/oneapi/v3/vehicle/guid", get(api::vehicles))
+        .route("/oneapi/v1/global/remote/status", get(api::status))
+        .route("/oneapi/v2/telemetry", get(api::telemetry))
+        .route("/oneapi/v1/global/remote/command", post(api::command))
+        .route(
+            "/oneapi/mock/remote/command/status",
+            get(api::command_status),
+        )
+        .with_state(state);
+
+    let listener = match tokio::net::TcpListener::bind(cli.listen).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Unable to listen on {}: {err}", cli.listen);
+            process::exit(1);
+        }
+    };
+    // We may have been asked for any available port, so we'll print the one we received.
+    let address = listener.local_addr().unwrap_or(cli.listen);
+    println!("Listening on http://{address}");
+    if let Err(err) = axum::serve(listener, app).await {
+        eprintln!("Server failed: {err}");
+        process::exit(1);
+    }
+}
@@ -0,0 +1,335 @@
+//! A local cache of API responses, kept per profile and then per VIN.
+//!
+//! Nothing here is secret: we only ever cache what the API told us about vehicles.
+//! Failing to read or write the cache is never fatal, so we'll log and carry on.
+//!
+//! Within each profile's directory, `vehicles.json` holds the vehicle list, and
+//! `<VIN>/status.json` and `<VIN>/telemetry.json` hold the last snapshots we obtained.
+//! `<VIN>/capabilities.json` holds the remote commands each vehicle last reported supporting.
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    api::{RemoteCapabilities, Telemetry, Vehicle, VehicleStatus},
+    storage,
+};
+
+/// How long the vehicle list is considered fresh by default. It rarely changes.
+pub const VEHICLES_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long a vehicle's capabilities are considered fresh by default.
+/// They only change with a new subscription, so we'll keep them for a week.
+pub const CAPABILITIES_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How long status and telemetry snapshots are considered fresh by default.
+pub const SNAPSHOT_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// A cached value, alongside when we fetched it.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CacheEntry<T> {
+    /// When this value was fetched, as a Unix timestamp.
+    pub fetched_at: u64,
+    pub value: T,
+}
+
+impl<T> CacheEntry<T> {
+    /// How long ago this value was fetched.
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(now().saturating_sub(self.fetched_at))
+    }
+}
+
+/// A directory of cached responses for a single profile.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+/// The current Unix timestamp.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl Cache {
+    /// Creates a cache within the given directory.
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// The cache for the given profile, within the user's cache directory.
+    ///
+    /// We'll refuse profile names that could escape it, per ``storage::is_valid_profile``.
+    pub fn for_profile(profile: &str) -> Option<Self> {
+        if !storage::is_valid_profile(profile) {
+            return None;
+        }
+        Some(Self::new(
+            &dirs::cache_dir()?.join("toyotactl").join(profile),
+        ))
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.json"))
+    }
+
+    /// Reads the given entry, if present and readable.
+    pub fn read<T: DeserializeOwned>(&self, name: &str) -> Option<CacheEntry<T>> {
+        let path = self.path(name);
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+            Err(err) => {
+                tracing::warn!(path = %path.display(), "unable to read cache: {err}");
+                return None;
+            }
+        };
+        match serde_json::from_slice(&contents) {
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                tracing::warn!(path = %path.display(), "unable to parse cache: {err}");
+                None
+            }
+        }
+    }
+
+    /// Writes the given value, noting that it was fetched just now.
+    pub fn write<T: Serialize>(&self, name: &str, value: &T) {
+        let path = self.path(name);
+        let entry = CacheEntry {
+            fetched_at: now(),
+            value,
+        };
+        let parent = path.parent().unwrap_or(&self.dir);
+        let written = fs::create_dir_all(parent)
+            .and_then(|_| serde_json::to_vec_pretty(&entry).map_err(io::Error::other))
+            .and_then(|contents| fs::write(&path, contents));
+        if let Err(err) = written {
+            tracing::warn!(path = %path.display(), "unable to write cache: {err}");
+        }
+    }
+
+    /// Removes the given entry, if present.
+    pub fn remove(&self, name: &str) {
+        let path = self.path(name);
+        match fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => tracing::warn!(path = %path.display(), "unable to remove cache: {err}"),
+        }
+    }
+
+    /// The vehicles we last listed, such as for shell completion.
+    pub fn vehicles(&self) -> Option<Vec<Vehicle>> {
+        self.vehicles_entry().map(|entry| entry.value)
+    }
+
+    /// The vehicles we last listed, alongside when we listed them.
+    pub fn vehicles_entry(&self) -> Option<CacheEntry<Vec<Vehicle>>> {
+        self.read("vehicles")
+    }
+
+    /// Remembers the vehicles we just listed, alongside the capabilities each reported.
+    pub fn store_vehicles(&self, vehicles: &[Vehicle]) {
+        self.write("vehicles", &vehicles);
+        for vehicle in vehicles {
+            if let Some(capabilities) = &vehicle.remote_service_capabilities {
+                self.store_capabilities(&vehicle.vin, capabilities);
+            }
+        }
+    }
+
+    /// The remote commands the given vehicle last reported supporting.
+    pub fn capabilities(&self, vin: &str) -> Option<CacheEntry<RemoteCapabilities>> {
+        self.read(&vehicle_entry(vin, "capabilities")?)
+    }
+
+    pub fn store_capabilities(&self, vin: &str, capabilities: &RemoteCapabilities) {
+        if let Some(name) = vehicle_entry(vin, "capabilities") {
+            self.write(&name, capabilities)
+        }
+    }
+
+    /// The last status we obtained for the given vehicle.
+    pub fn status(&self, vin: &str) -> Option<CacheEntry<VehicleStatus>> {
+        self.read(&vehicle_entry(vin, "status")?)
+    }
+
+    pub fn store_status(&self, vin: &str, status: &VehicleStatus) {
+        if let Some(name) = vehicle_entry(vin, "status") {
+            self.write(&name, status)
+        }
+    }
+
+    /// The last telemetry we obtained for the given vehicle.
+    pub fn telemetry(&self, vin: &str) -> Option<CacheEntry<Telemetry>> {
+        self.read(&vehicle_entry(vin, "telemetry")?)
+    }
+
+    pub fn store_telemetry(&self, vin: &str, telemetry: &Telemetry) {
+        if let Some(name) = vehicle_entry(vin, "telemetry") {
+            self.write(&name, telemetry)
+        }
+    }
+
+    /// Forgets the given vehicle's last status and telemetry,
+    /// such as after a remote command changed them.
+    pub fn invalidate(&self, vin: &str) {
+        for entry in ["status", "telemetry"] {
+            if let Some(name) = vehicle_entry(vin, entry) {
+                self.remove(&name)
+            }
+        }
+    }
+}
+
+/// The name of the given entry for the given vehicle.
+///
+/// VINs come from the user, so we'll refuse anything that could escape our directory.
+fn vehicle_entry(vin: &str, name: &str) -> Option<String> {
+    if vin.is_empty() || !vin.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    Some(format!("{vin}/{name}"))
+}
+
+/// Parses a maximum age, such as `90`, `90s`, `5m`, `2h` or `1d`.
+pub fn parse_max_age(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((index, 's')) => (&value[..index], 1),
+        Some((index, 'm')) => (&value[..index], 60),
+        Some((index, 'h')) => (&value[..index], 60 * 60),
+        Some((index, 'd')) => (&value[..index], 24 * 60 * 60),
+        _ => (value, 1),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid age: {value} (expected e.g. 90, 90s, 5m, 2h or 1d)"))?;
+    Ok(Duration::from_secs(number.saturating_mul(multiplier)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    use crate::api::RemoteCommand;
+
+    const VIN: &str = "JTMOCK00000000001";
+
+    /// A cache within its own temporary directory, removed once dropped.
+    struct TempCache(Cache);
+
+    impl TempCache {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("toyotactl-cache-{}-{name}", std::process::id()));
+            Self(Cache::new(&dir))
+        }
+    }
+
+    impl Drop for TempCache {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0.dir);
+        }
+    }
+
+    fn snapshot<T: DeserializeOwned>(value: serde_json::Value) -> T {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn profiles_cannot_escape() {
+        for profile in ["", ".", "..", "../..", "a/b", "/etc", ".hidden", "a b"] {
+            assert!(Cache::for_profile(profile).is_none(), "{profile:?}");
+        }
+        if let Some(cache) = Cache::for_profile("work-2_b") {
+            assert!(cache.dir.ends_with("toyotactl/work-2_b"));
+        }
+    }
+
+    #[test]
+    fn vehicles_store_capabilities() {
+        let cache = TempCache::new("capabilities");
+        let vehicles: Vec<Vehicle> = snapshot(json!([
+            {
+                "vin": VIN,
+                "remoteServiceCapabilities": {
+                    "dlockUnlockCapable": true,
+                    "estartStopCapable": false,
+                    "trunkLockUnlockCapable": true,
+                },
+            },
+            { "vin": "JTMOCK00000000002" },
+        ]));
+        cache.0.store_vehicles(&vehicles);
+
+        let capabilities = cache.0.capabilities(VIN).unwrap();
+        assert!(capabilities.age() < CAPABILITIES_MAX_AGE);
+        let capabilities = capabilities.value;
+        assert_eq!(capabilities.supports(RemoteCommand::DoorUnlock), Some(true));
+        assert_eq!(
+            capabilities.supports(RemoteCommand::EngineStart),
+            Some(false)
+        );
+        assert_eq!(capabilities.supports(RemoteCommand::HazardOn), None);
+
+        // Vehicles reporting nothing have nothing cached.
+        assert!(cache.0.capabilities("JTMOCK00000000002").is_none());
+        assert_eq!(cache.0.vehicles().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn invalidate_clears_snapshots() {
+        let cache = TempCache::new("invalidate");
+        let status: VehicleStatus = snapshot(json!({ "vehicleStatus": [] }));
+        let telemetry: Telemetry = snapshot(json!({ "fuelLevel": 50.0 }));
+        let capabilities = RemoteCapabilities {
+            hazard_capable: Some(true),
+            ..RemoteCapabilities::default()
+        };
+        cache.0.store_status(VIN, &status);
+        cache.0.store_telemetry(VIN, &telemetry);
+        cache.0.store_capabilities(VIN, &capabilities);
+
+        cache.0.invalidate(VIN);
+        assert!(cache.0.status(VIN).is_none());
+        assert!(cache.0.telemetry(VIN).is_none());
+        // Commands don't change what a vehicle is capable of.
+        assert_eq!(cache.0.capabilities(VIN).unwrap().value, capabilities);
+    }
+
+    #[test]
+    fn vins_cannot_escape() {
+        let cache = TempCache::new("escape");
+        let telemetry: Telemetry = snapshot(json!({}));
+        for vin in ["", "../escape", "a/b", "VIN.json"] {
+            cache.0.store_telemetry(vin, &telemetry);
+            assert!(cache.0.telemetry(vin).is_none(), "{vin}");
+        }
+        assert!(!cache.0.dir.exists());
+    }
+
+    #[test]
+    fn max_ages() {
+        assert_eq!(parse_max_age("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_max_age("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_max_age("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_max_age(" 2h ").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_max_age("1d").unwrap(), Duration::from_secs(86400));
+        assert_eq!(parse_max_age("0").unwrap(), Duration::ZERO);
+        for invalid in ["", "m", "5w", "-1", "1.5h"] {
+            assert!(parse_max_age(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,471 @@
+//! Capturing HTTP traffic as a HAR file, such as for attaching to bug reports.
+//!
+//! Every secret is redacted prior to being written, so that captures can be shared.
+//! Please refer to http://www.softwareishard.com/blog/har-12-spec/ for the format.
+
+use reqwest::{header::HeaderMap, Request, StatusCode, Version};
+use serde::Serialize;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    time::{Duration, SystemTime},
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::redact;
+
+/// Records requests and responses, writing them to a HAR file.
+///
+/// We rewrite the entire file after every entry. This way, our capture
+/// is complete even if we exit abruptly - which is when it's needed most.
+#[derive(Debug)]
+pub struct HarCapture {
+    path: PathBuf,
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl HarCapture {
+    /// Creates a capture that will be written to the given path.
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records the given exchange, and writes our capture to disk.
+    pub fn record(&self, entry: Entry) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.push(entry);
+        if let Err(err) = self.write(&entries) {
+            tracing::warn!(path = %self.path.display(), "unable to write capture: {err}");
+        }
+    }
+
+    fn write(&self, entries: &[Entry]) -> io::Result<()> {
+        let har = Har {
+            log: Log {
+                version: "1.2",
+                creator: Creator {
+                    name: env!("CARGO_PKG_NAME"),
+                    version: env!("CARGO_PKG_VERSION"),
+                },
+                entries,
+            },
+        };
+        let contents = serde_json::to_vec_pretty(&har).map_err(io::Error::other)?;
+        fs::write(&self.path, contents)
+    }
+}
+
+#[derive(Serialize)]
+struct Har<'a> {
+    log: Log<'a>,
+}
+
+#[derive(Serialize)]
+struct Log<'a> {
+    version: &'static str,
+    creator: Creator,
+    entries: &'a [Entry],
+}
+
+#[derive(Serialize)]
+struct Creator {
+    name: &'static str,
+    version: &'static str,
+}
+
+/// An individual request and its response.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    started_date_time: String,
+    /// The total time taken, in milliseconds.
+    time: f64,
+    request: HarRequest,
+    response: HarResponse,
+    cache: serde_json::Value,
+    timings: Timings,
+}
+
+impl Entry {
+    /// Creates an entry for the given exchange, which began at the given time.
+    pub fn new(
+        started: SystemTime,
+        elapsed: Duration,
+        request: HarRequest,
+        response: HarResponse,
+    ) -> Self {
+        let started_date_time = OffsetDateTime::from(started)
+            .format(&Rfc3339)
+            .unwrap_or_default();
+        let time = elapsed.as_secs_f64() * 1000.0;
+        Self {
+            started_date_time,
+            time,
+            request,
+            response,
+            cache: serde_json::json!({}),
+            timings: Timings {
+                send: 0.0,
+                wait: time,
+                receive: 0.0,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct Timings {
+    send: f64,
+    wait: f64,
+    receive: f64,
+}
+
+#[derive(Serialize, Debug)]
+struct NameValue {
+    name: String,
+    value: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PostData {
+    mime_type: String,
+    text: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HarRequest {
+    method: String,
+    url: String,
+    http_version: String,
+    cookies: Vec<NameValue>,
+    headers: Vec<NameValue>,
+    query_string: Vec<NameValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    post_data: Option<PostData>,
+    headers_size: i64,
+    body_size: i64,
+}
+
+impl HarRequest {
+    /// Describes the given request, redacting all secrets.
+    pub fn new(request: &Request) -> Self {
+        let body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(String::from_utf8_lossy);
+        let body_size = body.as_ref().map_or(0, |body| body.len() as i64);
+        let post_data = body.map(|body| PostData {
+            mime_type: content_type(request.headers()),
+            text: redact::redact_body(&body),
+        });
+
+        let query_string = request
+            .url()
+            .query_pairs()
+            .map(|(name, value)| NameValue {
+                value: redact::redact_query_value(&name, &value),
+                name: name.into_owned(),
+            })
+            .collect();
+
+        Self {
+            method: request.method().to_string(),
+            url: redact::redact_url(request.url().as_str()),
+            http_version: http_version(request.version()),
+            cookies: Vec::new(),
+            headers: headers(request.headers()),
+            query_string,
+            post_data,
+            headers_size: -1,
+            body_size,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Content {
+    size: i64,
+    mime_type: String,
+    text: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HarResponse {
+    status: u16,
+    status_text: String,
+    http_version: String,
+    cookies: Vec<NameValue>,
+    headers: Vec<NameValue>,
+    content: Content,
+    #[serde(rename = "redirectURL")]
+    redirect_url: String,
+    headers_size: i64,
+    body_size: i64,
+    /// Why no response was received, should the request have failed.
+    #[serde(rename = "_error", skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl HarResponse {
+    /// Describes the given response, redacting all secrets.
+    pub fn new(status: StatusCode, version: Version, headers: &HeaderMap, body: &[u8]) -> Self {
+        let redirect_url = headers
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .map(redact::redact_url)
+            .unwrap_or_default();
+
+        Self {
+            status: status.as_u16(),
+            status_text: status.canonical_reason().unwrap_or_default().to_string(),
+            http_version: http_version(version),
+            cookies: Vec::new(),
+            headers: self::headers(headers),
+            content: Content {
+                size: body.len() as i64,
+                mime_type: content_type(headers),
+                text: redact::redact_body(&String::from_utf8_lossy(body)),
+            },
+            redirect_url,
+            headers_size: -1,
+            body_size: body.len() as i64,
+            error: None,
+        }
+    }
+
+    /// Describes a request that failed without a response, such as due to a connection error.
+    pub fn failed(error: &reqwest::Error) -> Self {
+        Self {
+            status: 0,
+            status_text: String::new(),
+            http_version: String::new(),
+            cookies: Vec::new(),
+            headers: Vec::new(),
+            content: Content {
+                size: 0,
+                mime_type: String::new(),
+                text: String::new(),
+            },
+            redirect_url: String::new(),
+            headers_size: -1,
+            body_size: -1,
+            error: Some(redact::redact(&error.to_string())),
+        }
+    }
+}
+
+/// Redacts the given headers, converting them to HAR's format.
+fn headers(headers: &HeaderMap) -> Vec<NameValue> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            let value = if redact::is_secret_header(name.as_str()) {
+                redact::REDACTED.to_string()
+            } else if name == reqwest::header::LOCATION {
+                redact::redact_url(&value)
+            } else {
+                redact::redact(&value)
+            };
+            NameValue {
+                name: name.to_string(),
+                value,
+            }
+        })
+        .collect()
+}
+
+fn content_type(headers: &HeaderMap) -> String {
+    headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn http_version(version: Version) -> String {
+    format!("{version:?}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, COOKIE, SET_COOKIE};
+    use serde_json::{json, Value};
+
+    const JWT: &str = "eyJhbGciOiJub25lIn0.eyJzdWIiOiJndWlkIn0.c2ln";
+
+    /// A unique path within our temporary directory, removed once dropped.
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!(
+                "toyotactl-capture-{}-{name}.har",
+                std::process::id()
+            )))
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Records the given entry, returning the resulting HAR file both parsed and as-is.
+    fn captured(name: &str, entry: Entry) -> (Value, String) {
+        let path = TempPath::new(name);
+        HarCapture::new(&path.0).record(entry);
+        let contents = fs::read_to_string(&path.0).unwrap();
+        (serde_json::from_str(&contents).unwrap(), contents)
+    }
+
+    fn request() -> Request {
+        reqwest::Client::new()
+            .post("https://example.com/oauth2/access_token?grant_type=refresh_token&refresh_token=opaque-refresh")
+            .header(AUTHORIZATION, format!("Bearer {JWT}"))
+            .header(COOKIE, "iPlanetDirectoryPro=session-token; amlbcookie=01")
+            .header("X-API-KEY", "gateway-key")
+            .header(CONTENT_TYPE, "application/json")
+            .body(json!({"password": "hunter22", "nickName": "Tacoma"}).to_string())
+            .build()
+            .unwrap()
+    }
+
+    fn response() -> HarResponse {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(
+            SET_COOKIE,
+            HeaderValue::from_static("iPlanetDirectoryPro=rotated-session; Path=/"),
+        );
+        let body = json!({"access_token": JWT, "refresh_token": "opaque-refresh"}).to_string();
+        HarResponse::new(StatusCode::OK, Version::HTTP_11, &headers, body.as_bytes())
+    }
+
+    fn header<'a>(message: &'a Value, name: &str) -> &'a Value {
+        let headers = message["headers"].as_array().unwrap();
+        let header = headers.iter().find(|header| header["name"] == name);
+        &header.unwrap_or_else(|| panic!("missing {name}"))["value"]
+    }
+
+    #[test]
+    fn entries_are_har_1_2() {
+        let entry = Entry::new(
+            SystemTime::UNIX_EPOCH,
+            Duration::from_millis(250),
+            HarRequest::new(&request()),
+            response(),
+        );
+        let (har, _) = captured("valid", entry);
+
+        let log = &har["log"];
+        assert_eq!(log["version"], "1.2");
+        assert_eq!(log["creator"]["name"], env!("CARGO_PKG_NAME"));
+        assert_eq!(log["creator"]["version"], env!("CARGO_PKG_VERSION"));
+
+        // Every field HAR 1.2 requires must be present, and of the correct type.
+        let entry = &log["entries"][0];
+        assert_eq!(entry["startedDateTime"], "1970-01-01T00:00:00Z");
+        assert_eq!(entry["time"], 250.0);
+        assert!(entry["cache"].is_object());
+        for timing in ["send", "wait", "receive"] {
+            assert!(entry["timings"][timing].is_number(), "{timing}");
+        }
+
+        let request = &entry["request"];
+        assert_eq!(request["method"], "POST");
+        assert_eq!(request["httpVersion"], "HTTP/1.1");
+        for list in ["cookies", "headers", "queryString"] {
+            assert!(request[list].is_array(), "{list}");
+        }
+        assert_eq!(request["headersSize"], -1);
+        assert!(request["bodySize"].as_i64().unwrap() > 0);
+        assert_eq!(request["postData"]["mimeType"], "application/json");
+        assert_eq!(
+            request["queryString"][0],
+            json!({"name": "grant_type", "value": "refresh_token"})
+        );
+
+        let response = &entry["response"];
+        assert_eq!(response["status"], 200);
+        assert_eq!(response["statusText"], "OK");
+        assert_eq!(response["httpVersion"], "HTTP/1.1");
+        assert!(response["cookies"].is_array());
+        assert!(response["headers"].is_array());
+        assert_eq!(response["content"]["mimeType"], "application/json");
+        assert!(response["content"]["size"].as_i64().unwrap() > 0);
+        assert_eq!(response["redirectURL"], "");
+        assert_eq!(response["headersSize"], -1);
+        assert!(response.get("_error").is_none());
+    }
+
+    #[test]
+    fn secrets_are_redacted() {
+        let entry = Entry::new(
+            SystemTime::now(),
+            Duration::ZERO,
+            HarRequest::new(&request()),
+            response(),
+        );
+        let (har, contents) = captured("redacted", entry);
+        for secret in [
+            JWT,
+            "session-token",
+            "rotated-session",
+            "gateway-key",
+            "opaque-refresh",
+            "hunter22",
+        ] {
+            assert!(!contents.contains(secret), "{secret} was captured");
+        }
+
+        let entry = &har["log"]["entries"][0];
+        let request = &entry["request"];
+        assert_eq!(header(request, "authorization"), redact::REDACTED);
+        assert_eq!(header(request, "cookie"), redact::REDACTED);
+        assert_eq!(header(request, "x-api-key"), redact::REDACTED);
+        assert_eq!(
+            request["queryString"][1],
+            json!({"name": "refresh_token", "value": redact::REDACTED})
+        );
+        // Anything that isn't secret remains legible.
+        assert!(request["postData"]["text"]
+            .as_str()
+            .unwrap()
+            .contains("Tacoma"));
+        assert_eq!(header(&entry["response"], "set-cookie"), redact::REDACTED);
+    }
+
+    #[tokio::test]
+    async fn failed_requests_are_captured() {
+        // Nothing listens on the discard port, so our connection is refused.
+        let url = format!("http://127.0.0.1:9/?access_token={JWT}");
+        let error = reqwest::Client::new().get(&url).send().await.unwrap_err();
+        let request = reqwest::Client::new().get(&url).build().unwrap();
+        let entry = Entry::new(
+            SystemTime::now(),
+            Duration::ZERO,
+            HarRequest::new(&request),
+            HarResponse::failed(&error),
+        );
+        let (har, contents) = captured("failed", entry);
+        assert!(!contents.contains(JWT));
+
+        let response = &har["log"]["entries"][0]["response"];
+        assert_eq!(response["status"], 0);
+        assert_eq!(response["bodySize"], -1);
+        assert_eq!(response["content"]["size"], 0);
+        let error = response["_error"].as_str().unwrap();
+        assert!(!error.is_empty());
+    }
+}
@@ -0,0 +1,39 @@
+//! `audit`, querying the log of remote commands issued from this machine.
+
+use toyotactl::audit::{AuditEntry, AuditLog};
+
+use super::{exit_with_error, print, Context};
+use crate::AuditQuery;
+
+/// Prints the entries within the audit log matching the given query.
+pub fn run(query: AuditQuery, context: &Context) {
+    let Some(path) = context.audit_path() else {
+        exit_with_error("Unable to read audit log", "no data directory");
+    };
+    let entries = match AuditLog::entries(&path) {
+        Ok(entries) => entries,
+        Err(err) => exit_with_error("Unable to read audit log", err),
+    };
+    let vin = query
+        .vin
+        .and_then(|vin| context.profile.resolve_vin(Some(&vin)));
+    let since = query
+        .since
+        .and_then(|since| chrono::Duration::from_std(since).ok())
+        .map(|since| chrono::Utc::now() - since);
+
+    let mut entries: Vec<AuditEntry> = entries
+        .into_iter()
+        .filter(|entry| vin.as_ref().is_none_or(|vin| &entry.vin == vin))
+        .filter(|entry| {
+            query
+                .remote_command
+                .is_none_or(|command| entry.command == command)
+        })
+        .filter(|entry| since.is_none_or(|since| entry.timestamp >= since))
+        .collect();
+    if let Some(limit) = query.limit {
+        entries.drain(..entries.len().saturating_sub(limit));
+    }
+    print(&entries, context);
+}
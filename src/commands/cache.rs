@@ -0,0 +1,50 @@
+//! Deciding whether a cached response is fresh enough to answer with.
+
+use std::time::Duration;
+use toyotactl::cache::CacheEntry;
+
+use super::{exit_with_error, Context};
+
+/// How fresh cached responses must be to be used.
+pub struct Freshness {
+    /// Overrides the default maximum age for every kind of response.
+    pub max_age: Option<Duration>,
+    /// Use whatever we have cached, regardless of age, and never fetch.
+    pub offline: bool,
+}
+
+impl Freshness {
+    pub fn new(context: &Context) -> Self {
+        Self {
+            max_age: context.cli.max_age,
+            offline: context.cli.offline,
+        }
+    }
+
+    /// The value of the given cache entry, should it be fresh enough.
+    ///
+    /// When offline, we'll use the entry regardless of age, and exit if there's none.
+    pub fn cached<T>(
+        &self,
+        entry: Option<CacheEntry<T>>,
+        default_max_age: Duration,
+        what: &str,
+    ) -> Option<T> {
+        let max_age = self.max_age.unwrap_or(default_max_age);
+        match entry {
+            Some(entry) if entry.age() < max_age => {
+                tracing::info!(age = entry.age().as_secs(), "using cached {what}");
+                Some(entry.value)
+            }
+            Some(entry) if self.offline => {
+                tracing::warn!("cached {what} is {}s old", entry.age().as_secs());
+                Some(entry.value)
+            }
+            _ if self.offline => exit_with_error(
+                &format!("Unable to obtain {what}"),
+                "nothing is cached, so try again without --offline",
+            ),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! `completions` and `man`, alongside the candidates we offer while completing.
+
+use clap::CommandFactory;
+use clap_complete::{env::Shells, CompletionCandidate};
+use std::{env, io, path::PathBuf};
+use toyotactl::{cache::Cache, config::Config};
+
+use super::exit_with_error;
+use crate::{Cli, Shell};
+
+/// Offers the aliases and VINs we know of while completing a vehicle.
+///
+/// We'll only look locally, at our configuration and the vehicles we last listed,
+/// as a network round trip would make completion unbearably slow.
+pub fn vehicle_candidates() -> Vec<CompletionCandidate> {
+    let config = env::var_os("TOYOTACTL_CONFIG")
+        .map(PathBuf::from)
+        .or_else(Config::default_path)
+        .and_then(|path| Config::load(&path).ok())
+        .unwrap_or_default();
+    let profile_name = config.profile_name(env::var("TOYOTACTL_PROFILE").ok().as_deref());
+
+    let mut candidates: Vec<CompletionCandidate> = config
+        .profile(&profile_name)
+        .aliases
+        .into_iter()
+        .map(|(alias, vin)| CompletionCandidate::new(alias).help(Some(vin.into())))
+        .collect();
+    let vehicles = Cache::for_profile(&profile_name)
+        .and_then(|cache| cache.vehicles())
+        .unwrap_or_default();
+    candidates.extend(vehicles.into_iter().map(|vehicle| {
+        let description = vehicle.nick_name.or(vehicle.model_name);
+        CompletionCandidate::new(vehicle.vin).help(description.map(Into::into))
+    }));
+    candidates
+}
+
+/// Prints a script registering our completions with the given shell.
+///
+/// The script calls back into us while completing, so that vehicles can be offered.
+pub fn run(shell: Shell) {
+    // Much like clap_complete itself, we'll refer to ourselves by our full path if possible.
+    let bin = env::current_exe()
+        .ok()
+        .and_then(|path| path.to_str().map(str::to_string))
+        .unwrap_or_else(|| "toyotactl".to_string());
+    if let Err(err) = write(shell, &bin, &mut io::stdout()) {
+        exit_with_error("Unable to write completions", err);
+    }
+}
+
+/// Writes the script registering the given binary's completions with the given shell.
+fn write(shell: Shell, bin: &str, buf: &mut dyn io::Write) -> io::Result<()> {
+    let name = match shell {
+        Shell::Bash => "bash",
+        Shell::Zsh => "zsh",
+        Shell::Fish => "fish",
+    };
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(name)
+        .expect("builtin shells should include bash, zsh and fish");
+    completer.write_registration("COMPLETE", "toyotactl", "toyotactl", bin, buf)
+}
+
+/// Prints our man page.
+pub fn man() {
+    let man = clap_mangen::Man::new(Cli::command());
+    if let Err(err) = man.render(&mut std::io::stdout()) {
+        exit_with_error("Unable to write man page", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ValueEnum;
+
+    #[test]
+    fn scripts_call_back_into_us() {
+        for shell in Shell::value_variants() {
+            let mut script = Vec::new();
+            write(*shell, "/usr/bin/toyotactl", &mut script).unwrap();
+            let script = String::from_utf8(script).unwrap();
+            assert!(!script.trim().is_empty());
+            assert!(script.contains("/usr/bin/toyotactl"), "{script}");
+            assert!(script.contains("COMPLETE"), "{script}");
+        }
+    }
+
+    #[test]
+    fn subcommands_are_offered() {
+        // This is what each script asks of us when completing `toyotactl <TAB>`.
+        let args = vec!["toyotactl".into(), "".into()];
+        let candidates = clap_complete::engine::complete(&mut Cli::command(), args, 1, None)
+            .unwrap()
+            .into_iter()
+            .map(|candidate| candidate.get_value().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        for subcommand in Cli::command().get_subcommands() {
+            if subcommand.is_hide_set() {
+                continue;
+            }
+            let name = subcommand.get_name();
+            assert!(
+                candidates.iter().any(|candidate| candidate == name),
+                "{name}"
+            );
+        }
+        for name in ["vehicles", "status", "lock", "completions"] {
+            assert!(
+                candidates.iter().any(|candidate| candidate == name),
+                "{name}"
+            );
+        }
+    }
+}
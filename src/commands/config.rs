@@ -0,0 +1,50 @@
+//! `config`, which operates on the configuration file as-is, even if it's invalid.
+
+use std::path::{Path, PathBuf};
+use toyotactl::config::ConfigFile;
+
+use super::exit_with_error;
+use crate::ConfigCommand;
+
+/// Handles `config` subcommands.
+pub fn run(command: ConfigCommand, path: Option<PathBuf>) {
+    let Some(path) = path else {
+        exit_with_error("Unable to locate config", "no config directory available");
+    };
+    match command {
+        ConfigCommand::Path => println!("{}", path.display()),
+        ConfigCommand::Get { key } => match open(&path).get(&key) {
+            // Strings are printed without quotes, so that scripts needn't strip them.
+            Some(toml::Value::String(value)) => println!("{value}"),
+            Some(toml::Value::Table(table)) => print!("{table}"),
+            Some(value) => println!("{value}"),
+            None => exit_with_error(&format!("Unable to get {key}"), "not set"),
+        },
+        ConfigCommand::Set { key, value } => {
+            let mut file = open(&path);
+            if let Err(err) = file.set(&key, &value).and_then(|_| file.save()) {
+                exit_with_error(&format!("Unable to set {key}"), err);
+            }
+        }
+        ConfigCommand::Unset { key } => {
+            let mut file = open(&path);
+            match file.unset(&key) {
+                Ok(true) => {
+                    if let Err(err) = file.save() {
+                        exit_with_error(&format!("Unable to unset {key}"), err);
+                    }
+                }
+                Ok(false) => exit_with_error(&format!("Unable to unset {key}"), "not set"),
+                Err(err) => exit_with_error(&format!("Unable to unset {key}"), err),
+            }
+        }
+    }
+}
+
+/// Opens the configuration file at the given path, exiting if we're unable to.
+fn open(path: &Path) -> ConfigFile {
+    match ConfigFile::open(path) {
+        Ok(file) => file,
+        Err(err) => exit_with_error("Unable to open config", err),
+    }
+}
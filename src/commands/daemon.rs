@@ -0,0 +1,98 @@
+//! `daemon`, and how other invocations find it.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::UnixListener,
+    signal::unix::{signal, SignalKind},
+};
+use toyotactl::{
+    api::SharedClient,
+    daemon::{self, Daemon, DaemonClient},
+};
+
+use super::{exit_with_error, Context};
+
+/// Where the daemon for our profile listens.
+fn socket_path(context: &Context) -> Option<PathBuf> {
+    context
+        .cli
+        .daemon_socket
+        .clone()
+        .or_else(|| daemon::socket_path(&context.profile_name))
+}
+
+/// Connects to the daemon for our profile, should one be running.
+///
+/// A running daemon answers on our behalf, unless we were asked for anything it wouldn't do.
+pub async fn connect(context: &Context) -> Option<DaemonClient> {
+    let cli = &context.cli;
+    let bypass = cli.no_daemon
+        || cli.dry_run
+        || cli.verify_tokens
+        || cli.capture.is_some()
+        || cli.record.is_some()
+        || cli.replay.is_some();
+    if bypass {
+        return None;
+    }
+
+    let socket = socket_path(context)?;
+    match DaemonClient::connect(&socket).await {
+        Ok(daemon) => {
+            tracing::info!(socket = %socket.display(), "using daemon");
+            Some(daemon)
+        }
+        Err(err) => {
+            tracing::debug!(socket = %socket.display(), "not using daemon: {err}");
+            None
+        }
+    }
+}
+
+/// Starts the daemon, running it until we're interrupted or terminated.
+pub async fn run(poll_interval: Duration, context: Context) {
+    let Some(socket) = socket_path(&context) else {
+        exit_with_error("Unable to start daemon", "no runtime or cache directory");
+    };
+    let listener = match Daemon::bind(&socket).await {
+        Ok(listener) => listener,
+        Err(err) => exit_with_error("Unable to start daemon", err),
+    };
+    let audit = context.open_audit_log();
+    // We'll log in now, while there's still a terminal to prompt upon.
+    let client = context.api_client().await;
+    let daemon = Daemon::new(
+        &context.profile_name,
+        SharedClient::new(client),
+        context.cache(),
+        audit,
+        context.profile.policy.clone().unwrap_or_default(),
+    );
+    serve(daemon, listener, &socket, poll_interval).await;
+}
+
+/// Serves the given daemon until we're interrupted or terminated, removing its socket after.
+async fn serve(daemon: Daemon, listener: UnixListener, socket: &Path, poll_interval: Duration) {
+    let daemon = Arc::new(daemon);
+    if !poll_interval.is_zero() {
+        tokio::spawn(daemon.clone().poll(poll_interval));
+    }
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(err) => exit_with_error("Unable to start daemon", err),
+    };
+    eprintln!("Listening on {}", socket.display());
+    tokio::select! {
+        _ = daemon.serve(listener) => {}
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+
+    if let Err(err) = std::fs::remove_file(socket) {
+        tracing::warn!(socket = %socket.display(), "unable to remove socket: {err}");
+    }
+}
@@ -0,0 +1,19 @@
+//! `--dry-run`, printing the request a remote command would send.
+
+use toyotactl::api::RemoteCommand;
+
+use super::{exit_with_error, print, remote, Context};
+
+/// Prints the request the given remote command would send, without sending it.
+///
+/// We'll first ensure the vehicle belongs to this account and supports the command,
+/// as the API would.
+pub async fn run(command: RemoteCommand, vin: &str, context: &Context) {
+    let client = context.api_client().await;
+    remote::ensure_eligible(&client, command, vin, context).await;
+
+    match client.prepare_remote_command(vin, command).await {
+        Ok(request) => print(&request, context),
+        Err(err) => exit_with_error(&format!("Unable to prepare {command}"), err),
+    }
+}
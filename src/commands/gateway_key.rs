@@ -0,0 +1,24 @@
+//! `gateway-key`, managing the API gateway key.
+
+use toyotactl::api;
+
+use super::{exit_with_error, Context};
+use crate::GatewayKeyCommand;
+
+/// Handles `gateway-key` subcommands.
+pub async fn run(command: GatewayKeyCommand, context: &Context) {
+    match command {
+        GatewayKeyCommand::Refresh => {
+            let http = context.http();
+            let environment = context.environment();
+            let key_config = context.key_config();
+            if let Err(err) =
+                api::refresh_gateway_key(&key_config, &http, &environment, context.store.as_ref())
+                    .await
+            {
+                exit_with_error("Unable to refresh API gateway key", err);
+            }
+            println!("Refreshed API gateway key");
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! Handlers for each of our subcommands, alongside what they share.
+//!
+//! Every handler reports failure by exiting with a message, as there's
+//! nobody left to return an error to.
+
+pub mod audit;
+pub mod cache;
+pub mod completions;
+pub mod config;
+#[cfg(unix)]
+pub mod daemon;
+pub mod dry_run;
+pub mod gateway_key;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod remote;
+pub mod session;
+pub mod vehicles;
+pub mod whoami;
+
+use std::{fmt, path::PathBuf, process, sync::Arc, time::Duration};
+use toyotactl::{
+    api::{self, ApiClient, GatewayKeyConfig, Units},
+    audit::AuditLog,
+    cache::Cache,
+    config::{Config, CredentialBackend, ProfileConfig},
+    environment::Environment,
+    fixture::{Recorder, Replayer},
+    forgerock::{self, CredentialStorage, JwksCache, TerminalPrompt},
+    http::{HttpClient, HttpConfig, RetryPolicy},
+    output::{self, Tabular},
+    storage::{self, CredentialStore, FileStore, KeyringStore, MemoryStore},
+};
+
+use crate::Cli;
+
+/// Prints the given error alongside its context, and exits.
+pub fn exit_with_error(context: &str, err: impl fmt::Display) -> ! {
+    eprintln!("{context}: {err}");
+    process::exit(1);
+}
+
+/// Prints the given result in the requested format.
+pub fn print<T: Tabular>(value: &T, context: &Context) {
+    match output::render(value, &context.cli.output) {
+        Ok(rendered) => println!("{rendered}"),
+        Err(err) => exit_with_error("Unable to print result", err),
+    }
+}
+
+/// Everything a handler may need: our flags, and the profile they select.
+///
+/// Anything touching the network is only created once asked for,
+/// so that local commands never need it.
+pub struct Context {
+    pub cli: Cli,
+    pub profile_name: String,
+    pub profile: ProfileConfig,
+    pub store: Arc<dyn CredentialStore>,
+    /// Recording and replaying never touch our stored session or cache,
+    /// as a replayed login would otherwise save scrubbed tokens over our own.
+    isolated: bool,
+}
+
+impl Context {
+    /// Loads our configuration, and the profile and credential store it selects.
+    pub fn load(cli: Cli, config_path: Option<PathBuf>) -> Self {
+        let config = match &config_path {
+            Some(path) => match Config::load(path) {
+                Ok(config) => config,
+                Err(err) => exit_with_error("Unable to load config", err),
+            },
+            None => Config::default(),
+        };
+
+        // Flags take precedence over our configuration, which takes precedence over our defaults.
+        let profile_name = config.profile_name(cli.profile.as_deref());
+        if !storage::is_valid_profile(&profile_name) {
+            exit_with_error(
+                &format!("Invalid profile {profile_name:?}"),
+                "only letters, digits, - and _ are allowed",
+            );
+        }
+        let profile = config.profile(&profile_name);
+        let isolated = cli.record.is_some() || cli.replay.is_some();
+        let store = if isolated {
+            Arc::new(MemoryStore::new())
+        } else {
+            credential_store(
+                profile.credential_backend.unwrap_or_default(),
+                &profile_name,
+            )
+        };
+        Self {
+            cli,
+            profile_name,
+            profile,
+            store,
+            isolated,
+        }
+    }
+
+    /// The cache for this profile, unless we're recording or replaying.
+    pub fn cache(&self) -> Option<Cache> {
+        if self.isolated {
+            None
+        } else {
+            Cache::for_profile(&self.profile_name)
+        }
+    }
+
+    pub fn units(&self) -> Units {
+        self.cli.units.or(self.profile.units).unwrap_or_default()
+    }
+
+    /// Resolves the vehicle a command should act upon, exiting if there is none.
+    pub fn resolve_vin(&self, vin: Option<String>) -> String {
+        match self
+            .profile
+            .resolve_vin(vin.as_deref().or(self.cli.vehicle.as_deref()))
+        {
+            Some(vin) => vin,
+            None => exit_with_error(
+                "No vehicle specified",
+                "pass a VIN or alias, or set default_vin within your config",
+            ),
+        }
+    }
+
+    pub fn audit_path(&self) -> Option<PathBuf> {
+        self.cli.audit_log.clone().or_else(AuditLog::default_path)
+    }
+
+    /// Opens the audit log for appending, exiting if we're unable to.
+    pub fn open_audit_log(&self) -> AuditLog {
+        match self.audit_path().as_deref().map(AuditLog::open) {
+            Some(Ok(audit)) => audit,
+            Some(Err(err)) => exit_with_error("Unable to open audit log", err),
+            None => exit_with_error("Unable to open audit log", "no data directory"),
+        }
+    }
+
+    /// Creates the HTTP client every request shares, and as such a single connection pool.
+    pub fn http(&self) -> HttpClient {
+        let cli = &self.cli;
+        let http_config = HttpConfig {
+            user_agent: cli.user_agent.clone(),
+            connect_timeout: Duration::from_secs(cli.connect_timeout),
+            request_timeout: Duration::from_secs(cli.timeout),
+            proxy: cli.proxy.clone(),
+            capture: cli.capture.clone(),
+            retry: {
+                let retry = self.profile.retry.clone().unwrap_or_default();
+                let policy = retry.apply(RetryPolicy::default());
+                RetryPolicy {
+                    max_retries: cli.retries.unwrap_or(policy.max_retries),
+                    ..policy
+                }
+            },
+            ..HttpConfig::default()
+        };
+        let mut http = match HttpClient::new(&http_config) {
+            Ok(http) => http,
+            Err(err) => exit_with_error("Unable to create HTTP client", err),
+        };
+        if let Some(path) = &cli.record {
+            http = http.with_recorder(Recorder::new(path));
+        }
+        if let Some(path) = &cli.replay {
+            match Replayer::load(path) {
+                Ok(replayer) => http = http.with_replayer(replayer),
+                Err(err) => exit_with_error("Unable to load fixture", err),
+            }
+        }
+        http
+    }
+
+    /// The services we target: our region's production services, unless told otherwise.
+    pub fn environment(&self) -> Environment {
+        let cli = &self.cli;
+        let region = cli.region.or(self.profile.region).unwrap_or_default();
+        let mut environment = region.environment();
+        if let Some(locale) = &self.profile.locale {
+            environment.locale = locale.clone();
+        }
+        if let Some(endpoints) = &self.profile.endpoints {
+            endpoints.apply(&mut environment);
+        }
+        // Flags take precedence over our configuration.
+        if let Some(forgerock_url) = cli.forgerock_url.clone() {
+            environment.forgerock_base = forgerock_url;
+        }
+        if let Some(realm) = cli.realm.clone() {
+            environment.realm = realm;
+        }
+        if let Some(api_url) = cli.api_url.clone() {
+            environment.api_gateway_base = api_url;
+        }
+        if let Some(package_url) = cli.package_url.clone() {
+            environment.package_source = package_url;
+        }
+        environment
+    }
+
+    pub fn key_config(&self) -> GatewayKeyConfig {
+        GatewayKeyConfig {
+            key: self.cli.gateway_key.clone(),
+            sdist_path: self.cli.gateway_key_sdist.clone(),
+            sdist_sha256: self.cli.gateway_key_sdist_sha256.clone(),
+        }
+    }
+
+    /// Obtains the API gateway key and logs in, creating an API client.
+    pub async fn api_client(&self) -> ApiClient {
+        let http = self.http();
+        let environment = self.environment();
+        let key_config = self.key_config();
+        let store = self.store.clone();
+
+        // Before anything else, let's ensure we have the API key available.
+        if let Err(err) =
+            api::ensure_gateway_key(&key_config, &http, &environment, store.as_ref()).await
+        {
+            exit_with_error("Unable to obtain API gateway key", err);
+        }
+
+        // We can finally initialize our API client!
+        let client =
+            match forgerock::login(&http, &environment, store.clone(), &TerminalPrompt).await {
+                Ok(client) => client.with_gateway_key_config(key_config),
+                Err(err) => exit_with_error("Unable to log in", err),
+            };
+
+        if self.cli.verify_tokens {
+            verify_access_token(&http, &environment, store.as_ref()).await;
+        }
+        client
+    }
+}
+
+/// Creates the credential store configured for the given profile.
+fn credential_store(backend: CredentialBackend, profile: &str) -> Arc<dyn CredentialStore> {
+    match backend {
+        CredentialBackend::Keyring => Arc::new(KeyringStore::for_profile(profile)),
+        CredentialBackend::File => match FileStore::default_path() {
+            Some(path) => Arc::new(FileStore::new(&path, profile)),
+            None => exit_with_error(
+                "Unable to locate credential file",
+                "no data directory available",
+            ),
+        },
+        CredentialBackend::Memory => Arc::new(MemoryStore::new()),
+    }
+}
+
+/// Ensures our stored access token was signed by the realm, exiting otherwise.
+async fn verify_access_token(
+    http: &HttpClient,
+    environment: &Environment,
+    store: &dyn CredentialStore,
+) {
+    let credentials = match CredentialStorage::load(store) {
+        Ok(Some(credentials)) => credentials,
+        Ok(None) => exit_with_error("Unable to verify access token", "no credentials stored"),
+        Err(err) => exit_with_error("Unable to verify access token", err),
+    };
+    let Some(cache) = JwksCache::for_environment(environment) else {
+        exit_with_error(
+            "Unable to verify access token",
+            "no cache directory available",
+        );
+    };
+    if let Err(err) = cache
+        .verify(http, environment, &credentials.access_token)
+        .await
+    {
+        exit_with_error("Unable to verify access token", err);
+    }
+}
@@ -0,0 +1,57 @@
+//! `mqtt`, bridging vehicles to an MQTT broker.
+
+use std::sync::Arc;
+use toyotactl::{
+    api::SharedClient,
+    mqtt::{Bridge, BridgeConfig, Topics},
+    redact::{self, Secret},
+};
+
+use super::{exit_with_error, Context};
+use crate::MqttArgs;
+
+/// Starts the bridge, running it until interrupted.
+pub async fn run(args: MqttArgs, context: Context) {
+    // Anything published to us is issued for real.
+    if context.cli.dry_run {
+        exit_with_error(
+            "Unable to perform a dry run",
+            "only remote commands can be dry run",
+        );
+    }
+    if let Some(password) = &args.password {
+        redact::register_secret(Secret::BrokerPassword, password);
+    }
+    let config = BridgeConfig {
+        broker: args.broker,
+        username: args.username,
+        password: args.password,
+        client_id: format!("toyotactl-{}", context.profile_name),
+        topics: Topics::new(&args.topic_prefix),
+        discovery_prefix: (!args.no_discovery).then_some(args.discovery_prefix),
+        poll_interval: args.poll_interval,
+        units: context.units(),
+        allowed: args.allowed,
+    };
+    let audit = context.open_audit_log();
+    let client = context.api_client().await;
+    let bridge = Bridge::new(
+        &context.profile_name,
+        SharedClient::new(client),
+        context.cache(),
+        audit,
+        context.profile.policy.clone().unwrap_or_default(),
+        config,
+    );
+    let (bridge, eventloop) = match bridge {
+        Ok(bridge) => bridge,
+        Err(err) => exit_with_error("Unable to start MQTT bridge", err),
+    };
+
+    // Our broker publishes our will, marking us offline, once we've gone.
+    eprintln!("Bridging vehicles to MQTT");
+    tokio::select! {
+        _ = Arc::new(bridge).run(eventloop) => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
@@ -0,0 +1,139 @@
+//! Remote commands, such as `lock` or `start`, alongside our safety checks.
+
+use std::io::IsTerminal;
+use toyotactl::{
+    api::{ApiClient, RemoteCommand},
+    cache::{Cache, CAPABILITIES_MAX_AGE, VEHICLES_MAX_AGE},
+    safety::{self, Origin, Policy, Refusal},
+};
+
+#[cfg(unix)]
+use super::daemon;
+use super::{cache::Freshness, dry_run, exit_with_error, print, Context};
+use crate::Target;
+
+/// Issues the given remote command, recording it within the audit log and printing its result.
+///
+/// Remote commands must pass our safety checks before we so much as log in.
+pub async fn run(command: RemoteCommand, target: Target, context: &Context) {
+    let cli = &context.cli;
+    let vin = context.resolve_vin(target.vin);
+    let policy = context.profile.policy.clone().unwrap_or_default();
+    authorize(&policy, command, &vin, cli.yes, cli.dry_run);
+    if cli.dry_run {
+        return dry_run::run(command, &vin, context).await;
+    }
+
+    // The daemon applies its policy and records the command itself. As it treats everyone
+    // as automation, we'll issue anything reserved for a person ourselves.
+    #[cfg(unix)]
+    let daemon = if policy.interactive_only.contains(&command) {
+        None
+    } else {
+        daemon::connect(context).await
+    };
+    #[cfg(unix)]
+    if let Some(mut daemon) = daemon {
+        match daemon.remote_command(&vin, command, true).await {
+            Ok(result) => print(&result, context),
+            Err(err) => exit_with_error(&format!("Unable to issue {command}"), err),
+        }
+        return;
+    }
+
+    // We'll never issue a command we're unable to record.
+    let audit = context.open_audit_log();
+    let client = context.api_client().await;
+    ensure_eligible(&client, command, &vin, context).await;
+    match audit
+        .issue(&client, &context.profile_name, &vin, command)
+        .await
+    {
+        Ok(result) => {
+            // Whatever status we had cached is now out of date.
+            if let Some(cache) = context.cache() {
+                cache.invalidate(&vin);
+            }
+            print(&result, context)
+        }
+        Err(err) => exit_with_error(&format!("Unable to issue {command}"), err),
+    }
+}
+
+/// Ensures the vehicle belongs to this account and supports the given command, exiting if not.
+///
+/// The vehicle list may come from our cache, as may its capabilities should the list lack them.
+pub async fn ensure_eligible(
+    client: &ApiClient,
+    command: RemoteCommand,
+    vin: &str,
+    context: &Context,
+) {
+    let cache = context.cache();
+    let cache = cache.as_ref();
+    let freshness = Freshness::new(context);
+
+    let entry = cache.and_then(Cache::vehicles_entry);
+    let vehicles = match freshness.cached(entry, VEHICLES_MAX_AGE, "vehicles") {
+        Some(vehicles) => vehicles,
+        None => match client.vehicles().await {
+            Ok(vehicles) => {
+                if let Some(cache) = cache {
+                    cache.store_vehicles(&vehicles);
+                }
+                vehicles
+            }
+            Err(err) => exit_with_error("Unable to list vehicles", err),
+        },
+    };
+
+    // Cached capabilities outlive the vehicle list.
+    let cached = || {
+        let entry = cache.and_then(|cache| cache.capabilities(vin))?;
+        freshness.cached(Some(entry), CAPABILITIES_MAX_AGE, "capabilities")
+    };
+    if let Err(refusal) = safety::check_eligible(&vehicles, vin, command, cached) {
+        exit_with_error(&format!("Refusing to issue {command} to {vin}"), refusal);
+    }
+}
+
+/// Ensures the given command may be issued, asking for confirmation if necessary.
+///
+/// Without a terminal, we'll treat ourselves as automation: confirmation must be given via
+/// `--yes`, and anything the policy reserves for a person is refused outright.
+/// During a dry run, we'll only note that confirmation would be required.
+fn authorize(policy: &Policy, command: RemoteCommand, vin: &str, assume_yes: bool, dry_run: bool) {
+    let interactive = std::io::stdin().is_terminal() && std::io::stderr().is_terminal();
+    let origin = if interactive {
+        Origin::Interactive
+    } else {
+        Origin::Automation
+    };
+    let refuse = |refusal: Refusal| -> ! {
+        exit_with_error(&format!("Refusing to issue {command} to {vin}"), refusal)
+    };
+
+    if let Err(refusal) = policy.check(command, origin) {
+        refuse(refusal);
+    }
+    if !policy.needs_confirmation(command) || assume_yes {
+        return;
+    }
+    if dry_run {
+        eprintln!("Issuing {command} to {vin} would require confirmation.");
+        return;
+    }
+    if !interactive {
+        eprintln!("Pass --yes to confirm non-interactively.");
+        refuse(Refusal::Unconfirmed(command));
+    }
+
+    eprint!("Issue {command} to {vin}? [y/N] ");
+    let mut answer = String::new();
+    if let Err(err) = std::io::stdin().read_line(&mut answer) {
+        exit_with_error("Unable to read confirmation", err);
+    }
+    if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+        refuse(Refusal::Declined(command));
+    }
+}
@@ -0,0 +1,51 @@
+//! `session`, moving the stored session between machines.
+
+use std::{env, process};
+use toyotactl::session;
+
+use super::{exit_with_error, Context};
+use crate::SessionCommand;
+
+/// Handles `session` subcommands.
+pub fn run(command: SessionCommand, context: &Context) {
+    let store = context.store.as_ref();
+    match command {
+        SessionCommand::Export { path } => {
+            let passphrase = passphrase(true);
+            if let Err(err) = session::export(store, &path, &passphrase) {
+                exit_with_error("Unable to export session", err);
+            }
+            println!("Exported session to {}", path.display());
+        }
+        SessionCommand::Import { path } => {
+            let passphrase = passphrase(false);
+            if let Err(err) = session::import(store, &path, &passphrase) {
+                exit_with_error("Unable to import session", err);
+            }
+            println!("Imported session from {}", path.display());
+        }
+    }
+}
+
+/// Reads the session bundle passphrase, either from the environment or interactively.
+fn passphrase(confirm: bool) -> String {
+    if let Ok(passphrase) = env::var("TOYOTACTL_SESSION_PASSPHRASE") {
+        return passphrase;
+    }
+
+    let passphrase = match rpassword::prompt_password("Session bundle passphrase: ") {
+        Ok(passphrase) => passphrase,
+        Err(err) => exit_with_error("Unable to read passphrase", err),
+    };
+    if confirm {
+        let confirmation = match rpassword::prompt_password("Confirm passphrase: ") {
+            Ok(confirmation) => confirmation,
+            Err(err) => exit_with_error("Unable to read passphrase", err),
+        };
+        if passphrase != confirmation {
+            eprintln!("Passphrases do not match.");
+            process::exit(1);
+        }
+    }
+    passphrase
+}
@@ -0,0 +1,99 @@
+//! `vehicles`, `status` and `telemetry`, answered from our cache where possible.
+
+use toyotactl::cache::{Cache, SNAPSHOT_MAX_AGE, VEHICLES_MAX_AGE};
+
+#[cfg(unix)]
+use super::daemon;
+use super::{cache::Freshness, exit_with_error, print, Context};
+use crate::Target;
+
+/// Lists all vehicles associated with the account.
+pub async fn list(context: &Context) {
+    let cache = context.cache();
+    let entry = cache.as_ref().and_then(Cache::vehicles_entry);
+    if let Some(vehicles) = Freshness::new(context).cached(entry, VEHICLES_MAX_AGE, "vehicles") {
+        return print(&vehicles, context);
+    }
+
+    // The daemon caches whatever it fetches on our behalf.
+    #[cfg(unix)]
+    if let Some(mut daemon) = daemon::connect(context).await {
+        match daemon.vehicles().await {
+            Ok(vehicles) => print(&vehicles, context),
+            Err(err) => exit_with_error("Unable to list vehicles", err),
+        }
+        return;
+    }
+
+    let client = context.api_client().await;
+    match client.vehicles().await {
+        Ok(vehicles) => {
+            if let Some(cache) = &cache {
+                cache.store_vehicles(&vehicles);
+            }
+            print(&vehicles, context)
+        }
+        Err(err) => exit_with_error("Unable to list vehicles", err),
+    }
+}
+
+/// Shows the remote status of the given vehicle.
+pub async fn status(target: Target, context: &Context) {
+    let vin = context.resolve_vin(target.vin);
+    let cache = context.cache();
+    let entry = cache.as_ref().and_then(|cache| cache.status(&vin));
+    if let Some(status) = Freshness::new(context).cached(entry, SNAPSHOT_MAX_AGE, "status") {
+        return print(&status, context);
+    }
+
+    #[cfg(unix)]
+    if let Some(mut daemon) = daemon::connect(context).await {
+        match daemon.vehicle_status(&vin).await {
+            Ok(status) => print(&status, context),
+            Err(err) => exit_with_error("Unable to obtain status", err),
+        }
+        return;
+    }
+
+    let client = context.api_client().await;
+    match client.vehicle_status(&vin).await {
+        Ok(status) => {
+            if let Some(cache) = &cache {
+                cache.store_status(&vin, &status);
+            }
+            print(&status, context)
+        }
+        Err(err) => exit_with_error("Unable to obtain status", err),
+    }
+}
+
+/// Shows telemetry for the given vehicle, in the units asked for.
+pub async fn telemetry(target: Target, context: &Context) {
+    let vin = context.resolve_vin(target.vin);
+    let units = context.units();
+    let cache = context.cache();
+    let entry = cache.as_ref().and_then(|cache| cache.telemetry(&vin));
+    if let Some(telemetry) = Freshness::new(context).cached(entry, SNAPSHOT_MAX_AGE, "telemetry") {
+        return print(&telemetry.convert(units), context);
+    }
+
+    #[cfg(unix)]
+    if let Some(mut daemon) = daemon::connect(context).await {
+        match daemon.telemetry(&vin).await {
+            Ok(telemetry) => print(&telemetry.convert(units), context),
+            Err(err) => exit_with_error("Unable to obtain telemetry", err),
+        }
+        return;
+    }
+
+    let client = context.api_client().await;
+    match client.telemetry(&vin).await {
+        Ok(telemetry) => {
+            if let Some(cache) = &cache {
+                cache.store_telemetry(&vin, &telemetry);
+            }
+            print(&telemetry.convert(units), context)
+        }
+        Err(err) => exit_with_error("Unable to obtain telemetry", err),
+    }
+}
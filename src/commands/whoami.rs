@@ -0,0 +1,109 @@
+//! `whoami`, showing who we're logged in as.
+
+use serde::Serialize;
+use toyotactl::{
+    forgerock::{self, CredentialStorage, TerminalPrompt},
+    output::Tabular,
+};
+
+use super::{exit_with_error, print, Context};
+
+/// Who we're logged in as, alongside when our tokens expire.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Identity {
+    name: Option<String>,
+    email: Option<String>,
+    guid: String,
+    access_token_expiry: Option<String>,
+    refresh_token_expiry: Option<String>,
+    /// Sessions stored prior to our retaining ID tokens will lack one.
+    id_token_expiry: Option<String>,
+}
+
+impl Tabular for Identity {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "NAME",
+            "EMAIL",
+            "GUID",
+            "ACCESS EXPIRES",
+            "REFRESH EXPIRES",
+            "ID EXPIRES",
+        ]
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let value = |value: &Option<String>| value.clone().unwrap_or_default();
+        vec![vec![
+            value(&self.name),
+            value(&self.email),
+            self.guid.clone(),
+            value(&self.access_token_expiry),
+            value(&self.refresh_token_expiry),
+            value(&self.id_token_expiry),
+        ]]
+    }
+}
+
+/// Formats the expiry of the given token as RFC 3339, if it has one.
+fn token_expiry(token: &str) -> Option<String> {
+    let exp = forgerock::Jwt::decode(token).ok()?.claims.exp?;
+    time::OffsetDateTime::from_unix_timestamp(exp as i64)
+        .ok()?
+        .format(&time::format_description::well_known::Rfc3339)
+        .ok()
+}
+
+/// Prints who we're logged in as.
+///
+/// We'll prefer what the userinfo endpoint tells us, falling back to
+/// our ID token's claims should that fail. We'll only need ForgeRock,
+/// so there's no need for the gateway key.
+pub async fn run(context: &Context) {
+    let http = context.http();
+    let environment = context.environment();
+    let store = context.store.clone();
+
+    // Logging in ensures our tokens are refreshed if necessary.
+    let client = match forgerock::login(&http, &environment, store.clone(), &TerminalPrompt).await {
+        Ok(client) => client,
+        Err(err) => exit_with_error("Unable to log in", err),
+    };
+    let credentials = match CredentialStorage::load(store.as_ref()) {
+        Ok(Some(credentials)) => credentials,
+        Ok(None) => exit_with_error("Unable to read credentials", "no credentials stored"),
+        Err(err) => exit_with_error("Unable to read credentials", err),
+    };
+
+    let id_claims = credentials
+        .id_token
+        .as_deref()
+        .and_then(|token| forgerock::Jwt::decode(token).ok())
+        .map(|jwt| jwt.claims.extra)
+        .unwrap_or_default();
+    let id_claim = |name: &str| {
+        id_claims
+            .get(name)
+            .and_then(|value| value.as_str())
+            .map(str::to_string)
+    };
+    let (name, email) =
+        match forgerock::userinfo(&http, &environment, &credentials.access_token).await {
+            Ok(info) => (info.name, info.email),
+            Err(err) => {
+                tracing::warn!("unable to obtain userinfo, using ID token instead: {err}");
+                (id_claim("name"), id_claim("email"))
+            }
+        };
+
+    let identity = Identity {
+        name,
+        email,
+        guid: client.guid().to_string(),
+        access_token_expiry: token_expiry(&credentials.access_token),
+        refresh_token_expiry: token_expiry(&credentials.refresh_token),
+        id_token_expiry: credentials.id_token.as_deref().and_then(token_expiry),
+    };
+    print(&identity, context);
+}
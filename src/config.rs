@@ -0,0 +1,528 @@
+//! Our configuration file, holding defaults that would otherwise be passed as flags.
+//!
+//! It lives at `$XDG_CONFIG_HOME/toyotactl/config.toml` (or the platform's
+//! equivalent), and is entirely optional. For example:
+//! ```toml
+//! default_profile = "personal"
+//!
+//! [profiles.personal]
+//! region = "toyota-us"
+//! default_vin = "tacoma"
+//! units = "metric"
+//! credential_backend = "file"
+//!
+//! [profiles.personal.aliases]
+//! tacoma = "JTEXAMPLE00000001"
+//!
+//! [profiles.personal.retry]
+//! max_retries = 5
+//! ```
+//! Flags and environment variables always take precedence.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use url::Url;
+
+use crate::{
+    api::Units,
+    environment::{Environment, Region},
+    http::RetryPolicy,
+    safety::Policy,
+    storage::DEFAULT_PROFILE,
+};
+
+/// Possible errors while reading or modifying our configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+    /// We couldn't determine where our configuration should live.
+    NoConfigDir,
+    /// The given key is not something we can get or set.
+    InvalidKey(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "unable to access config: {err}"),
+            ConfigError::Parse(err) => write!(f, "unable to parse config: {err}"),
+            ConfigError::Serialize(err) => write!(f, "unable to serialize config: {err}"),
+            ConfigError::NoConfigDir => write!(f, "unable to determine config directory"),
+            ConfigError::InvalidKey(key) => write!(f, "invalid key: {key}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Where secrets should be persisted.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CredentialBackend {
+    /// The user's keyring, via ``storage::KeyringStore``.
+    #[default]
+    Keyring,
+    /// A plain JSON file, via ``storage::FileStore``.
+    File,
+    /// Nowhere at all, requiring login on every invocation.
+    Memory,
+}
+
+/// The contents of our configuration file.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The profile used when `--profile` is not specified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+}
+
+/// Settings for a single profile.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+    /// The VIN (or alias) used when a command is given none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_vin: Option<String>,
+    /// Friendlier names for vehicles, mapped to their VIN.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub units: Option<Units>,
+    /// The UI locale presented while authenticating, such as `en-US`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential_backend: Option<CredentialBackend>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoints: Option<EndpointConfig>,
+    /// Restrictions upon remote commands, per ``safety::Policy``.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<Policy>,
+}
+
+/// Overrides for our retry policy. Anything unspecified keeps its default.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RetryConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial_backoff_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_backoff_ms: Option<u64>,
+}
+
+impl RetryConfig {
+    /// The given policy, with our overrides applied.
+    pub fn apply(&self, policy: RetryPolicy) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: self.max_retries.unwrap_or(policy.max_retries),
+            initial_backoff: self
+                .initial_backoff_ms
+                .map(Duration::from_millis)
+                .unwrap_or(policy.initial_backoff),
+            max_backoff: self
+                .max_backoff_ms
+                .map(Duration::from_millis)
+                .unwrap_or(policy.max_backoff),
+        }
+    }
+}
+
+/// Overrides for the hosts we communicate with, as with `--forgerock-url` and friends.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct EndpointConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forgerock_url: Option<Url>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub realm: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_url: Option<Url>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package_url: Option<Url>,
+    /// The OAuth2 client ID, should an account's differ from its region's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oauth_client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oauth_redirect_uri: Option<String>,
+    /// The authentication tree we log in via, such as `OneAppSignIn`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_index_value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_id: Option<String>,
+    /// The API path reporting the progress of accepted remote commands, such as for a stand-in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command_status_path: Option<String>,
+}
+
+impl EndpointConfig {
+    /// Applies our overrides to the given environment.
+    ///
+    /// The redirect URI is derived from the app identifier, as with each region,
+    /// unless it is overridden as well.
+    pub fn apply(&self, environment: &mut Environment) {
+        if let Some(forgerock_url) = &self.forgerock_url {
+            environment.forgerock_base = forgerock_url.clone();
+        }
+        if let Some(realm) = &self.realm {
+            environment.realm = realm.clone();
+        }
+        if let Some(api_url) = &self.api_url {
+            environment.api_gateway_base = api_url.clone();
+        }
+        if let Some(package_url) = &self.package_url {
+            environment.package_source = package_url.clone();
+        }
+        if let Some(app_id) = &self.app_id {
+            environment.oauth_redirect_uri = format!("{app_id}:/oauth2Callback");
+            environment.app_id = app_id.clone();
+        }
+        if let Some(oauth_client_id) = &self.oauth_client_id {
+            environment.oauth_client_id = oauth_client_id.clone();
+        }
+        if let Some(oauth_redirect_uri) = &self.oauth_redirect_uri {
+            environment.oauth_redirect_uri = oauth_redirect_uri.clone();
+        }
+        if let Some(auth_index_value) = &self.auth_index_value {
+            environment.auth_index_value = auth_index_value.clone();
+        }
+        if let Some(command_status_path) = &self.command_status_path {
+            environment.command_status_path = Some(command_status_path.clone());
+        }
+    }
+}
+
+impl Config {
+    /// Where our configuration lives by default.
+    ///
+    /// We honor `XDG_CONFIG_HOME` on every platform, falling back to the platform's default.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .or_else(dirs::config_dir)?;
+        Some(base.join("toyotactl").join("config.toml"))
+    }
+
+    /// Loads the configuration at the given path. A missing file is an empty configuration.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(ConfigError::Parse),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(ConfigError::Io(err)),
+        }
+    }
+
+    /// The profile to use: the given one (such as via `--profile`),
+    /// then our default profile, and lastly ``DEFAULT_PROFILE``.
+    pub fn profile_name(&self, requested: Option<&str>) -> String {
+        requested
+            .or(self.default_profile.as_deref())
+            .unwrap_or(DEFAULT_PROFILE)
+            .to_string()
+    }
+
+    /// Settings for the given profile, which may well be empty.
+    pub fn profile(&self, name: &str) -> ProfileConfig {
+        self.profiles.get(name).cloned().unwrap_or_default()
+    }
+}
+
+impl ProfileConfig {
+    /// Resolves the given vehicle, which may be an alias, to its VIN.
+    ///
+    /// Without a vehicle, we'll fall back to our default.
+    pub fn resolve_vin(&self, vehicle: Option<&str>) -> Option<String> {
+        let vehicle = vehicle.or(self.default_vin.as_deref())?;
+        Some(
+            self.aliases
+                .get(vehicle)
+                .cloned()
+                .unwrap_or_else(|| vehicle.to_string()),
+        )
+    }
+}
+
+/// Our configuration as a plain TOML document, for `config get` and `config set`.
+///
+/// Keys are dotted paths, such as `profiles.default.units`.
+#[derive(Debug)]
+pub struct ConfigFile {
+    path: PathBuf,
+    table: toml::Table,
+}
+
+impl ConfigFile {
+    /// Opens the configuration at the given path. A missing file is an empty document.
+    pub fn open(path: &Path) -> Result<Self, ConfigError> {
+        let table = match fs::read_to_string(path) {
+            Ok(contents) => contents.parse().map_err(ConfigError::Parse)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => toml::Table::new(),
+            Err(err) => return Err(ConfigError::Io(err)),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            table,
+        })
+    }
+
+    /// The value at the given key, if set.
+    pub fn get(&self, key: &str) -> Option<&toml::Value> {
+        let mut parts = key.split('.');
+        let first = self.table.get(parts.next()?)?;
+        parts.try_fold(first, |value, part| value.get(part))
+    }
+
+    /// Sets the given key, creating tables along the way.
+    ///
+    /// The value is interpreted as TOML if possible (such as `5` or `true`),
+    /// and as a string otherwise. The result must remain a valid configuration.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let value = parse_value(value);
+        let (parents, last) = split_key(key)?;
+        let mut table = &mut self.table;
+        for part in parents {
+            let entry = table
+                .entry(part.to_string())
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            table = entry
+                .as_table_mut()
+                .ok_or_else(|| ConfigError::InvalidKey(key.to_string()))?;
+        }
+        table.insert(last.to_string(), value);
+        self.validate()
+    }
+
+    /// Removes the given key, returning whether it was set.
+    pub fn unset(&mut self, key: &str) -> Result<bool, ConfigError> {
+        let (parents, last) = split_key(key)?;
+        let mut table = &mut self.table;
+        for part in parents {
+            match table.get_mut(part).and_then(toml::Value::as_table_mut) {
+                Some(child) => table = child,
+                None => return Ok(false),
+            }
+        }
+        Ok(table.remove(last).is_some())
+    }
+
+    /// Ensures our document still describes a valid configuration.
+    fn validate(&self) -> Result<(), ConfigError> {
+        let contents = toml::to_string(&self.table).map_err(ConfigError::Serialize)?;
+        toml::from_str::<Config>(&contents).map_err(ConfigError::Parse)?;
+        Ok(())
+    }
+
+    /// Writes our document back to disk.
+    pub fn save(&self) -> Result<(), ConfigError> {
+        self.validate()?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(ConfigError::Io)?;
+        }
+        let contents = toml::to_string_pretty(&self.table).map_err(ConfigError::Serialize)?;
+        fs::write(&self.path, contents).map_err(ConfigError::Io)
+    }
+}
+
+/// Splits a dotted key into its parent tables and final component.
+fn split_key(key: &str) -> Result<(Vec<&str>, &str), ConfigError> {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    if parts.iter().any(|part| part.is_empty()) {
+        return Err(ConfigError::InvalidKey(key.to_string()));
+    }
+    let last = parts.pop().expect("split should yield at least one part");
+    Ok((parts, last))
+}
+
+/// Interprets a value given on the command line.
+fn parse_value(value: &str) -> toml::Value {
+    // We can only parse values within a document, so we'll provide one.
+    format!("value = {value}")
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads the given configuration from disk, as we would normally.
+    fn load(name: &str, contents: &str) -> Result<Config, ConfigError> {
+        let path = std::env::temp_dir().join(format!(
+            "toyotactl-config-{}-{name}.toml",
+            std::process::id()
+        ));
+        fs::write(&path, contents).unwrap();
+        let config = Config::load(&path);
+        let _ = fs::remove_file(&path);
+        config
+    }
+
+    #[test]
+    fn profile_resolution_order() {
+        let config = load(
+            "profiles",
+            r#"
+            default_profile = "personal"
+
+            [profiles.personal]
+            units = "metric"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.profile_name(Some("work")), "work");
+        assert_eq!(config.profile_name(None), "personal");
+        assert_eq!(config.profile("personal").units, Some(Units::Metric));
+        // Profiles without settings are simply empty.
+        assert!(config.profile("work").units.is_none());
+
+        let config = Config::default();
+        assert_eq!(config.profile_name(Some("work")), "work");
+        assert_eq!(config.profile_name(None), DEFAULT_PROFILE);
+    }
+
+    #[test]
+    fn missing_file_is_empty() {
+        let path = std::env::temp_dir().join("toyotactl-config-nonexistent.toml");
+        let config = Config::load(&path).unwrap();
+        assert!(config.default_profile.is_none());
+        assert!(config.profiles.is_empty());
+    }
+
+    #[test]
+    fn aliases() {
+        let config = load(
+            "aliases",
+            r#"
+            [profiles.default]
+            default_vin = "tacoma"
+
+            [profiles.default.aliases]
+            tacoma = "JTEXAMPLE00000001"
+            prius = "JTEXAMPLE00000002"
+            "#,
+        )
+        .unwrap();
+        let profile = config.profile("default");
+        assert_eq!(
+            profile.resolve_vin(Some("prius")).as_deref(),
+            Some("JTEXAMPLE00000002")
+        );
+        // VINs pass through as-is.
+        assert_eq!(
+            profile.resolve_vin(Some("JTEXAMPLE00000003")).as_deref(),
+            Some("JTEXAMPLE00000003")
+        );
+        // Our default may itself be an alias.
+        assert_eq!(
+            profile.resolve_vin(None).as_deref(),
+            Some("JTEXAMPLE00000001")
+        );
+        assert_eq!(ProfileConfig::default().resolve_vin(None), None);
+    }
+
+    #[test]
+    fn endpoint_overrides() {
+        let config = load(
+            "endpoints",
+            r#"
+            [profiles.default.endpoints]
+            forgerock_url = "http://127.0.0.1:8080/"
+            api_url = "http://127.0.0.1:8080/oneapi/"
+            realm = "test-realm"
+            app_id = "com.example.app"
+            "#,
+        )
+        .unwrap();
+        let mut environment = Environment::production();
+        config
+            .profile("default")
+            .endpoints
+            .unwrap()
+            .apply(&mut environment);
+        assert_eq!(
+            environment.forgerock_base.as_str(),
+            "http://127.0.0.1:8080/"
+        );
+        assert_eq!(
+            environment.api_gateway_base.as_str(),
+            "http://127.0.0.1:8080/oneapi/"
+        );
+        assert_eq!(environment.realm, "test-realm");
+        assert_eq!(environment.app_id, "com.example.app");
+        // Our app identifier implies its redirect URI...
+        assert_eq!(
+            environment.oauth_redirect_uri,
+            "com.example.app:/oauth2Callback"
+        );
+        // ...and everything else is left be.
+        let production = Environment::production();
+        assert_eq!(environment.oauth_client_id, production.oauth_client_id);
+        assert_eq!(environment.package_source, production.package_source);
+
+        // An explicit redirect URI takes precedence over one derived from the app.
+        let endpoints = EndpointConfig {
+            app_id: Some("com.example.app".to_string()),
+            oauth_redirect_uri: Some("com.example.other:/callback".to_string()),
+            ..EndpointConfig::default()
+        };
+        endpoints.apply(&mut environment);
+        assert_eq!(
+            environment.oauth_redirect_uri,
+            "com.example.other:/callback"
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        for (name, contents) in [
+            ("top-level", "default_profil = \"personal\""),
+            ("profile", "[profiles.default]\nunit = \"metric\""),
+            (
+                "endpoints",
+                "[profiles.default.endpoints]\nforgerock = \"http://127.0.0.1/\"",
+            ),
+            ("retry", "[profiles.default.retry]\nretries = 5"),
+        ] {
+            assert!(
+                matches!(load(name, contents), Err(ConfigError::Parse(_))),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        for (name, contents) in [
+            ("syntax", "[profiles.default"),
+            ("type", "[profiles.default]\nunits = 5"),
+            ("region", "[profiles.default]\nregion = \"atlantis\""),
+            (
+                "url",
+                "[profiles.default.endpoints]\napi_url = \"not a url\"",
+            ),
+        ] {
+            assert!(
+                matches!(load(name, contents), Err(ConfigError::Parse(_))),
+                "{name}"
+            );
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+use std::path::Path;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{
+        unix::{OwnedReadHalf, OwnedWriteHalf},
+        UnixStream,
+    },
+};
+
+use super::{CommandParams, DaemonError, RpcRequest, RpcResponse, VehicleParams};
+use crate::api::{RemoteCommand, RemoteCommandResult, Telemetry, Vehicle, VehicleStatus};
+
+/// A connection to a running daemon.
+#[derive(Debug)]
+pub struct DaemonClient {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    next_id: u64,
+}
+
+impl DaemonClient {
+    /// Connects to the daemon listening on the given socket.
+    pub async fn connect(path: &Path) -> Result<Self, DaemonError> {
+        let stream = UnixStream::connect(path).await.map_err(DaemonError::Io)?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self {
+            reader: BufReader::new(reader),
+            writer,
+            next_id: 1,
+        })
+    }
+
+    /// Calls the given method, parsing its result.
+    #[tracing::instrument(skip(self, params))]
+    pub async fn call<T: DeserializeOwned>(
+        &mut self,
+        method: &str,
+        params: impl Serialize,
+    ) -> Result<T, DaemonError> {
+        let request = RpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: json!(self.next_id),
+            method: method.to_string(),
+            params: serde_json::to_value(params).map_err(DaemonError::Parse)?,
+        };
+        self.next_id += 1;
+
+        let mut line = serde_json::to_vec(&request).map_err(DaemonError::Parse)?;
+        line.push(b'\n');
+        self.writer
+            .write_all(&line)
+            .await
+            .map_err(DaemonError::Io)?;
+
+        let mut response = String::new();
+        let read = self
+            .reader
+            .read_line(&mut response)
+            .await
+            .map_err(DaemonError::Io)?;
+        if read == 0 {
+            return Err(DaemonError::Disconnected);
+        }
+        let response: RpcResponse = serde_json::from_str(&response).map_err(DaemonError::Parse)?;
+        if let Some(error) = response.error {
+            return Err(DaemonError::Rpc(error));
+        }
+        serde_json::from_value(response.result.unwrap_or_default()).map_err(DaemonError::Parse)
+    }
+
+    /// Lists all vehicles associated with the daemon's account.
+    pub async fn vehicles(&mut self) -> Result<Vec<Vehicle>, DaemonError> {
+        self.call("vehicles", json!({})).await
+    }
+
+    pub async fn vehicle_status(&mut self, vin: &str) -> Result<VehicleStatus, DaemonError> {
+        let params = VehicleParams {
+            vin: vin.to_string(),
+        };
+        self.call("status", params).await
+    }
+
+    pub async fn telemetry(&mut self, vin: &str) -> Result<Telemetry, DaemonError> {
+        let params = VehicleParams {
+            vin: vin.to_string(),
+        };
+        self.call("telemetry", params).await
+    }
+
+    /// Asks the daemon to issue the given command. It applies its own policy, too,
+    /// treating us as automation.
+    pub async fn remote_command(
+        &mut self,
+        vin: &str,
+        command: RemoteCommand,
+        confirmed: bool,
+    ) -> Result<RemoteCommandResult, DaemonError> {
+        let params = CommandParams {
+            vin: vin.to_string(),
+            command,
+            confirmed,
+        };
+        self.call("command", params).await
+    }
+}
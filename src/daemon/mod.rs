@@ -0,0 +1,160 @@
+//! A long-running process keeping an ``ApiClient`` logged in, reachable over a Unix socket.
+//!
+//! Rather than reading the keyring and checking tokens upon every invocation, the CLI will
+//! ask the daemon for its profile when it's running. The daemon also polls each vehicle's
+//! status and telemetry into our cache, so that reads are usually answered without asking.
+//!
+//! We speak JSON-RPC 2.0, with a single request or response per line:
+//! ```text
+//! → {"jsonrpc":"2.0","id":1,"method":"status","params":{"vin":"JT..."}}
+//! ← {"jsonrpc":"2.0","id":1,"result":{"vehicleStatus":[...]}}
+//! ```
+//! Methods are `vehicles`, `status`, `telemetry` and `command`. Results follow the serde
+//! shapes of our API types, as with `--output json`.
+
+mod client;
+mod server;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{fmt, io, path::PathBuf};
+
+use crate::{api::RemoteCommand, storage};
+
+pub use client::DaemonClient;
+pub use server::Daemon;
+
+/// The request could not be parsed as JSON.
+pub const PARSE_ERROR: i64 = -32700;
+/// The request was not a valid JSON-RPC request.
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// We were unable to log in, or the API returned an error.
+pub const API_ERROR: i64 = -32000;
+/// The safety policy refused the command.
+pub const REFUSED: i64 = -32001;
+
+/// Possible errors while communicating with the daemon.
+#[derive(Debug)]
+pub enum DaemonError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    /// The daemon responded with an error.
+    Rpc(RpcError),
+    /// The daemon closed our connection without responding.
+    Disconnected,
+    /// Another daemon is already listening on the given socket.
+    AlreadyRunning(PathBuf),
+}
+
+impl fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DaemonError::Io(err) => write!(f, "unable to communicate with daemon: {err}"),
+            DaemonError::Parse(err) => write!(f, "unable to parse daemon response: {err}"),
+            DaemonError::Rpc(err) => write!(f, "{err}"),
+            DaemonError::Disconnected => write!(f, "daemon closed the connection"),
+            DaemonError::AlreadyRunning(path) => {
+                write!(f, "a daemon is already listening on {}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for DaemonError {}
+
+/// A JSON-RPC request.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+/// A JSON-RPC response, holding either a result or an error.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl RpcResponse {
+    pub fn success(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub fn failure(id: Value, error: RpcError) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: None,
+            error: Some(error),
+        }
+    }
+}
+
+/// A JSON-RPC error, such as ``REFUSED``.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl fmt::Display) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Parameters for `status` and `telemetry`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct VehicleParams {
+    pub vin: String,
+}
+
+/// Parameters for `command`.
+///
+/// We can't tell who is on the other end of the socket, so every command is treated as
+/// coming from ``Origin::Automation``: those the policy reserves for a person are refused.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CommandParams {
+    pub vin: String,
+    pub command: RemoteCommand,
+    /// Whether the caller has already obtained confirmation, as with `--yes`.
+    #[serde(default)]
+    pub confirmed: bool,
+}
+
+/// Where the daemon for the given profile listens by default.
+///
+/// We'll prefer the user's runtime directory, as it's private to them and
+/// cleared upon logout, falling back to their cache directory.
+/// Profile names that could escape it are refused, per ``storage::is_valid_profile``.
+pub fn socket_path(profile: &str) -> Option<PathBuf> {
+    if !storage::is_valid_profile(profile) {
+        return None;
+    }
+    let base = dirs::runtime_dir().or_else(dirs::cache_dir)?;
+    Some(base.join("toyotactl").join(format!("{profile}.sock")))
+}
@@ -0,0 +1,506 @@
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{fmt, fs, io, os::unix::fs::PermissionsExt, path::Path, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::RwLockReadGuard,
+    time::MissedTickBehavior,
+};
+
+use super::{
+    CommandParams, DaemonError, RpcError, RpcRequest, RpcResponse, VehicleParams, API_ERROR,
+    INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, PARSE_ERROR, REFUSED,
+};
+use crate::{
+    api::{ApiClient, SharedClient, Vehicle},
+    audit::AuditLog,
+    cache::{Cache, CAPABILITIES_MAX_AGE, VEHICLES_MAX_AGE},
+    safety::{self, Origin, Policy, Refusal},
+};
+
+/// A logged-in client for a single profile, shared between every connection.
+pub struct Daemon {
+    profile: String,
+    client: SharedClient,
+    cache: Option<Cache>,
+    audit: AuditLog,
+    policy: Policy,
+}
+
+/// Wraps an error from the API (or logging in) for our callers.
+fn api_error(err: impl fmt::Display) -> RpcError {
+    RpcError::new(API_ERROR, err)
+}
+
+/// Parses the parameters of a request.
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|err| RpcError::new(INVALID_PARAMS, err))
+}
+
+/// Serializes the result of a request.
+fn result(value: impl Serialize) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(api_error)
+}
+
+impl Daemon {
+    /// Creates a daemon around the given, already logged in, client.
+    pub fn new(
+        profile: &str,
+        client: SharedClient,
+        cache: Option<Cache>,
+        audit: AuditLog,
+        policy: Policy,
+    ) -> Self {
+        Self {
+            profile: profile.to_string(),
+            client,
+            cache,
+            audit,
+            policy,
+        }
+    }
+
+    /// Listens upon the given socket, accessible to the current user alone.
+    ///
+    /// Its directory is created should it not exist, accessible to the current user alone.
+    /// A socket left behind by a daemon that's no longer running is replaced.
+    pub async fn bind(path: &Path) -> Result<UnixListener, DaemonError> {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(DaemonError::AlreadyRunning(path.to_path_buf()));
+        }
+        // We'll only restrict a directory we create, rather than (say) `/tmp`.
+        let parent = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty());
+        if let Some(parent) = parent.filter(|parent| !parent.exists()) {
+            fs::create_dir_all(parent).map_err(DaemonError::Io)?;
+            fs::set_permissions(parent, fs::Permissions::from_mode(0o700))
+                .map_err(DaemonError::Io)?;
+        }
+        match fs::remove_file(path) {
+            Ok(()) => tracing::info!(path = %path.display(), "replacing stale socket"),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(DaemonError::Io(err)),
+        }
+
+        let listener = UnixListener::bind(path).map_err(DaemonError::Io)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(DaemonError::Io)?;
+        Ok(listener)
+    }
+
+    /// Serves every connection upon the given socket, forever.
+    pub async fn serve(self: Arc<Self>, listener: UnixListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let daemon = self.clone();
+                    tokio::spawn(async move { daemon.connection(stream).await });
+                }
+                Err(err) => tracing::warn!("unable to accept connection: {err}"),
+            }
+        }
+    }
+
+    /// Refreshes every vehicle's status and telemetry within our cache, forever.
+    pub async fn poll(self: Arc<Self>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = self.poll_once().await {
+                tracing::warn!("unable to poll vehicles: {err}");
+            }
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn poll_once(&self) -> Result<(), RpcError> {
+        let client = self.client().await?;
+        let vehicles = client.vehicles().await.map_err(api_error)?;
+        let Some(cache) = &self.cache else {
+            // With nowhere to keep them, there's little point in polling.
+            return Ok(());
+        };
+        cache.store_vehicles(&vehicles);
+
+        for vehicle in &vehicles {
+            let vin = &vehicle.vin;
+            match client.vehicle_status(vin).await {
+                Ok(status) => cache.store_status(vin, &status),
+                Err(err) => tracing::warn!(%vin, "unable to poll status: {err}"),
+            }
+            match client.telemetry(vin).await {
+                Ok(telemetry) => cache.store_telemetry(vin, &telemetry),
+                Err(err) => tracing::warn!(%vin, "unable to poll telemetry: {err}"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Our client, refreshed as necessary.
+    async fn client(&self) -> Result<RwLockReadGuard<'_, ApiClient>, RpcError> {
+        self.client.get().await.map_err(|err| {
+            api_error(format!(
+                "unable to refresh session, so please log in via toyotactl: {err}"
+            ))
+        })
+    }
+
+    /// The vehicles on this account, as we last polled them if recently enough.
+    async fn vehicles(&self, client: &ApiClient) -> Result<Vec<Vehicle>, RpcError> {
+        if let Some(entry) = self.cache.as_ref().and_then(Cache::vehicles_entry) {
+            if entry.age() < VEHICLES_MAX_AGE {
+                return Ok(entry.value);
+            }
+        }
+        let vehicles = client.vehicles().await.map_err(api_error)?;
+        if let Some(cache) = &self.cache {
+            cache.store_vehicles(&vehicles);
+        }
+        Ok(vehicles)
+    }
+
+    /// Responds to every request upon the given connection until it's closed.
+    async fn connection(&self, stream: UnixStream) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        loop {
+            let line = match lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => return,
+                Err(err) => {
+                    tracing::debug!("unable to read request: {err}");
+                    return;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = self.respond(&line).await;
+            let mut contents = match serde_json::to_vec(&response) {
+                Ok(contents) => contents,
+                Err(err) => {
+                    tracing::warn!("unable to serialize response: {err}");
+                    return;
+                }
+            };
+            contents.push(b'\n');
+            if let Err(err) = writer.write_all(&contents).await {
+                tracing::debug!("unable to write response: {err}");
+                return;
+            }
+        }
+    }
+
+    async fn respond(&self, line: &str) -> RpcResponse {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(err) => return RpcResponse::failure(Value::Null, RpcError::new(PARSE_ERROR, err)),
+        };
+        let id = request.get("id").cloned().unwrap_or_default();
+        let request: RpcRequest = match serde_json::from_value(request) {
+            Ok(request) => request,
+            Err(err) => return RpcResponse::failure(id, RpcError::new(INVALID_REQUEST, err)),
+        };
+        if request.jsonrpc != "2.0" {
+            let error = RpcError::new(INVALID_REQUEST, "only JSON-RPC 2.0 is supported");
+            return RpcResponse::failure(id, error);
+        }
+
+        match self.handle(&request.method, request.params).await {
+            Ok(value) => RpcResponse::success(id, value),
+            Err(error) => RpcResponse::failure(id, error),
+        }
+    }
+
+    #[tracing::instrument(skip(self, params))]
+    async fn handle(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "vehicles" => {
+                let vehicles = self.client().await?.vehicles().await;
+                let vehicles = vehicles.map_err(api_error)?;
+                if let Some(cache) = &self.cache {
+                    cache.store_vehicles(&vehicles);
+                }
+                result(vehicles)
+            }
+            "status" => {
+                let VehicleParams { vin } = self::params(params)?;
+                let status = self.client().await?.vehicle_status(&vin).await;
+                let status = status.map_err(api_error)?;
+                if let Some(cache) = &self.cache {
+                    cache.store_status(&vin, &status);
+                }
+                result(status)
+            }
+            "telemetry" => {
+                let VehicleParams { vin } = self::params(params)?;
+                let telemetry = self.client().await?.telemetry(&vin).await;
+                let telemetry = telemetry.map_err(api_error)?;
+                if let Some(cache) = &self.cache {
+                    cache.store_telemetry(&vin, &telemetry);
+                }
+                result(telemetry)
+            }
+            "command" => self.command(self::params(params)?).await,
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method: {method}"),
+            )),
+        }
+    }
+
+    /// Issues a remote command, applying our own policy regardless of what the caller did.
+    ///
+    /// Anyone able to reach our socket could claim to be a person, so we treat all as automation.
+    async fn command(&self, params: CommandParams) -> Result<Value, RpcError> {
+        let CommandParams {
+            vin,
+            command,
+            confirmed,
+        } = params;
+        let refuse = |refusal: Refusal| {
+            RpcError::new(
+                REFUSED,
+                format!("refusing to issue {command} to {vin}: {refusal}"),
+            )
+        };
+        self.policy
+            .check(command, Origin::Automation)
+            .map_err(refuse)?;
+        if self.policy.needs_confirmation(command) && !confirmed {
+            return Err(refuse(Refusal::Unconfirmed(command)));
+        }
+
+        let client = self.client().await?;
+        let vehicles = self.vehicles(&client).await?;
+        let cached = || {
+            let entry = self.cache.as_ref()?.capabilities(&vin)?;
+            (entry.age() < CAPABILITIES_MAX_AGE).then_some(entry.value)
+        };
+        safety::check_eligible(&vehicles, &vin, command, cached).map_err(refuse)?;
+
+        let issued = self
+            .audit
+            .issue(&client, &self.profile, &vin, command)
+            .await;
+        let issued = issued.map_err(api_error)?;
+        // Whatever status we had cached is now out of date.
+        if let Some(cache) = &self.cache {
+            cache.invalidate(&vin);
+        }
+        result(issued)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{
+        api::{GatewayKeyConfig, RemoteCommand},
+        daemon::{DaemonClient, DaemonError},
+        environment::Environment,
+        fixture::{Fixture, Interaction, RecordedRequest, RecordedResponse, Replayer},
+        http::{HttpClient, HttpConfig},
+        storage::MemoryStore,
+    };
+
+    const VIN: &str = "JTMOCK00000000001";
+
+    /// A unique directory within our temporary directory, removed once dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("toyotactl-daemon-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[tokio::test]
+    async fn stale_sockets_are_replaced() {
+        let dir = TempDir::new("stale");
+        let socket = dir.0.join("default.sock");
+
+        // We create our directory privately.
+        let listener = Daemon::bind(&socket).await.unwrap();
+        assert_eq!(mode(&dir.0), 0o700);
+        assert_eq!(mode(&socket), 0o600);
+
+        // A daemon exiting without removing its socket leaves it behind.
+        drop(listener);
+        assert!(socket.exists());
+        let _listener = Daemon::bind(&socket).await.unwrap();
+        assert!(UnixStream::connect(&socket).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn existing_directories_are_left_alone() {
+        let dir = TempDir::new("existing");
+        fs::create_dir(&dir.0).unwrap();
+        fs::set_permissions(&dir.0, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let _listener = Daemon::bind(&dir.0.join("default.sock")).await.unwrap();
+        assert_eq!(mode(&dir.0), 0o755);
+    }
+
+    #[tokio::test]
+    async fn only_one_daemon_runs() {
+        let dir = TempDir::new("running");
+        let socket = dir.0.join("default.sock");
+        let _listener = Daemon::bind(&socket).await.unwrap();
+
+        let err = Daemon::bind(&socket).await.unwrap_err();
+        assert!(
+            matches!(&err, DaemonError::AlreadyRunning(path) if path == &socket),
+            "{err:?}"
+        );
+        // The running daemon remains reachable.
+        assert!(socket.exists());
+    }
+
+    /// An unsigned JWT expiring well into the future, so that we never refresh it.
+    fn token() -> String {
+        let header = URL_SAFE_NO_PAD.encode(json!({"alg": "none"}).to_string());
+        let exp = chrono::Utc::now().timestamp() + 3600;
+        let claims = URL_SAFE_NO_PAD.encode(json!({"sub": "guid", "exp": exp}).to_string());
+        format!("{header}.{claims}.")
+    }
+
+    /// A daemon serving upon a socket within the given directory, alongside its audit log,
+    /// answering from the given API responses in order.
+    async fn serve(
+        dir: &TempDir,
+        responses: Vec<(&str, serde_json::Value)>,
+        policy: Policy,
+    ) -> (PathBuf, Arc<Replayer>) {
+        let environment = Environment::production();
+        let interactions = responses
+            .into_iter()
+            .map(|(path, body)| Interaction {
+                request: RecordedRequest {
+                    method: "GET".to_string(),
+                    url: environment.api_url(path),
+                    body: None,
+                },
+                response: RecordedResponse {
+                    status: 200,
+                    headers: vec![],
+                    body: json!({ "payload": body }).to_string(),
+                },
+            })
+            .collect();
+        let replayer = Arc::new(Replayer::new(Fixture { interactions }));
+        let http = HttpClient::new(&HttpConfig::default())
+            .unwrap()
+            .with_replayer(replayer.clone());
+        let client = ApiClient::new(
+            http,
+            environment,
+            token(),
+            "guid".to_string(),
+            Arc::new(MemoryStore::new()),
+        )
+        .with_gateway_key_config(GatewayKeyConfig {
+            key: Some("gateway-key".to_string()),
+            ..GatewayKeyConfig::default()
+        });
+
+        fs::create_dir_all(&dir.0).unwrap();
+        let audit = AuditLog::open(&dir.0.join("audit.jsonl")).unwrap();
+        let daemon = Daemon::new("default", SharedClient::new(client), None, audit, policy);
+        let socket = dir.0.join("default.sock");
+        let listener = Daemon::bind(&socket).await.unwrap();
+        tokio::spawn(Arc::new(daemon).serve(listener));
+        (socket, replayer)
+    }
+
+    #[tokio::test]
+    async fn vehicles_and_status_round_trip() {
+        let dir = TempDir::new("round-trip");
+        let responses = vec![
+            (
+                "v3/vehicle/guid",
+                json!([{ "vin": VIN, "nickName": "tacoma" }]),
+            ),
+            (
+                "v1/global/remote/status",
+                json!({ "occurrenceDate": "2024-01-01T00:00:00Z", "vehicleStatus": [] }),
+            ),
+        ];
+        let (socket, replayer) = serve(&dir, responses, Policy::default()).await;
+
+        let mut client = DaemonClient::connect(&socket).await.unwrap();
+        let vehicles = client.vehicles().await.unwrap();
+        assert_eq!(vehicles.len(), 1);
+        assert_eq!(vehicles[0].vin, VIN);
+        assert_eq!(vehicles[0].nick_name.as_deref(), Some("tacoma"));
+        let status = client.vehicle_status(VIN).await.unwrap();
+        assert_eq!(
+            status.occurrence_date.as_deref(),
+            Some("2024-01-01T00:00:00Z")
+        );
+        assert!(replayer.is_exhausted());
+
+        // Anything else is answered with an error, rather than closing the connection.
+        let err = client.call::<Value>("reboot", json!({})).await.unwrap_err();
+        assert!(
+            matches!(&err, DaemonError::Rpc(error) if error.code == METHOD_NOT_FOUND),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn policy_applies_over_the_socket() {
+        let dir = TempDir::new("policy");
+        let policy = Policy {
+            interactive_only: vec![RemoteCommand::DoorUnlock],
+            ..Policy::default()
+        };
+        let (socket, replayer) = serve(&dir, vec![], policy).await;
+        let mut client = DaemonClient::connect(&socket).await.unwrap();
+        let refused = |result: Result<Value, DaemonError>| match result {
+            Err(DaemonError::Rpc(error)) if error.code == REFUSED => error.message,
+            result => panic!("expected a refusal, not {result:?}"),
+        };
+
+        // Claiming to be a person, and to have confirmed it, changes nothing.
+        let params = json!({
+            "vin": VIN,
+            "command": "door-unlock",
+            "origin": "interactive",
+            "confirmed": true,
+        });
+        let message = refused(client.call("command", params).await);
+        assert!(message.contains("only allowed interactively"), "{message}");
+
+        // Commands requiring confirmation must have it.
+        let params = json!({ "vin": VIN, "command": "engine-start" });
+        let message = refused(client.call("command", params).await);
+        assert!(message.contains("requires confirmation"), "{message}");
+
+        // Nothing refused reached the API, nor was recorded.
+        assert!(replayer.is_exhausted());
+        let entries = AuditLog::entries(&dir.0.join("audit.jsonl")).unwrap();
+        assert!(entries.is_empty());
+    }
+}
@@ -0,0 +1,230 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use url::Url;
+
+/// The brand and region an account belongs to.
+///
+/// Each has its own OneApp, and as such its own app identifier, redirect URI and API brand.
+/// We only offer regions whose values we've verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Region {
+    #[default]
+    ToyotaUs,
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Region::ToyotaUs => f.write_str("toyota-us"),
+        }
+    }
+}
+
+/// The values differing between each region's production environment.
+struct RegionEndpoints {
+    forgerock_base: &'static str,
+    realm: &'static str,
+    oauth_client_id: &'static str,
+    auth_index_value: &'static str,
+    app_id: &'static str,
+    api_gateway_base: &'static str,
+    brand: &'static str,
+    region_code: &'static str,
+}
+
+/// Toyota USA's values are those within `toyota-na` 2.1.1 (see ``Environment::package_source``),
+/// the same package we obtain our API gateway key from.
+const TOYOTA_US: RegionEndpoints = RegionEndpoints {
+    forgerock_base: "https://login.toyotadriverslogin.com/",
+    realm: "tmna-native",
+    oauth_client_id: "oneappsdkclient",
+    auth_index_value: "OneAppSignIn",
+    app_id: "com.toyota.oneapp",
+    api_gateway_base: "https://onecdn.telematicsct.com/oneapi/",
+    brand: "T",
+    region_code: "US",
+};
+
+impl Region {
+    /// The production environment for this region.
+    pub fn environment(&self) -> Environment {
+        let endpoints = match self {
+            Region::ToyotaUs => TOYOTA_US,
+        };
+
+        Environment {
+            forgerock_base: Url::parse(endpoints.forgerock_base)
+                .expect("production ForgeRock URL should be valid"),
+            realm: endpoints.realm.to_string(),
+            oauth_client_id: endpoints.oauth_client_id.to_string(),
+            // The redirect URI is derived from each app's identifier.
+            oauth_redirect_uri: format!("{}:/oauth2Callback", endpoints.app_id),
+            // ForgeRock documents that you must specify an auth index "type".
+            // We use the "service" type with "OneAppSignIn" so that we can log in.
+            // (For registeration, there is also "OneAppSignUp".)
+            auth_index_type: "service".to_string(),
+            auth_index_value: endpoints.auth_index_value.to_string(),
+            locale: "en-US".to_string(),
+            app_id: endpoints.app_id.to_string(),
+            api_gateway_base: Url::parse(endpoints.api_gateway_base)
+                .expect("production API gateway URL should be valid"),
+            brand: endpoints.brand.to_string(),
+            region_code: endpoints.region_code.to_string(),
+            package_source: Url::parse("https://files.pythonhosted.org/packages/0c/57/45e0db16e4f8d2f1fe864205ee90adcc8a9b8451eec045f69f9a4b42acf3/toyota-na-2.1.1.tar.gz")
+                .expect("production package URL should be valid"),
+            // We've yet to find where the app learns how a command turned out.
+            command_status_path: None,
+        }
+    }
+}
+
+/// The hosts and paths we communicate with.
+///
+/// By default, these point at Toyota's production services.
+/// They can be overridden to target a local stand-in server,
+/// such as for integration tests, or another region entirely.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    /// The base URL of ForgeRock AM, such as `https://login.toyotadriverslogin.com/`.
+    pub forgerock_base: Url,
+    /// The ForgeRock realm we authenticate within, such as `tmna-native`.
+    pub realm: String,
+    /// The client ID across all OAuth2 requests.
+    pub oauth_client_id: String,
+    /// The redirect URI across all OAuth2 requests.
+    pub oauth_redirect_uri: String,
+    /// The auth index "type" used while authenticating.
+    pub auth_index_type: String,
+    /// The auth index value, i.e. the authentication tree, used while authenticating.
+    pub auth_index_value: String,
+    /// The UI locale we present while authenticating, such as `en-US`.
+    pub locale: String,
+    /// The identifier of the app we present ourselves as.
+    pub app_id: String,
+    /// The base URL for all OneApp API requests.
+    pub api_gateway_base: Url,
+    /// The brand sent alongside API requests, such as `T` for Toyota or `L` for Lexus.
+    pub brand: String,
+    /// The region sent alongside API requests, such as `US`.
+    pub region_code: String,
+    /// Where to download the `toyota-na` sdist from, should we need the API gateway key.
+    pub package_source: Url,
+    /// The API path reporting the progress of an accepted remote command, if known.
+    /// Without one, commands are recorded as merely accepted.
+    pub command_status_path: Option<String>,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::production()
+    }
+}
+
+impl Environment {
+    /// Toyota USA's production services.
+    pub fn production() -> Self {
+        Region::ToyotaUs.environment()
+    }
+
+    /// The authenticate endpoint using ForgeRock AM.
+    pub fn authenticate_endpoint(&self) -> String {
+        join(
+            &self.forgerock_base,
+            &format!("json/realms/root/realms/{}/authenticate", self.realm),
+        )
+    }
+
+    /// The authorization endpoint via ForgeRock AM.
+    /// ("Authorize" should be read in the context of OAuth2, and not the previous custom authentication flow.)
+    pub fn authorization_endpoint(&self) -> String {
+        join(
+            &self.forgerock_base,
+            &format!("oauth2/realms/root/realms/{}/authorize", self.realm),
+        )
+    }
+
+    /// The endpoint leveraged for obtaining an access token.
+    pub fn access_token_endpoint(&self) -> String {
+        join(
+            &self.forgerock_base,
+            &format!("oauth2/realms/root/realms/{}/access_token", self.realm),
+        )
+    }
+
+    /// The OpenID Connect userinfo endpoint, describing who we're logged in as.
+    pub fn userinfo_endpoint(&self) -> String {
+        join(
+            &self.forgerock_base,
+            &format!("oauth2/realms/root/realms/{}/userinfo", self.realm),
+        )
+    }
+
+    /// The realm's JSON Web Key Set, used to verify token signatures.
+    pub fn jwks_endpoint(&self) -> String {
+        join(
+            &self.forgerock_base,
+            &format!("oauth2/realms/root/realms/{}/connect/jwk_uri", self.realm),
+        )
+    }
+
+    /// The URL for the given path within the OneApp API.
+    pub fn api_url(&self, path: &str) -> String {
+        join(&self.api_gateway_base, path)
+    }
+}
+
+/// Joins the given path onto a base URL.
+///
+/// Unlike ``Url::join``, we always treat the base as a directory,
+/// so that a base of `http://localhost/mock` keeps its `mock` component.
+fn join(base: &Url, path: &str) -> String {
+    format!(
+        "{}/{}",
+        base.as_str().trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toyota_us() {
+        let environment = Region::ToyotaUs.environment();
+        assert_eq!(
+            environment.forgerock_base.as_str(),
+            "https://login.toyotadriverslogin.com/"
+        );
+        assert_eq!(environment.realm, "tmna-native");
+        assert_eq!(environment.oauth_client_id, "oneappsdkclient");
+        assert_eq!(
+            environment.oauth_redirect_uri,
+            "com.toyota.oneapp:/oauth2Callback"
+        );
+        assert_eq!(environment.auth_index_value, "OneAppSignIn");
+        assert_eq!(environment.app_id, "com.toyota.oneapp");
+        assert_eq!(
+            environment.api_gateway_base.as_str(),
+            "https://onecdn.telematicsct.com/oneapi/"
+        );
+        assert_eq!(environment.brand, "T");
+        assert_eq!(environment.region_code, "US");
+        assert_eq!(environment.command_status_path, None);
+        assert_eq!(
+            environment.access_token_endpoint(),
+            "https://login.toyotadriverslogin.com/oauth2/realms/root/realms/tmna-native/access_token"
+        );
+    }
+
+    #[test]
+    fn every_region_is_named() {
+        for region in Region::value_variants() {
+            let name = region.to_string();
+            assert_eq!(Region::from_str(&name, false).unwrap(), *region);
+            region.environment();
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Recording and replaying HTTP interactions, such as to log in without a network.
+//!
+//! When recording, secrets are scrubbed prior to being written: passwords, codes and
+//! opaque tokens are redacted, while JWTs keep their claims (alongside a far-future expiry)
+//! so that replaying can proceed through login. Replayed requests must arrive
+//! in the same order, with the same method and path, as when recorded.
+
+use reqwest::{Request, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
+
+use crate::redact;
+
+/// A recorded request alongside its response.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    /// Recorded for legibility, but not compared when replaying.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RecordedResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    #[serde(default)]
+    pub body: String,
+}
+
+/// The on-disk format of a fixture.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Fixture {
+    pub interactions: Vec<Interaction>,
+}
+
+/// Possible errors while loading a fixture.
+#[derive(Debug)]
+pub enum FixtureError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+}
+
+impl fmt::Display for FixtureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FixtureError::Io(err) => write!(f, "unable to read fixture: {err}"),
+            FixtureError::Parse(err) => write!(f, "unable to parse fixture: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for FixtureError {}
+
+/// Records interactions, writing them to a fixture file.
+///
+/// Similar to HAR captures, we rewrite the entire file after every interaction.
+#[derive(Debug)]
+pub struct Recorder {
+    path: PathBuf,
+    fixture: Mutex<Fixture>,
+}
+
+impl Recorder {
+    /// Creates a recorder that will write to the given path.
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            fixture: Mutex::new(Fixture::default()),
+        }
+    }
+
+    /// Scrubs and records the given interaction.
+    pub fn record(
+        &self,
+        request: &Request,
+        status: StatusCode,
+        headers: &reqwest::header::HeaderMap,
+        body: &[u8],
+    ) {
+        let request_body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(|body| redact::scrub_body(&String::from_utf8_lossy(body)));
+        let headers = headers
+            .iter()
+            .map(|(name, value)| {
+                let value = String::from_utf8_lossy(value.as_bytes());
+                let value = if redact::is_secret_header(name.as_str()) {
+                    redact::REDACTED.to_string()
+                } else if name == reqwest::header::LOCATION {
+                    redact::scrub_url(&value)
+                } else {
+                    redact::scrub_body(&value)
+                };
+                (name.to_string(), value)
+            })
+            .collect();
+
+        let interaction = Interaction {
+            request: RecordedRequest {
+                method: request.method().to_string(),
+                url: redact::scrub_url(request.url().as_str()),
+                body: request_body,
+            },
+            response: RecordedResponse {
+                status: status.as_u16(),
+                headers,
+                body: redact::scrub_body(&String::from_utf8_lossy(body)),
+            },
+        };
+
+        let mut fixture = self.fixture.lock().unwrap_or_else(PoisonError::into_inner);
+        fixture.interactions.push(interaction);
+        let written = serde_json::to_vec_pretty(&*fixture)
+            .map_err(io::Error::other)
+            .and_then(|contents| fs::write(&self.path, contents));
+        if let Err(err) = written {
+            tracing::warn!(path = %self.path.display(), "unable to write fixture: {err}");
+        }
+    }
+}
+
+/// Serves previously recorded interactions in order.
+#[derive(Debug)]
+pub struct Replayer {
+    interactions: Mutex<VecDeque<Interaction>>,
+}
+
+impl Replayer {
+    /// Loads the fixture at the given path.
+    pub fn load(path: &Path) -> Result<Self, FixtureError> {
+        let contents = fs::read(path).map_err(FixtureError::Io)?;
+        let fixture: Fixture = serde_json::from_slice(&contents).map_err(FixtureError::Parse)?;
+        Ok(Self::new(fixture))
+    }
+
+    /// Creates a replayer serving the given fixture.
+    pub fn new(fixture: Fixture) -> Self {
+        Self {
+            interactions: Mutex::new(fixture.interactions.into()),
+        }
+    }
+
+    /// Whether every recorded interaction has been served.
+    pub fn is_exhausted(&self) -> bool {
+        self.interactions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_empty()
+    }
+
+    /// The recorded response for the given request.
+    ///
+    /// If the request differs from what we recorded next, or we've run out
+    /// of recorded interactions, we respond with 404 Not Found and a message
+    /// describing the mismatch. Mismatches leave our recording as-is.
+    pub fn respond(&self, request: &Request) -> RecordedResponse {
+        let mut interactions = self
+            .interactions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let method = request.method().as_str();
+        let path = request.url().path();
+
+        let mismatch = match interactions.front() {
+            Some(next) if next.request.method == method && recorded_path(next) == path => {
+                let next = interactions.pop_front().expect("front should be present");
+                return next.response;
+            }
+            Some(next) => format!(
+                "expected {} {}, but received {method} {path}",
+                next.request.method,
+                recorded_path(next),
+            ),
+            None => format!("no interactions remain for {method} {path}"),
+        };
+
+        tracing::error!("replay mismatch: {mismatch}");
+        RecordedResponse {
+            status: StatusCode::NOT_FOUND.as_u16(),
+            headers: vec![("content-type".to_string(), "application/json".to_string())],
+            body: serde_json::json!({ "message": mismatch }).to_string(),
+        }
+    }
+}
+
+/// The path of the given interaction's URL.
+fn recorded_path(interaction: &Interaction) -> String {
+    match url::Url::parse(&interaction.request.url) {
+        Ok(url) => url.path().to_string(),
+        Err(_) => interaction.request.url.clone(),
+    }
+}
@@ -1,10 +1,16 @@
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::{io, io::Write};
-use uuid::Uuid;
 
-use super::ForgeRockError;
+use super::{
+    device::DeviceProfile,
+    prompt::{Prompt, PromptKind},
+    ForgeRockError,
+};
+use crate::{
+    environment::Environment,
+    http::HttpClient,
+    redact::{self, Secret},
+};
 
 /// The high-level response format from authentication.
 /// Please refer to the ``authenticate`` function for its format.
@@ -60,49 +66,44 @@ pub struct AuthCredentials {
     pub password: String,
 }
 
-/// The path to the authenticate endpoint using ForgeRock AM.
-const AUTHENTICATE_ENDPOINT: &str =
-    "https://login.toyotadriverslogin.com/json/realms/root/realms/tmna-native/authenticate";
-
 /// Creates and executes the actual authentication request with the given client.
+#[tracing::instrument(name = "authenticate_request", skip_all, fields(status))]
 pub async fn perform_authenticate_request<T: Serialize>(
+    http: &HttpClient,
+    environment: &Environment,
     json: T,
 ) -> Result<AuthenticateFormat, ForgeRockError> {
     // We'll need to serialize our text to begin with.
-    let posted_contents =
-        serde_json::to_string(&json).expect("should have valid JSON to POST with");
-    println!("About to post: {}", posted_contents);
+    let posted_contents = serde_json::to_value(&json).map_err(ForgeRockError::Parse)?;
+    tracing::debug!(body = %redact::redact_json(&posted_contents), "posting authentication request");
 
     // There are several necessary components to our authenticate request:
-    let result = Client::new()
-        .post(AUTHENTICATE_ENDPOINT)
+    let request = http
+        .post(&environment.authenticate_endpoint())
         // We must specify we're POSTing JSON, and an acceptable API version.
         .header("Content-Type", "application/json")
         .header("Accept-API-Version", "resource=2.1, protocol=1.0")
         // ForgeRock documents that you must specify an auth index "type".
-        // We use the "service" type with "OneAppSignIn" so that we can log in.
-        // (For registeration, there is also "OneAppSignUp".)
+        // Our environment specifies which, alongside its value.
         .query(&[
-            ("authIndexType", "service"),
-            ("authIndexValue", "OneAppSignIn"),
+            ("authIndexType", environment.auth_index_type.as_str()),
+            ("authIndexValue", environment.auth_index_value.as_str()),
         ])
-        .body(posted_contents)
-        .send()
-        .await
-        .map_err(ForgeRockError::Reqwest)?;
+        .body(posted_contents.to_string());
+    let result = http.send(request).await.map_err(ForgeRockError::Reqwest)?;
 
     // Let's ensure that we made this request successfully.
     // We (naively) assume that any request resulting in an error
     // will have a non-200 response code.
-    if !result.status().is_success() {
-        // TODO(spotlightishere): Handle this better!
-        println!("Hmm... something has gone awry: {:?}", result.text().await);
-        panic!("Hell has frozen over");
+    let status = result.status();
+    tracing::Span::current().record("status", status.as_u16());
+    let response_text = result.text().await.map_err(ForgeRockError::Reqwest)?;
+    if !status.is_success() {
+        return Err(ForgeRockError::UnexpectedResponse(status, response_text));
     }
 
     // Finally, we can serialize to our expected format.
-    let response_text = result.text().await.map_err(ForgeRockError::Reqwest)?;
-    println!("body: {}", response_text);
+    tracing::debug!(body = %redact::redact_body(&response_text), "received authentication response");
 
     match serde_json::from_str(response_text.as_str()) {
         Ok(body) => Ok(body),
@@ -143,13 +144,20 @@ pub async fn perform_authenticate_request<T: Serialize>(
 /// The client would be expected to send back the *exact same* JSON object, but
 /// with the first input's `value` set to their device locale (e.g. `en-US`).
 /// There are several types of callback types, and we only handle a few.
-pub async fn authenticate(credentials: AuthCredentials) -> Result<String, ForgeRockError> {
+#[tracing::instrument(skip_all, fields(tree = %environment.auth_index_value))]
+pub async fn authenticate(
+    http: &HttpClient,
+    environment: &Environment,
+    credentials: AuthCredentials,
+    device: &DeviceProfile,
+    prompt: &dyn Prompt,
+) -> Result<String, ForgeRockError> {
     // We must now loop through all possible callbacks until we get
     // a final token that we can handle, or until we receive an error.
     //
     // First, make a request with an empty body to obtain our initial callback.
     // We assume that this should always be our authentication format.
-    let mut response = perform_authenticate_request("").await?;
+    let mut response = perform_authenticate_request(http, environment, "").await?;
 
     // Let's loop for no more than 15 times to allow repeating if
     // the user makes a mistake with their username, password, or OTP code.
@@ -166,18 +174,16 @@ pub async fn authenticate(credentials: AuthCredentials) -> Result<String, ForgeR
         // With no provided token, we should continue the authentication tango.
         // If we have no auth ID and no callbacks, then something else is awry.
         if working_body.auth_id.is_some() && working_body.callbacks.is_empty() {
-            // TODO(spotlightishere) Convert to a proper error
-            panic!("No authentication ID, or no callbacks!");
+            return Err(ForgeRockError::Auth);
         };
 
         // We now must handle all callbacks.
         for callback in working_body.callbacks.iter_mut() {
-            callback.process(&credentials);
+            callback.process(environment, &credentials, device, prompt)?;
         }
 
-        // println!("{:?}", working_body);
         // We now make the request once more but with our adapted body.
-        response = perform_authenticate_request(working_body).await?;
+        response = perform_authenticate_request(http, environment, working_body).await?;
         callback_count += 1;
     }
 
@@ -187,21 +193,31 @@ pub async fn authenticate(credentials: AuthCredentials) -> Result<String, ForgeR
 
 impl AuthenticationCallback {
     /// Process and handle all necessary inputs/outputs for this callback.
-    pub fn process(&mut self, credentials: &AuthCredentials) {
+    #[tracing::instrument(name = "callback", skip_all, fields(callback_type = %self.callback_type))]
+    pub fn process(
+        &mut self,
+        environment: &Environment,
+        credentials: &AuthCredentials,
+        device: &DeviceProfile,
+        prompt: &dyn Prompt,
+    ) -> Result<(), ForgeRockError> {
         let callback_type = self.callback_type.as_str();
-        println!("Callback type: {}", self.callback_type);
+        tracing::debug!("processing callback");
 
         // Not every callback type has inputs.
         if callback_type == "TextOutputCallback" {
-            return;
+            return Ok(());
         }
 
         // Frustratingly, not every output has a corresponding input.
         // We'll iterate through pairs and handle as necessary.
-        //
-        // TODO(spotlightishere): Properly determine instead of forcibly unwrapping
-        let mut output_iter = self.output.as_mut().unwrap().iter_mut();
-        let mut input_iter = self.input.as_mut().unwrap().iter_mut();
+        let (Some(outputs), Some(inputs)) = (self.output.as_mut(), self.input.as_mut()) else {
+            return Err(ForgeRockError::MalformedCallback(
+                self.callback_type.clone(),
+            ));
+        };
+        let mut output_iter = outputs.iter_mut();
+        let mut input_iter = inputs.iter_mut();
 
         // TODO(spotlightishere): This design is a mess with all the different types :(
         // Can this design be refactored?
@@ -212,62 +228,38 @@ impl AuthenticationCallback {
                 let prompt_name = &output.value;
                 if prompt_name == "ui_locales" {
                     // We need to set the device's UI locale, e.g. en-US.
-                    // We'll hardcode this for our own sake.
-                    input.value = json!("en-US");
+                    input.value = json!(environment.locale);
                 } else if prompt_name == "User Name" {
                     // We'll use the user's specified name.
                     input.value = json!(credentials.username);
                 } else {
-                    unimplemented!("unknown name callback prompt name: {}", prompt_name)
+                    return Err(ForgeRockError::UnsupportedCallback(format!(
+                        "NameCallback with prompt {prompt_name}"
+                    )));
                 }
             }
             ("PasswordCallback", Some(output), Some(input)) => {
                 // Password callbacks handle both passwords and OTP values.
                 let prompt_name = &output.value;
                 if prompt_name == "Password" {
+                    redact::register_secret(Secret::Password, &credentials.password);
                     input.value = json!(credentials.password);
                 } else if prompt_name == "One Time Password" {
-                    // TODO(spotlightishere): We probably shouldn't be just randomly requesting input here...
-                    let mut otp_code = String::new();
-                    print!("Please enter the OTP code you were just emailed/texted: ");
-                    io::stdout().flush().unwrap();
-
-                    io::stdin()
-                        .read_line(&mut otp_code)
-                        .expect("should be able to read OTP code");
-                    // Remove newline
-                    otp_code.truncate(otp_code.len() - 1);
+                    let otp_code = prompt
+                        .prompt(PromptKind::OneTimePassword)
+                        .map_err(ForgeRockError::Prompt)?;
+                    redact::register_secret(Secret::OneTimePassword, &otp_code);
                     input.value = json!(otp_code);
                 } else {
-                    unimplemented!("unknown password callback prompt name: {}", prompt_name)
+                    return Err(ForgeRockError::UnsupportedCallback(format!(
+                        "PasswordCallback with prompt {prompt_name}"
+                    )));
                 }
             }
             ("HiddenValueCallback", _, Some(input)) => {
                 // TODO(spotlightishere) There's likely more than one possible value than `devicePrint`
                 // with HiddenValueCallback, but this appears to be the only one handled by the SDK as of writing.
-                let hardware_id = Uuid::new_v4();
-                // The fingerprint must be a string containing JSON.
-                let device_fingerprint = json!({
-                    "appId": "com.toyota.oneapp",
-                    "biometricEnabled": "false",
-                    "deviceType": "Android",
-                    // Oddly, this value is hardcoded to "real".
-                    "emulator": "real",
-                    "geolocation": null,
-                    // A randomly generated UUID, not persisted.
-                    "hardwareId": hardware_id,
-                    "language": "en",
-                    "model": "Pixel",
-                    // The device's brand string, along with its build user.
-                    "brand": "Google android-build",
-                    "pushTokenId": null,
-                    // The SDK/API version.
-                    "systemOS": "34",
-                    "timeZone": "America/New_York"
-                })
-                .to_string();
-
-                input.value = json!(device_fingerprint);
+                input.value = json!(device.fingerprint(&environment.app_id));
             }
             ("ChoiceCallback", _, _) => {
                 // Observed choices have been related to password resets,
@@ -282,9 +274,12 @@ impl AuthenticationCallback {
                 // TODO(spotlightishere): Change if necessary
             }
             (_, _, _) => {
-                println!("{:?}", self);
-                unimplemented!("unknown callback type: {}", callback_type);
+                return Err(ForgeRockError::UnsupportedCallback(
+                    self.callback_type.clone(),
+                ));
             }
         }
+
+        Ok(())
     }
 }
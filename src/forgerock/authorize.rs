@@ -1,45 +1,48 @@
-use super::{ForgeRockError, OAUTH_CLIENT_ID, OAUTH_REDIRECT_URI};
+use super::ForgeRockError;
+use crate::{environment::Environment, http::HttpClient};
 use reqwest::{header, StatusCode};
 use std::collections::HashMap;
 use url::Url;
 
-/// The path to authorization endpoint via ForgeRock AM.
-/// ("Authorize" should be read in the context of OAuth2, and not the previous custom authentication flow.)
-const AUTHORIZATION_ENDPOINT: &str =
-    "https://login.toyotadriverslogin.com/oauth2/realms/root/realms/tmna-native/authorize";
-
 /// Performs OAuth2 authorization, obtaining a code we can exchange for an access token.
-pub async fn perform_authorize_request(token_id: String) -> Result<String, ForgeRockError> {
-    let result = reqwest::Client::new()
-        .get(AUTHORIZATION_ENDPOINT)
+#[tracing::instrument(name = "authorize", skip_all, fields(status))]
+pub async fn perform_authorize_request(
+    http: &HttpClient,
+    environment: &Environment,
+    token_id: String,
+) -> Result<String, ForgeRockError> {
+    let request = http
+        .get(&environment.authorization_endpoint())
         // We only have to deviate once here: we must set our obtained token as a cookie.
         .header(header::COOKIE, format!("iPlanetDirectoryPro={token_id}"))
         // Standard OAuth2 query parameters.
         .query(&[
-            ("client_id", OAUTH_CLIENT_ID),
+            ("client_id", environment.oauth_client_id.as_str()),
             ("scope", "openid profile write"),
             ("response_type", "code"),
-            ("redirect_uri", OAUTH_REDIRECT_URI),
+            ("redirect_uri", environment.oauth_redirect_uri.as_str()),
             ("code_challenge", "plain"),
             ("code_challenge_method", "plain"),
-        ])
-        .send()
-        .await
-        .map_err(ForgeRockError::Reqwest)?;
+        ]);
+    let result = http.send(request).await.map_err(ForgeRockError::Reqwest)?;
 
     // We should be given 302 Found, and redirected to the OAuth2 URL.
-    if result.status() != StatusCode::FOUND {
-        panic!("Invalid authorization request response!");
+    let status = result.status();
+    tracing::Span::current().record("status", status.as_u16());
+    if status != StatusCode::FOUND {
+        let response_text = result.text().await.map_err(ForgeRockError::Reqwest)?;
+        return Err(ForgeRockError::UnexpectedResponse(status, response_text));
     }
     let Some(location_header) = result.headers().get(header::LOCATION) else {
-        panic!("Unable to find redirection location in authorization request response!");
+        return Err(ForgeRockError::OAuth2);
     };
 
     // We should now be able to parse this location.
-    let location_str = location_header
+    let location = location_header
         .to_str()
-        .expect("should be able to obtain location header string");
-    let location = Url::parse(location_str).expect("should be able to parse location header URL");
+        .ok()
+        .and_then(|location_str| Url::parse(location_str).ok())
+        .ok_or(ForgeRockError::OAuth2)?;
 
     // Our OAuth2 authorization code should be present within the "code" query parameter.
     let query_parameters: HashMap<String, String> = location.query_pairs().into_owned().collect();
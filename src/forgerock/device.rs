@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::storage::{CredentialStore, StorageError, StorageKey};
+
+/// The device we present ourselves as during authentication.
+///
+/// Previously, we generated a new hardware ID for every login.
+/// Persisting it allows a session to move between machines while
+/// still appearing as the same device to ForgeRock.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceProfile {
+    /// A randomly generated UUID, persisted alongside our credentials.
+    pub hardware_id: Uuid,
+    /// The device's model name.
+    pub model: String,
+    /// The device's brand string, along with its build user.
+    pub brand: String,
+    /// The SDK/API version.
+    pub system_os: String,
+    /// The device's time zone.
+    pub time_zone: String,
+}
+
+impl DeviceProfile {
+    /// Creates a new profile with a random hardware ID.
+    pub fn generate() -> Self {
+        Self {
+            hardware_id: Uuid::new_v4(),
+            model: "Pixel".to_string(),
+            brand: "Google android-build".to_string(),
+            system_os: "34".to_string(),
+            time_zone: "America/New_York".to_string(),
+        }
+    }
+
+    /// Loads our persisted device profile, creating one if necessary.
+    pub fn load_or_create(store: &dyn CredentialStore) -> Self {
+        if let Ok(Some(contents)) = store.load(StorageKey::DeviceProfile) {
+            if let Ok(profile) = serde_json::from_str(&contents) {
+                return profile;
+            }
+        }
+
+        // We'll persist this profile so future logins remain consistent.
+        // If we're unable to, we can still continue with this one.
+        let profile = Self::generate();
+        let _ = profile.save(store);
+        profile
+    }
+
+    /// Persists this device profile to the given store.
+    pub fn save(&self, store: &dyn CredentialStore) -> Result<(), StorageError> {
+        let contents =
+            serde_json::to_string(&self).expect("device profile should always be serializable");
+        store.store(StorageKey::DeviceProfile, &contents)
+    }
+
+    /// The fingerprint expected by `HiddenValueCallback`'s `devicePrint`.
+    /// The fingerprint must be a string containing JSON.
+    pub fn fingerprint(&self, app_id: &str) -> String {
+        json!({
+            "appId": app_id,
+            "biometricEnabled": "false",
+            "deviceType": "Android",
+            // Oddly, this value is hardcoded to "real".
+            "emulator": "real",
+            "geolocation": null,
+            "hardwareId": self.hardware_id,
+            "language": "en",
+            "model": self.model,
+            "brand": self.brand,
+            "pushTokenId": null,
+            "systemOS": self.system_os,
+            "timeZone": self.time_zone
+        })
+        .to_string()
+    }
+}
@@ -1,57 +1,692 @@
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
-use serde::Deserialize;
-use std::str;
-use std::time::{SystemTime, UNIX_EPOCH};
+use p256::ecdsa::{signature::Verifier, Signature as EcdsaSignature, VerifyingKey};
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::{
+    fmt, fs, io,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use super::ForgeRockError;
+use crate::{environment::Environment, http::HttpClient};
 
-/// We need the `sub` value from our token as a GUID within the API.
-#[derive(Deserialize)]
-struct TokenData {
-    pub sub: String,
-    pub exp: u64,
+/// Possible errors while decoding or verifying a JWT.
+#[derive(Debug)]
+pub enum JwtError {
+    /// The token did not have three `.`-separated components.
+    Malformed,
+    Base64(base64::DecodeError),
+    Json(serde_json::Error),
+    /// A claim we require was not present.
+    MissingClaim(&'static str),
+    Expired,
+    NotYetValid,
+    /// We don't know how to verify signatures made with this algorithm.
+    UnsupportedAlgorithm(String),
+    /// The realm's JWKS contains no key matching this token.
+    UnknownKey(Option<String>),
+    /// A key within the JWKS could not be used.
+    InvalidKey(String),
+    InvalidSignature,
+    /// We were unable to obtain the realm's JWKS.
+    Reqwest(reqwest::Error),
+    /// We were unable to read or write our cached JWKS.
+    Io(io::Error),
 }
 
-/// An extraordinarily, hilariously basic JWT parser.
-pub fn get_sub(token: &str) -> Result<String, ForgeRockError> {
-    // There's three components to a JWT: its header, its payload, and signature.
-    // These are separated by `.`s, and are all JSON encoded.
-    //
-    // First, let's separate these parts from our string.
-    let components: Vec<&str> = token.split('.').collect();
-    if components.len() != 3 {
-        return Err(ForgeRockError::InvalidToken);
-    }
-
-    // We don't care too much about validating the JWT here,
-    // as the API itself will do all of that for us.
-    // If the user provides us a fake JWT, that's not for us to handle.
-    //
-    // As such, we'll ignore the header and signature, and only parse the payload.
-    let encoded_payload = components[1];
-
-    // Components of JWTs are base64-encoded JSON strings, using the URL-safe, non-padded character set.
-    //
-    // These all produce errors we don't particularly care about handling in specific,
-    // so let's just blankly map them away. It looks rather disgusting, but it works...
-    let decoded_payload = URL_SAFE_NO_PAD
-        .decode(encoded_payload)
-        .expect("should be able to decode JWT payload");
-    let payload_json =
-        str::from_utf8(&decoded_payload).expect("should be able to decode JWT payload");
-    let payload_contents: TokenData =
-        serde_json::from_str(payload_json).expect("should be able to parse JWT payload");
-
-    // The only validation we'll do: let's evaluate our expiry.
-    let current_timestamp = SystemTime::now()
+impl fmt::Display for JwtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwtError::Malformed => write!(f, "token is not a JWT"),
+            JwtError::Base64(err) => write!(f, "unable to decode token: {err}"),
+            JwtError::Json(err) => write!(f, "unable to parse token: {err}"),
+            JwtError::MissingClaim(claim) => write!(f, "token is missing its `{claim}` claim"),
+            JwtError::Expired => write!(f, "token has expired"),
+            JwtError::NotYetValid => write!(f, "token is not yet valid"),
+            JwtError::UnsupportedAlgorithm(alg) => write!(f, "unsupported algorithm {alg}"),
+            JwtError::UnknownKey(Some(kid)) => write!(f, "no known key with ID {kid}"),
+            JwtError::UnknownKey(None) => write!(f, "no known key to verify with"),
+            JwtError::InvalidKey(reason) => write!(f, "invalid key: {reason}"),
+            JwtError::InvalidSignature => write!(f, "signature is invalid"),
+            JwtError::Reqwest(err) => write!(f, "unable to obtain JWKS: {err}"),
+            JwtError::Io(err) => write!(f, "unable to access cached JWKS: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for JwtError {}
+
+/// The JOSE header of a JWT.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct JwtHeader {
+    /// The signing algorithm, such as `RS256`.
+    pub alg: String,
+    /// The ID of the key used to sign this token, within the realm's JWKS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub typ: Option<String>,
+}
+
+/// The audience of a token, which may be one or many.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// The registered claims of a JWT, alongside any others.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Claims {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// For ForgeRock, this is the user's GUID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<Audience>,
+    /// When this token expires, as a Unix timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>,
+    /// When this token becomes valid, as a Unix timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<u64>,
+    /// When this token was issued, as a Unix timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    /// Every other claim, such as `name` or `email` within ID tokens.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A decoded, but not necessarily verified, JWT.
+#[derive(Debug, Clone)]
+pub struct Jwt {
+    pub header: JwtHeader,
+    pub claims: Claims,
+    /// The encoded header and payload, over which the signature is made.
+    signing_input: String,
+    signature: Vec<u8>,
+}
+
+/// The current Unix timestamp.
+fn now() -> u64 {
+    SystemTime::now()
         .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    let expiry = payload_contents.exp;
-    if current_timestamp >= expiry {
-        return Err(ForgeRockError::ExpiredToken);
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Decodes a single base64url-encoded JSON component.
+fn decode_component<T: DeserializeOwned>(component: &str) -> Result<T, JwtError> {
+    let decoded = URL_SAFE_NO_PAD
+        .decode(component)
+        .map_err(JwtError::Base64)?;
+    serde_json::from_slice(&decoded).map_err(JwtError::Json)
+}
+
+impl Jwt {
+    /// Decodes the given token, without validating it whatsoever.
+    pub fn decode(token: &str) -> Result<Self, JwtError> {
+        // There's three components to a JWT: its header, its payload, and signature.
+        // These are separated by `.`s, and are all base64url encoded.
+        let components: Vec<&str> = token.split('.').collect();
+        let [header, payload, signature] = components[..] else {
+            return Err(JwtError::Malformed);
+        };
+
+        Ok(Self {
+            header: decode_component(header)?,
+            claims: decode_component(payload)?,
+            signing_input: format!("{header}.{payload}"),
+            signature: URL_SAFE_NO_PAD
+                .decode(signature)
+                .map_err(JwtError::Base64)?,
+        })
+    }
+
+    /// Ensures this token is currently within its validity period.
+    pub fn validate_time(&self) -> Result<(), JwtError> {
+        let current_timestamp = now();
+        if self
+            .claims
+            .exp
+            .is_some_and(|expiry| current_timestamp >= expiry)
+        {
+            return Err(JwtError::Expired);
+        }
+        if self
+            .claims
+            .nbf
+            .is_some_and(|not_before| current_timestamp < not_before)
+        {
+            return Err(JwtError::NotYetValid);
+        }
+        Ok(())
+    }
+
+    /// How long until this token expires, if it has an expiry and has not yet expired.
+    pub fn expires_in(&self) -> Option<Duration> {
+        let expiry = self.claims.exp?;
+        expiry.checked_sub(now()).map(Duration::from_secs)
+    }
+
+    /// Verifies this token's signature against the given key set.
+    pub fn verify(&self, jwks: &Jwks) -> Result<(), JwtError> {
+        let key = jwks.find(self.header.kid.as_deref(), &self.header.alg)?;
+        let message = self.signing_input.as_bytes();
+
+        match self.header.alg.as_str() {
+            "RS256" => key.verify_rsa(
+                Pkcs1v15Sign::new::<Sha256>(),
+                &Sha256::digest(message),
+                &self.signature,
+            ),
+            "RS384" => key.verify_rsa(
+                Pkcs1v15Sign::new::<Sha384>(),
+                &Sha384::digest(message),
+                &self.signature,
+            ),
+            "RS512" => key.verify_rsa(
+                Pkcs1v15Sign::new::<Sha512>(),
+                &Sha512::digest(message),
+                &self.signature,
+            ),
+            "ES256" => key.verify_es256(message, &self.signature),
+            // Notably, this includes `none`.
+            other => Err(JwtError::UnsupportedAlgorithm(other.to_string())),
+        }
+    }
+}
+
+/// A JSON Web Key, as published within the realm's JWKS.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Jwk {
+    /// The key type, such as `RSA` or `EC`.
+    pub kty: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
+    /// RSA modulus.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    /// RSA exponent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    /// Elliptic curve, such as `P-256`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+}
+
+/// Decodes a base64url-encoded key parameter.
+fn key_parameter(parameter: &Option<String>, name: &str) -> Result<Vec<u8>, JwtError> {
+    let parameter = parameter
+        .as_deref()
+        .ok_or_else(|| JwtError::InvalidKey(format!("missing `{name}`")))?;
+    URL_SAFE_NO_PAD
+        .decode(parameter)
+        .map_err(|err| JwtError::InvalidKey(format!("`{name}` is not base64: {err}")))
+}
+
+impl Jwk {
+    fn verify_rsa(
+        &self,
+        scheme: Pkcs1v15Sign,
+        hashed: &[u8],
+        signature: &[u8],
+    ) -> Result<(), JwtError> {
+        if self.kty != "RSA" {
+            return Err(JwtError::InvalidKey(format!(
+                "expected RSA, not {}",
+                self.kty
+            )));
+        }
+        let n = BigUint::from_bytes_be(&key_parameter(&self.n, "n")?);
+        let e = BigUint::from_bytes_be(&key_parameter(&self.e, "e")?);
+        let key = RsaPublicKey::new(n, e).map_err(|err| JwtError::InvalidKey(err.to_string()))?;
+        key.verify(scheme, hashed, signature)
+            .map_err(|_| JwtError::InvalidSignature)
+    }
+
+    fn verify_es256(&self, message: &[u8], signature: &[u8]) -> Result<(), JwtError> {
+        if self.kty != "EC" || self.crv.as_deref() != Some("P-256") {
+            return Err(JwtError::InvalidKey("expected a P-256 key".to_string()));
+        }
+
+        // Uncompressed SEC1 points are prefixed by 0x04.
+        let mut point = vec![0x04];
+        point.extend(key_parameter(&self.x, "x")?);
+        point.extend(key_parameter(&self.y, "y")?);
+        let key = VerifyingKey::from_sec1_bytes(&point)
+            .map_err(|err| JwtError::InvalidKey(err.to_string()))?;
+
+        // JWS signatures are the raw `r || s` concatenation.
+        let signature =
+            EcdsaSignature::from_slice(signature).map_err(|_| JwtError::InvalidSignature)?;
+        key.verify(message, &signature)
+            .map_err(|_| JwtError::InvalidSignature)
+    }
+}
+
+/// A JSON Web Key Set, as published by the realm.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+impl Jwks {
+    /// Finds the key to verify a token with the given key ID and algorithm.
+    ///
+    /// Should the token lack a key ID, we'll accept the sole key for its algorithm.
+    fn find(&self, kid: Option<&str>, alg: &str) -> Result<&Jwk, JwtError> {
+        let compatible = |key: &&Jwk| key.alg.as_deref().is_none_or(|key_alg| key_alg == alg);
+        match kid {
+            Some(kid) => self
+                .keys
+                .iter()
+                .filter(compatible)
+                .find(|key| key.kid.as_deref() == Some(kid)),
+            None => {
+                let mut candidates = self.keys.iter().filter(compatible);
+                match (candidates.next(), candidates.next()) {
+                    (Some(key), None) => Some(key),
+                    _ => None,
+                }
+            }
+        }
+        .ok_or_else(|| JwtError::UnknownKey(kid.map(str::to_string)))
+    }
+}
+
+/// The realm's JWKS, cached on disk.
+///
+/// We refresh our cache once it's older than `max_age`, or whenever
+/// a token references a key we don't yet know about (i.e. after rotation).
+#[derive(Debug, Clone)]
+pub struct JwksCache {
+    path: PathBuf,
+    max_age: Duration,
+}
+
+impl JwksCache {
+    /// How long a cached JWKS is used by default.
+    pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+    /// Caches within the given file.
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            max_age: Self::DEFAULT_MAX_AGE,
+        }
+    }
+
+    /// Caches within the user's cache directory, separately per host and realm.
+    pub fn for_environment(environment: &Environment) -> Option<Self> {
+        let host = environment.forgerock_base.host_str().unwrap_or("unknown");
+        let name = format!("jwks-{host}-{}.json", environment.realm).replace(
+            |c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '.',
+            "_",
+        );
+        let path = dirs::cache_dir()?.join("toyotactl").join(name);
+        Some(Self::new(path))
+    }
+
+    /// Overrides how long our cached JWKS is used.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Our cached JWKS, if present and fresh.
+    fn cached(&self) -> Option<Jwks> {
+        let metadata = fs::metadata(&self.path).ok()?;
+        let age = metadata.modified().ok()?.elapsed().ok()?;
+        if age > self.max_age {
+            return None;
+        }
+        let contents = fs::read(&self.path).ok()?;
+        serde_json::from_slice(&contents).ok()
+    }
+
+    /// Obtains the realm's JWKS anew, replacing our cache.
+    pub async fn refresh(
+        &self,
+        http: &HttpClient,
+        environment: &Environment,
+    ) -> Result<Jwks, JwtError> {
+        let request = http.get(&environment.jwks_endpoint());
+        let response = http
+            .send(request)
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(JwtError::Reqwest)?;
+        let contents = response.bytes().await.map_err(JwtError::Reqwest)?;
+        let jwks: Jwks = serde_json::from_slice(&contents).map_err(JwtError::Json)?;
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(JwtError::Io)?;
+        }
+        fs::write(&self.path, &contents).map_err(JwtError::Io)?;
+        Ok(jwks)
+    }
+
+    /// Decodes the given token, ensuring it's both current and signed by the realm.
+    pub async fn verify(
+        &self,
+        http: &HttpClient,
+        environment: &Environment,
+        token: &str,
+    ) -> Result<Jwt, JwtError> {
+        let jwt = Jwt::decode(token)?;
+        jwt.validate_time()?;
+
+        let jwks = match self.cached() {
+            Some(jwks) => jwks,
+            None => self.refresh(http, environment).await?,
+        };
+        match jwt.verify(&jwks) {
+            // The realm may have rotated its keys since we last cached them.
+            Err(JwtError::UnknownKey(_)) => {
+                let jwks = self.refresh(http, environment).await?;
+                jwt.verify(&jwks)?;
+            }
+            result => result?,
+        }
+        Ok(jwt)
+    }
+}
+
+/// We need the `sub` value from our token as a GUID within the API.
+///
+/// We don't verify the token's signature here, as the API itself will do so for us.
+/// We will, however, ensure that it has not yet expired.
+pub fn get_sub(token: &str) -> Result<String, ForgeRockError> {
+    let jwt = Jwt::decode(token).map_err(ForgeRockError::InvalidToken)?;
+    match jwt.validate_time() {
+        Ok(()) => {}
+        Err(JwtError::Expired) => return Err(ForgeRockError::ExpiredToken),
+        Err(err) => return Err(ForgeRockError::InvalidToken(err)),
+    }
+    jwt.claims
+        .sub
+        .ok_or(ForgeRockError::InvalidToken(JwtError::MissingClaim("sub")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::{signature::Signer, SigningKey};
+    use rand::rngs::OsRng;
+    use rsa::{traits::PublicKeyParts, RsaPrivateKey};
+    use serde_json::{json, Value};
+    use std::sync::{Arc, OnceLock};
+
+    use crate::{
+        fixture::{Fixture, Interaction, RecordedRequest, RecordedResponse, Replayer},
+        http::HttpConfig,
     };
 
-    // We're done! All we need is the sub (used as a GUID within the API).
-    Ok(payload_contents.sub)
+    /// RSA key generation is slow without optimizations, so we'll share one.
+    fn rsa_key() -> &'static RsaPrivateKey {
+        static KEY: OnceLock<RsaPrivateKey> = OnceLock::new();
+        KEY.get_or_init(|| RsaPrivateKey::new(&mut OsRng, 2048).unwrap())
+    }
+
+    fn rsa_jwk(kid: &str) -> Jwk {
+        let key = rsa_key();
+        Jwk {
+            kty: "RSA".to_string(),
+            kid: Some(kid.to_string()),
+            alg: Some("RS256".to_string()),
+            n: Some(URL_SAFE_NO_PAD.encode(key.n().to_bytes_be())),
+            e: Some(URL_SAFE_NO_PAD.encode(key.e().to_bytes_be())),
+            crv: None,
+            x: None,
+            y: None,
+        }
+    }
+
+    fn ec_jwk(key: &SigningKey, kid: &str) -> Jwk {
+        let point = key.verifying_key().to_encoded_point(false);
+        Jwk {
+            kty: "EC".to_string(),
+            kid: Some(kid.to_string()),
+            alg: Some("ES256".to_string()),
+            n: None,
+            e: None,
+            crv: Some("P-256".to_string()),
+            x: Some(URL_SAFE_NO_PAD.encode(point.x().unwrap())),
+            y: Some(URL_SAFE_NO_PAD.encode(point.y().unwrap())),
+        }
+    }
+
+    fn claims() -> Value {
+        json!({ "sub": "guid", "exp": now() + 3600 })
+    }
+
+    fn signing_input(header: Value, claims: Value) -> String {
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        )
+    }
+
+    fn rs256(kid: &str, claims: Value) -> String {
+        let input = signing_input(json!({ "alg": "RS256", "kid": kid }), claims);
+        let signature = rsa_key()
+            .sign(
+                Pkcs1v15Sign::new::<Sha256>(),
+                &Sha256::digest(input.as_bytes()),
+            )
+            .unwrap();
+        format!("{input}.{}", URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    fn es256(key: &SigningKey, kid: &str, claims: Value) -> String {
+        let input = signing_input(json!({ "alg": "ES256", "kid": kid }), claims);
+        let signature: EcdsaSignature = key.sign(input.as_bytes());
+        format!("{input}.{}", URL_SAFE_NO_PAD.encode(signature.to_bytes()))
+    }
+
+    /// Replaces the signature of the given token with one for a single flipped bit.
+    fn tamper(token: &str) -> String {
+        let (input, signature) = token.rsplit_once('.').unwrap();
+        let mut signature = URL_SAFE_NO_PAD.decode(signature).unwrap();
+        signature[0] ^= 1;
+        format!("{input}.{}", URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    fn verify(token: &str, keys: Vec<Jwk>) -> Result<(), JwtError> {
+        Jwt::decode(token)?.verify(&Jwks { keys })
+    }
+
+    #[test]
+    fn rs256_verifies() {
+        let token = rs256("rsa", claims());
+        verify(&token, vec![rsa_jwk("rsa")]).unwrap();
+        assert!(matches!(
+            verify(&tamper(&token), vec![rsa_jwk("rsa")]),
+            Err(JwtError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn es256_verifies() {
+        let key = SigningKey::random(&mut OsRng);
+        let token = es256(&key, "ec", claims());
+        verify(&token, vec![ec_jwk(&key, "ec")]).unwrap();
+        assert!(matches!(
+            verify(&tamper(&token), vec![ec_jwk(&key, "ec")]),
+            Err(JwtError::InvalidSignature)
+        ));
+
+        // Signed by another key under the same ID.
+        let other = SigningKey::random(&mut OsRng);
+        assert!(matches!(
+            verify(&token, vec![ec_jwk(&other, "ec")]),
+            Err(JwtError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn alg_none_is_rejected() {
+        let key = SigningKey::random(&mut OsRng);
+        let input = signing_input(json!({ "alg": "none", "kid": "ec" }), claims());
+        let mut keys = vec![ec_jwk(&key, "ec")];
+        keys[0].alg = None;
+
+        for token in [format!("{input}."), format!("{input}.c2ln")] {
+            assert!(matches!(
+                verify(&token, keys.clone()),
+                Err(JwtError::UnsupportedAlgorithm(alg)) if alg == "none"
+            ));
+        }
+    }
+
+    #[test]
+    fn algorithm_mismatch_is_rejected() {
+        let key = SigningKey::random(&mut OsRng);
+
+        // The key declares another algorithm, so it's no match.
+        let token = rs256("ec", claims());
+        assert!(matches!(
+            verify(&token, vec![ec_jwk(&key, "ec")]),
+            Err(JwtError::UnknownKey(Some(kid))) if kid == "ec"
+        ));
+
+        // Without declaring one, its type must still suit the token's algorithm.
+        let mut rsa = rsa_jwk("rsa");
+        rsa.alg = None;
+        let token = es256(&key, "rsa", claims());
+        assert!(matches!(
+            verify(&token, vec![rsa]),
+            Err(JwtError::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_kid_is_rejected() {
+        let token = rs256("rotated", claims());
+        assert!(matches!(
+            verify(&token, vec![rsa_jwk("rsa")]),
+            Err(JwtError::UnknownKey(Some(kid))) if kid == "rotated"
+        ));
+    }
+
+    #[test]
+    fn malformed_tokens() {
+        let valid = rs256("rsa", claims());
+        let (header, rest) = valid.split_once('.').unwrap();
+
+        assert!(matches!(Jwt::decode("a.b"), Err(JwtError::Malformed)));
+        assert!(matches!(
+            Jwt::decode(&format!("{valid}.extra")),
+            Err(JwtError::Malformed)
+        ));
+        assert!(matches!(
+            Jwt::decode(&format!("not!base64.{rest}")),
+            Err(JwtError::Base64(_))
+        ));
+        let not_json = URL_SAFE_NO_PAD.encode("not json");
+        assert!(matches!(
+            Jwt::decode(&format!("{not_json}.{rest}")),
+            Err(JwtError::Json(_))
+        ));
+        // Every token must declare its algorithm.
+        let no_alg = URL_SAFE_NO_PAD.encode(json!({ "typ": "JWT" }).to_string());
+        assert!(matches!(
+            Jwt::decode(&format!("{no_alg}.{rest}")),
+            Err(JwtError::Json(_))
+        ));
+        assert!(Jwt::decode(&format!("{header}.{rest}")).is_ok());
+    }
+
+    #[test]
+    fn expired_tokens() {
+        let expired = rs256("rsa", json!({ "sub": "guid", "exp": now() - 60 }));
+        let jwt = Jwt::decode(&expired).unwrap();
+        assert!(matches!(jwt.validate_time(), Err(JwtError::Expired)));
+        assert_eq!(jwt.expires_in(), None);
+        assert!(matches!(
+            get_sub(&expired),
+            Err(ForgeRockError::ExpiredToken)
+        ));
+
+        let early = rs256("rsa", json!({ "sub": "guid", "nbf": now() + 60 }));
+        let jwt = Jwt::decode(&early).unwrap();
+        assert!(matches!(jwt.validate_time(), Err(JwtError::NotYetValid)));
+
+        let current = rs256("rsa", claims());
+        assert_eq!(get_sub(&current).unwrap(), "guid");
+    }
+
+    /// A client answering the given JWKS fetches, in order, without a network.
+    fn serving(jwks: &[&Jwks]) -> (HttpClient, Arc<Replayer>) {
+        let environment = Environment::production();
+        let interactions = jwks
+            .iter()
+            .map(|jwks| Interaction {
+                request: RecordedRequest {
+                    method: "GET".to_string(),
+                    url: environment.jwks_endpoint(),
+                    body: None,
+                },
+                response: RecordedResponse {
+                    status: 200,
+                    headers: vec![],
+                    body: serde_json::to_string(jwks).unwrap(),
+                },
+            })
+            .collect();
+        let replayer = Arc::new(Replayer::new(Fixture { interactions }));
+        let http = HttpClient::new(&HttpConfig::default())
+            .unwrap()
+            .with_replayer(Arc::clone(&replayer));
+        (http, replayer)
+    }
+
+    #[tokio::test]
+    async fn cache_refetches_after_rotation() {
+        let path = std::env::temp_dir().join(format!("toyotactl-jwks-{}.json", std::process::id()));
+        let cache = JwksCache::new(path.clone());
+        let environment = Environment::production();
+
+        // We've cached our realm's previous key, but it has since rotated to another.
+        let previous = Jwks {
+            keys: vec![rsa_jwk("previous")],
+        };
+        fs::write(&path, serde_json::to_vec(&previous).unwrap()).unwrap();
+        let rotated = SigningKey::random(&mut OsRng);
+        let current = Jwks {
+            keys: vec![rsa_jwk("previous"), ec_jwk(&rotated, "current")],
+        };
+        let (http, replayer) = serving(&[&current, &current]);
+
+        let token = es256(&rotated, "current", claims());
+        let verified = cache.verify(&http, &environment, &token).await;
+        let fetched_twice = replayer.is_exhausted();
+
+        // Keys we still don't know after refetching remain unknown.
+        let unknown = es256(&rotated, "unknown", claims());
+        let unknown = cache.verify(&http, &environment, &unknown).await;
+        let cached = cache.cached();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(verified.unwrap().claims.sub.as_deref(), Some("guid"));
+        assert!(!fetched_twice, "a single refetch should suffice");
+        assert!(matches!(unknown, Err(JwtError::UnknownKey(Some(kid))) if kid == "unknown"));
+        assert!(replayer.is_exhausted());
+        assert_eq!(cached.unwrap().keys.len(), 2);
+    }
 }
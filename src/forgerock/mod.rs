@@ -1,8 +1,15 @@
 mod authenticate;
 mod authorize;
+mod device;
 mod jwt;
 mod oauth_client;
+mod prompt;
 mod storage;
+mod userinfo;
+
+use crate::storage::StorageError;
+use reqwest::StatusCode;
+use std::{fmt, io};
 
 /// Possible error types while working with ForgeRock.
 #[derive(Debug)]
@@ -11,14 +18,53 @@ pub enum ForgeRockError {
     Reqwest(reqwest::Error),
     Parse(serde_json::Error),
     OAuth2,
-    InvalidToken,
+    /// A token could not be decoded or used.
+    InvalidToken(JwtError),
     ExpiredToken,
+    /// ForgeRock responded with an unexpected status code and body.
+    UnexpectedResponse(StatusCode, String),
+    /// We were provided a callback we do not know how to handle.
+    UnsupportedCallback(String),
+    /// We were provided a callback without its expected inputs or outputs.
+    MalformedCallback(String),
+    /// We were unable to prompt the user for information.
+    Prompt(io::Error),
+    Storage(StorageError),
 }
 
-/// The shared redirect URI across all OAuth2 requests.
-pub const OAUTH_REDIRECT_URI: &str = "com.toyota.oneapp:/oauth2Callback";
+impl fmt::Display for ForgeRockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ForgeRockError::Auth => write!(f, "unable to authenticate"),
+            ForgeRockError::Reqwest(err) => write!(f, "request failed: {err}"),
+            ForgeRockError::Parse(err) => write!(f, "unable to parse response: {err}"),
+            ForgeRockError::OAuth2 => write!(f, "unable to obtain OAuth2 authorization code"),
+            ForgeRockError::InvalidToken(err) => write!(f, "invalid token: {err}"),
+            ForgeRockError::ExpiredToken => write!(f, "expired token"),
+            ForgeRockError::UnexpectedResponse(status, body) => {
+                write!(f, "unexpected response {status}: {body}")
+            }
+            ForgeRockError::UnsupportedCallback(callback) => {
+                write!(f, "unsupported callback: {callback}")
+            }
+            ForgeRockError::MalformedCallback(callback) => {
+                write!(f, "malformed callback: {callback}")
+            }
+            ForgeRockError::Prompt(err) => write!(f, "unable to prompt for input: {err}"),
+            ForgeRockError::Storage(err) => write!(f, "{err}"),
+        }
+    }
+}
 
-/// Simialrly, the shared client ID across all OAuth2 requests.
-pub const OAUTH_CLIENT_ID: &str = "oneappsdkclient";
+impl std::error::Error for ForgeRockError {}
 
-pub use storage::login;
+pub use authenticate::{
+    authenticate, AuthCredentials, AuthenticateFormat, AuthenticationCallback, ValuePair,
+};
+pub use authorize::perform_authorize_request;
+pub use device::DeviceProfile;
+pub use jwt::{get_sub, Audience, Claims, Jwk, Jwks, JwksCache, Jwt, JwtError, JwtHeader};
+pub use oauth_client::{obtain_access_token, refresh_tokens};
+pub use prompt::{Prompt, PromptKind, TerminalPrompt};
+pub use storage::{login, refresh, request_username_password, CredentialStorage};
+pub use userinfo::{userinfo, UserInfo};
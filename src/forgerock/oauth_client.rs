@@ -1,73 +1,83 @@
-use super::{storage::CredentialStorage, ForgeRockError, OAUTH_CLIENT_ID, OAUTH_REDIRECT_URI};
-
-/// The endpoint leveraged for obtaining an access token.
-const ACCESS_TOKEN_ENDPOINT: &str =
-    "https://login.toyotadriverslogin.com/oauth2/realms/root/realms/tmna-native/access_token";
+use super::{storage::CredentialStorage, ForgeRockError};
+use crate::{
+    environment::Environment,
+    http::HttpClient,
+    redact::{self, Secret},
+};
 
 /// Attempt to obtain an access token via OAuth2.
 /// We authenticate via the `token_id` obtained within the authentication flow.
+#[tracing::instrument(
+    name = "token_exchange",
+    skip_all,
+    fields(grant_type = "authorization_code", status)
+)]
 pub async fn obtain_access_token(
+    http: &HttpClient,
+    environment: &Environment,
     authorize_code: String,
 ) -> Result<CredentialStorage, ForgeRockError> {
-    let result = reqwest::Client::new()
-        .post(ACCESS_TOKEN_ENDPOINT)
-        .query(&[
-            ("client_id", OAUTH_CLIENT_ID),
-            ("redirect_uri", OAUTH_REDIRECT_URI),
-            ("grant_type", "authorization_code"),
-            ("code_verifier", "plain"),
-            ("code", &authorize_code),
-        ])
-        .send()
-        .await
-        .map_err(ForgeRockError::Reqwest)?;
+    redact::register_secret(Secret::AuthorizationCode, &authorize_code);
+    let request = http.post(&environment.access_token_endpoint()).query(&[
+        ("client_id", environment.oauth_client_id.as_str()),
+        ("redirect_uri", environment.oauth_redirect_uri.as_str()),
+        ("grant_type", "authorization_code"),
+        ("code_verifier", "plain"),
+        ("code", &authorize_code),
+    ]);
+    let result = http.send(request).await.map_err(ForgeRockError::Reqwest)?;
 
     // We'll reuse CredentialStorage from the primary `forgerock` module
     // because it also has `access_token` and `refresh_token` fields,
     // which is all we need to care about from this response.
-    if !result.status().is_success() {
-        // TODO(spotlightishere): Handle this better!
-        println!("Hmm... something has gone awry: {:?}", result.text().await);
-        panic!("Hell has frozen over");
-    }
-
+    let status = result.status();
+    tracing::Span::current().record("status", status.as_u16());
     let response_text = result.text().await.map_err(ForgeRockError::Reqwest)?;
-    println!("access token body: {}", response_text);
-
-    match serde_json::from_str(response_text.as_str()) {
-        Ok(body) => Ok(body),
-        Err(error) => Err(ForgeRockError::Parse(error)),
+    if !status.is_success() {
+        return Err(ForgeRockError::UnexpectedResponse(status, response_text));
     }
+
+    tracing::debug!(body = %redact::redact_body(&response_text), "received access token response");
+    parse_credentials(&response_text)
 }
 
 /// Attempt to refresh both access/refresh tokens via OAuth2.
-pub async fn refresh_tokens(refresh_token: String) -> Result<CredentialStorage, ForgeRockError> {
-    let result = reqwest::Client::new()
-        .post(ACCESS_TOKEN_ENDPOINT)
-        .query(&[
-            ("client_id", OAUTH_CLIENT_ID),
-            ("grant_type", "refresh_token"),
-            ("code_verifier", "plain"),
-            ("refresh_token", &refresh_token),
-        ])
-        .send()
-        .await
-        .map_err(ForgeRockError::Reqwest)?;
+#[tracing::instrument(
+    name = "token_exchange",
+    skip_all,
+    fields(grant_type = "refresh_token", status)
+)]
+pub async fn refresh_tokens(
+    http: &HttpClient,
+    environment: &Environment,
+    refresh_token: String,
+) -> Result<CredentialStorage, ForgeRockError> {
+    let request = http.post(&environment.access_token_endpoint()).query(&[
+        ("client_id", environment.oauth_client_id.as_str()),
+        ("grant_type", "refresh_token"),
+        ("code_verifier", "plain"),
+        ("refresh_token", &refresh_token),
+    ]);
+    let result = http.send(request).await.map_err(ForgeRockError::Reqwest)?;
 
     // We'll reuse CredentialStorage from the primary `forgerock` module
     // because it also has `access_token` and `refresh_token` fields,
     // which is all we need to care about from this response.
-    if !result.status().is_success() {
-        // TODO(spotlightishere): Handle this better!
-        println!("Hmm... something has gone awry: {:?}", result.text().await);
-        panic!("Hell has frozen over");
+    let status = result.status();
+    tracing::Span::current().record("status", status.as_u16());
+    let response_text = result.text().await.map_err(ForgeRockError::Reqwest)?;
+    if !status.is_success() {
+        return Err(ForgeRockError::UnexpectedResponse(status, response_text));
     }
 
-    let response_text = result.text().await.map_err(ForgeRockError::Reqwest)?;
-    println!("refresh token response body: {}", response_text);
+    tracing::debug!(body = %redact::redact_body(&response_text), "received refresh token response");
+    parse_credentials(&response_text)
+}
 
-    match serde_json::from_str(response_text.as_str()) {
-        Ok(body) => Ok(body),
-        Err(error) => Err(ForgeRockError::Parse(error)),
-    }
+/// Parses the token endpoint's response, ensuring its tokens are never logged.
+fn parse_credentials(response_text: &str) -> Result<CredentialStorage, ForgeRockError> {
+    let credentials: CredentialStorage =
+        serde_json::from_str(response_text).map_err(ForgeRockError::Parse)?;
+    credentials.register_secrets();
+    Ok(credentials)
 }
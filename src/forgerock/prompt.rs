@@ -0,0 +1,47 @@
+use std::{io, io::Write};
+
+/// Information we may need to request from the user while authenticating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptKind {
+    Username,
+    Password,
+    /// The OTP code emailed or texted to the user.
+    OneTimePassword,
+}
+
+/// A way to request information from the user while authenticating.
+///
+/// The CLI uses ``TerminalPrompt``, but embedders are free to
+/// provide information however they see fit.
+pub trait Prompt: Send + Sync {
+    fn prompt(&self, kind: PromptKind) -> io::Result<String>;
+}
+
+/// Quick and dirty prompting via stdin/stdout.
+#[derive(Debug, Default)]
+pub struct TerminalPrompt;
+
+impl Prompt for TerminalPrompt {
+    fn prompt(&self, kind: PromptKind) -> io::Result<String> {
+        let message = match kind {
+            PromptKind::Username => "Please enter your username for your Toyota account: ",
+            // Our password should never be echoed back.
+            PromptKind::Password => {
+                return rpassword::prompt_password(
+                    "Please enter your password for your Toyota account: ",
+                )
+            }
+            PromptKind::OneTimePassword => {
+                "Please enter the OTP code you were just emailed/texted: "
+            }
+        };
+        print!("{message}");
+        io::stdout().flush()?;
+
+        let mut response = String::new();
+        io::stdin().read_line(&mut response)?;
+
+        // Remove newline
+        Ok(response.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
@@ -1,52 +1,89 @@
 use super::authenticate::{self, AuthCredentials};
-use super::ForgeRockError;
+use super::prompt::{Prompt, PromptKind};
+use super::{device::DeviceProfile, ForgeRockError};
 use crate::{
     api::ApiClient,
+    environment::Environment,
     forgerock::{authorize, jwt, oauth_client},
+    http::HttpClient,
+    redact::{self, Secret},
+    storage::{CredentialStore, StorageKey},
 };
-use keyring::Entry;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
-use std::{io, io::Write};
+use std::sync::Arc;
 
 /// The format of our JSON within our credential storage.
 ///
 /// While we would ideally have one credential per token type,
 /// it proved to be a pain to ensure both would exist.
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct CredentialStorage {
     pub access_token: String,
     pub refresh_token: String,
+    /// The OpenID Connect ID token, describing who we're logged in as.
+    /// Credentials stored prior to our retaining it will lack one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
 }
 
 impl CredentialStorage {
-    pub fn from_json(contents: String) -> Self {
-        serde_json::from_str(contents.as_str()).expect("should be able to parse credential JSON")
+    pub fn from_json(contents: &str) -> Result<Self, ForgeRockError> {
+        serde_json::from_str(contents).map_err(ForgeRockError::Parse)
+    }
+
+    /// Ensures none of our tokens are ever logged.
+    pub fn register_secrets(&self) {
+        redact::register_secret(Secret::AccessToken, &self.access_token);
+        redact::register_secret(Secret::RefreshToken, &self.refresh_token);
+        redact::register_secret(
+            Secret::IdToken,
+            self.id_token.as_deref().unwrap_or_default(),
+        );
     }
 
     pub fn to_json(&self) -> String {
-        serde_json::to_string(&self).expect("should be able to serialize credential JSON")
+        serde_json::to_string(&self).expect("credentials should always be serializable")
+    }
+
+    /// Loads our stored credentials, if any.
+    pub fn load(store: &dyn CredentialStore) -> Result<Option<Self>, ForgeRockError> {
+        match store
+            .load(StorageKey::Credentials)
+            .map_err(ForgeRockError::Storage)?
+        {
+            Some(contents) => {
+                let credentials = Self::from_json(&contents)?;
+                credentials.register_secrets();
+                Ok(Some(credentials))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Persists these credentials to the given store.
+    pub fn save(&self, store: &dyn CredentialStore) -> Result<(), ForgeRockError> {
+        store
+            .store(StorageKey::Credentials, &self.to_json())
+            .map_err(ForgeRockError::Storage)
     }
 }
 
-/// Retrieves a valid access token from the user's storage.
+/// Retrieves a valid access token from the given storage.
 /// If not possible, the user will be requested to reauthenticate.
-pub async fn login() -> Result<ApiClient, ForgeRockError> {
-    let credentials_entry = Entry::new("toyotactl", "OAuth2 Credentials")
-        .expect("should be able to retrieve OAuth2 credentials");
-
-    // Do we have existing access tokens/refresh tokens in the user's keyring?
-    let credential_storage = credentials_entry.get_password();
-    let Ok(credential_contents) = credential_storage else {
+pub async fn login(
+    http: &HttpClient,
+    environment: &Environment,
+    store: Arc<dyn CredentialStore>,
+    prompt: &dyn Prompt,
+) -> Result<ApiClient, ForgeRockError> {
+    // Do we have existing access tokens/refresh tokens in the user's storage?
+    let Some(storage) = CredentialStorage::load(store.as_ref())? else {
         // We have no password stored.
         // Let's request for the user to enter, and update our storage.
-        //
-        // TODO(spotlightishere): Find a nicer arrangement to handle errors when creating the client
-        return request_username_password(credentials_entry).await;
+        return request_username_password(http, environment, store, prompt).await;
     };
 
-    // We do have tokens! Parse.
-    let storage = CredentialStorage::from_json(credential_contents);
-
     // Let's ensure our access token has not yet expired.
     // While validating, we'll also obtain the necessary `sub` value - used as a GUID within the API.
     //
@@ -54,83 +91,115 @@ pub async fn login() -> Result<ApiClient, ForgeRockError> {
     // If we were given an expired token, we'll refresh it momentarily.
     // However, if we were given any other error, we need to stop here.
     match jwt::get_sub(&storage.access_token) {
-        Ok(jwt_sub) => return Ok(ApiClient::new(storage.access_token, jwt_sub)),
+        Ok(jwt_sub) => {
+            return Ok(ApiClient::new(
+                http.clone(),
+                environment.clone(),
+                storage.access_token,
+                jwt_sub,
+                store,
+            ))
+        }
         Err(ForgeRockError::ExpiredToken) => {}
         Err(err) => return Err(err),
     };
 
-    // TODO(spotlightishere): This is a mess. Can we make it cleaner?
-    // We'll reuse the same as above, but with our refresh token.
-    //
-    // If we were successful, we can continue and refresh.
-    // If we were given an expired token, the user needs to re-authenticate.
-    // However, if we were given any other error, we need to stop here.
-    match jwt::get_sub(&storage.refresh_token) {
-        Ok(_) => {}
-        Err(ForgeRockError::ExpiredToken) => {
-            return request_username_password(credentials_entry).await
+    // Refresh tokens may be opaque, so we can't tell whether ours has expired.
+    // Instead, we'll let ForgeRock decide: should it reject our refresh token,
+    // the user needs to re-authenticate.
+    match refresh_stored(http, environment, store.clone(), storage).await {
+        Err(ForgeRockError::UnexpectedResponse(status, _))
+            if status == StatusCode::BAD_REQUEST || status == StatusCode::UNAUTHORIZED =>
+        {
+            tracing::debug!("refresh token was rejected, re-authenticating");
+            request_username_password(http, environment, store, prompt).await
         }
-        Err(err) => return Err(err),
+        result => result,
+    }
+}
+
+/// Refreshes our stored tokens, regardless of whether our access token has expired,
+/// such as to keep a long-running client logged in.
+///
+/// Unlike ``login``, we'll never prompt: if our refresh token has expired, that's an error.
+pub async fn refresh(
+    http: &HttpClient,
+    environment: &Environment,
+    store: Arc<dyn CredentialStore>,
+) -> Result<ApiClient, ForgeRockError> {
+    let Some(storage) = CredentialStorage::load(store.as_ref())? else {
+        return Err(ForgeRockError::Auth);
     };
+    refresh_stored(http, environment, store, storage).await
+}
 
+async fn refresh_stored(
+    http: &HttpClient,
+    environment: &Environment,
+    store: Arc<dyn CredentialStore>,
+    storage: CredentialStorage,
+) -> Result<ApiClient, ForgeRockError> {
     // Refresh!
-    let refreshed_tokens = oauth_client::refresh_tokens(storage.refresh_token).await?;
-    credentials_entry
-        .set_password(&refreshed_tokens.to_json())
-        .expect("should be able to update stored OAuth2 credentials");
+    let mut refreshed_tokens =
+        oauth_client::refresh_tokens(http, environment, storage.refresh_token).await?;
+    // ForgeRock may not issue a new ID token upon refresh, so we'll keep our existing one.
+    if refreshed_tokens.id_token.is_none() {
+        refreshed_tokens.id_token = storage.id_token;
+    }
+    refreshed_tokens.save(store.as_ref())?;
 
     // Similar to username/password authentication below, we should be able to
     // obtain a JWT sub as this token was just issued via refresh.
     let jwt_sub = jwt::get_sub(&refreshed_tokens.access_token)?;
 
-    Ok(ApiClient::new(refreshed_tokens.access_token, jwt_sub))
-}
-
-/// Quick and dirty function to read input from the user.
-pub fn interactive_prompt(prompt_type: &str) -> String {
-    let mut response = String::new();
-    print!("Please enter {prompt_type} for your Toyota account: ");
-    io::stdout().flush().unwrap();
-    io::stdin()
-        .read_line(&mut response)
-        .expect("should be able to read response");
-
-    // Remove newline
-    response.truncate(response.len() - 1);
-    response
+    Ok(ApiClient::new(
+        http.clone(),
+        environment.clone(),
+        refreshed_tokens.access_token,
+        jwt_sub,
+        store,
+    ))
 }
 
 /// Interactively request the user for their username and password.
 /// We store the given tokens after authentication, and create an ``ApiClient`` around them.
 pub async fn request_username_password(
-    credentials_entry: Entry,
+    http: &HttpClient,
+    environment: &Environment,
+    store: Arc<dyn CredentialStore>,
+    prompt: &dyn Prompt,
 ) -> Result<ApiClient, ForgeRockError> {
     let credentials = AuthCredentials {
-        username: interactive_prompt("your username"),
-        password: interactive_prompt("your password"),
+        username: prompt
+            .prompt(PromptKind::Username)
+            .map_err(ForgeRockError::Prompt)?,
+        password: prompt
+            .prompt(PromptKind::Password)
+            .map_err(ForgeRockError::Prompt)?,
     };
 
-    let token_id = authenticate::authenticate(credentials)
-        .await
-        .expect("should be able to authenticate");
-    println!("got a token: {}", token_id);
+    // We present ourselves as the same device across logins.
+    let device = DeviceProfile::load_or_create(store.as_ref());
+    let token_id =
+        authenticate::authenticate(http, environment, credentials, &device, prompt).await?;
+    redact::register_secret(Secret::SessionToken, &token_id);
+    tracing::debug!("obtained token ID");
 
     // Obtain an authorization code from the given token ID.
-    let authorize_code = authorize::perform_authorize_request(token_id)
-        .await
-        .expect("should be able to authorize");
-    println!("got a code: {}", authorize_code);
-
-    let credentials = oauth_client::obtain_access_token(authorize_code)
-        .await
-        .expect("should be able to obtain access token");
+    let authorize_code = authorize::perform_authorize_request(http, environment, token_id).await?;
+    redact::register_secret(Secret::AuthorizationCode, &authorize_code);
+    tracing::debug!("obtained authorization code");
 
-    credentials_entry
-        .set_password(&credentials.to_json())
-        .expect("should be able to update stored OAuth2 credentials");
+    let credentials = oauth_client::obtain_access_token(http, environment, authorize_code).await?;
+    credentials.save(store.as_ref())?;
 
     // We should be able to obtain a JWT sub because this token was (theoretically) just issued.
-    // TODO(spotlightishere): Find a nicer arrangement to handle errors when creating the client
     let jwt_sub = jwt::get_sub(&credentials.access_token)?;
-    Ok(ApiClient::new(credentials.access_token, jwt_sub))
+    Ok(ApiClient::new(
+        http.clone(),
+        environment.clone(),
+        credentials.access_token,
+        jwt_sub,
+        store,
+    ))
 }
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+
+use super::ForgeRockError;
+use crate::{environment::Environment, http::HttpClient};
+
+/// Claims about the user we're logged in as, per OpenID Connect.
+///
+/// We only request the `openid profile` scopes, so most of these are optional.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct UserInfo {
+    /// The user's GUID.
+    pub sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub given_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    /// Every other claim the realm provides.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Obtains information about the user the given access token was issued for.
+#[tracing::instrument(skip_all, fields(status))]
+pub async fn userinfo(
+    http: &HttpClient,
+    environment: &Environment,
+    access_token: &str,
+) -> Result<UserInfo, ForgeRockError> {
+    let request = http
+        .get(&environment.userinfo_endpoint())
+        .bearer_auth(access_token);
+    let result = http.send(request).await.map_err(ForgeRockError::Reqwest)?;
+
+    let status = result.status();
+    tracing::Span::current().record("status", status.as_u16());
+    let response_text = result.text().await.map_err(ForgeRockError::Reqwest)?;
+    if !status.is_success() {
+        return Err(ForgeRockError::UnexpectedResponse(status, response_text));
+    }
+    serde_json::from_str(&response_text).map_err(ForgeRockError::Parse)
+}
@@ -0,0 +1,492 @@
+use bytes::Bytes;
+use rand::Rng;
+use reqwest::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    redirect, Method, Proxy, Request, RequestBuilder, Response, StatusCode, Version,
+};
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    capture::{Entry, HarCapture, HarRequest, HarResponse},
+    fixture::{Recorder, Replayer},
+};
+
+/// The User-Agent sent by default, mimicking the OneApp's HTTP client.
+pub const DEFAULT_USER_AGENT: &str = "okhttp/4.12.0";
+
+/// How our shared HTTP client should behave.
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    /// The User-Agent sent alongside every request.
+    pub user_agent: String,
+    /// How long to wait while establishing a connection.
+    pub connect_timeout: Duration,
+    /// How long to wait for an entire request, including reading its response.
+    pub request_timeout: Duration,
+    /// How long idle connections are kept around within our pool.
+    pub pool_idle_timeout: Duration,
+    /// An HTTP or SOCKS proxy to route all requests through,
+    /// such as `http://proxy:3128` or `socks5h://localhost:1080`.
+    pub proxy: Option<String>,
+    /// How idempotent requests are retried by default.
+    pub retry: RetryPolicy,
+    /// Where to write a HAR capture of all requests and responses, if anywhere.
+    pub capture: Option<PathBuf>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            connect_timeout: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(30),
+            pool_idle_timeout: Duration::from_secs(90),
+            proxy: None,
+            retry: RetryPolicy::default(),
+            capture: None,
+        }
+    }
+}
+
+/// How transient failures are retried: connection errors, timeouts,
+/// 5xx responses, and 429 Too Many Requests.
+///
+/// Timeouts are retried as well, as a request that never completed is safe to
+/// repeat for as long as it's idempotent. (Anything else is never retried at all.)
+/// Note that each attempt waits up to the full request timeout.
+///
+/// We back off exponentially between attempts, capped at `max_backoff`,
+/// and with full jitter so that multiple clients don't retry in lockstep.
+/// Requests with streaming bodies can't be repeated, and are only sent once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times to retry after the initial attempt.
+    pub max_retries: u32,
+    /// The upper bound of our first backoff, doubled with every retry.
+    pub initial_backoff: Duration,
+    /// The most we'll ever wait between attempts.
+    /// If the server asks us to wait longer via `Retry-After`, we'll give up instead.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry. Used for requests that must not be repeated, such as remote commands.
+    pub const NEVER: RetryPolicy = RetryPolicy {
+        max_retries: 0,
+        initial_backoff: Duration::ZERO,
+        max_backoff: Duration::ZERO,
+    };
+
+    /// How long to wait prior to the given retry, beginning at zero.
+    fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        rand::thread_rng().gen_range(Duration::ZERO..=ceiling)
+    }
+}
+
+/// Whether the given response is worth retrying.
+pub(crate) fn is_transient(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// The delay requested via `Retry-After`, if specified in seconds.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(header::RETRY_AFTER)?;
+    let seconds = value.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// The HTTP client shared across authentication and API requests.
+///
+/// Cloning is cheap, and clones share the same connection pool and cookie jar.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    retry: RetryPolicy,
+    capture: Option<Arc<HarCapture>>,
+    recorder: Option<Arc<Recorder>>,
+    replayer: Option<Arc<Replayer>>,
+}
+
+impl HttpClient {
+    /// Creates a new client with the given configuration.
+    pub fn new(config: &HttpConfig) -> Result<Self, reqwest::Error> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(&config.user_agent)
+            .connect_timeout(config.connect_timeout)
+            .timeout(config.request_timeout)
+            .pool_idle_timeout(config.pool_idle_timeout)
+            // ForgeRock uses cookies for load balancer affinity across authentication.
+            .cookie_store(true)
+            // Authorization redirects to the app's custom scheme with our code.
+            // We must stop there, rather than attempting to follow it.
+            .redirect(redirect::Policy::custom(|attempt| {
+                let is_http = matches!(attempt.url().scheme(), "http" | "https");
+                if is_http && attempt.previous().len() < 10 {
+                    attempt.follow()
+                } else {
+                    attempt.stop()
+                }
+            }));
+
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+            retry: config.retry,
+            capture: config
+                .capture
+                .as_deref()
+                .map(|path| Arc::new(HarCapture::new(path))),
+            recorder: None,
+            replayer: None,
+        })
+    }
+
+    /// Records all interactions to a fixture, scrubbing their secrets.
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(Arc::new(recorder));
+        self
+    }
+
+    /// Serves all requests from the given recorded interactions,
+    /// never touching the network.
+    pub fn with_replayer(mut self, replayer: impl Into<Arc<Replayer>>) -> Self {
+        self.replayer = Some(replayer.into());
+        self
+    }
+
+    /// Begins a request with the given method to the given URL.
+    pub fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        self.client.request(method, url)
+    }
+
+    /// Begins a GET request to the given URL.
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.request(Method::GET, url)
+    }
+
+    /// Begins a POST request to the given URL.
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.request(Method::POST, url)
+    }
+
+    /// The retry policy applied to idempotent requests by default.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    /// Sends the given request.
+    ///
+    /// All requests should be sent through here, rather than via ``RequestBuilder::send``.
+    /// Idempotent requests (such as GET) are retried via our default policy.
+    /// Anything else (such as POST) is only ever sent once.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, reqwest::Error> {
+        let request = request.build()?;
+        let policy = if request.method().is_idempotent() {
+            self.retry
+        } else {
+            RetryPolicy::NEVER
+        };
+        self.execute(request, policy).await
+    }
+
+    /// Sends the given request, retrying via the given policy.
+    ///
+    /// The caller is responsible for ensuring the request is safe to repeat.
+    pub async fn send_with(
+        &self,
+        request: RequestBuilder,
+        policy: RetryPolicy,
+    ) -> Result<Response, reqwest::Error> {
+        self.execute(request.build()?, policy).await
+    }
+
+    async fn execute(
+        &self,
+        request: Request,
+        policy: RetryPolicy,
+    ) -> Result<Response, reqwest::Error> {
+        let mut retry = 0;
+        loop {
+            // Streaming bodies can't be cloned, and as such can't be retried.
+            let Some(attempt) = request.try_clone().filter(|_| retry < policy.max_retries) else {
+                return self.execute_once(request).await;
+            };
+
+            let delay = match self.execute_once(attempt).await {
+                Ok(response) if is_transient(response.status()) => match retry_after(&response) {
+                    Some(delay) if delay > policy.max_backoff => return Ok(response),
+                    Some(delay) => delay,
+                    None => policy.backoff(retry),
+                },
+                Err(err) if err.is_connect() || err.is_timeout() => policy.backoff(retry),
+                result => return result,
+            };
+
+            tracing::info!(
+                url = %request.url(),
+                retry = retry + 1,
+                delay_ms = delay.as_millis() as u64,
+                "retrying after transient failure"
+            );
+            tokio::time::sleep(delay).await;
+            retry += 1;
+        }
+    }
+
+    /// Sends the given request exactly once, capturing or recording it if necessary.
+    async fn execute_once(&self, request: Request) -> Result<Response, reqwest::Error> {
+        if let Some(replayer) = &self.replayer {
+            let recorded = replayer.respond(&request);
+            let status = StatusCode::from_u16(recorded.status).unwrap_or(StatusCode::OK);
+            let mut headers = HeaderMap::new();
+            for (name, value) in recorded.headers {
+                if let (Ok(name), Ok(value)) = (
+                    HeaderName::from_bytes(name.as_bytes()),
+                    HeaderValue::from_str(&value),
+                ) {
+                    headers.append(name, value);
+                }
+            }
+            let body = Bytes::from(recorded.body);
+            return Ok(reassemble(status, Version::HTTP_11, headers, body));
+        }
+
+        if self.capture.is_none() && self.recorder.is_none() {
+            return self.client.execute(request).await;
+        }
+
+        let started = SystemTime::now();
+        let timer = Instant::now();
+        let har_request = HarRequest::new(&request);
+        // We'll need to retain our request in order to record it later on.
+        let recorded_request = self.recorder.as_ref().and_then(|_| request.try_clone());
+        let response = match self.client.execute(request).await {
+            Ok(response) => response,
+            Err(err) => {
+                if let Some(capture) = &self.capture {
+                    let har_response = HarResponse::failed(&err);
+                    capture.record(Entry::new(
+                        started,
+                        timer.elapsed(),
+                        har_request,
+                        har_response,
+                    ));
+                }
+                return Err(err);
+            }
+        };
+
+        // We'll need to read the body in order to capture it,
+        // after which we must reassemble our response.
+        let status = response.status();
+        let version = response.version();
+        let headers = response.headers().clone();
+        let body = response.bytes().await?;
+        if let Some(capture) = &self.capture {
+            let har_response = HarResponse::new(status, version, &headers, &body);
+            capture.record(Entry::new(
+                started,
+                timer.elapsed(),
+                har_request,
+                har_response,
+            ));
+        }
+        if let (Some(recorder), Some(request)) = (&self.recorder, &recorded_request) {
+            recorder.record(request, status, &headers, &body);
+        }
+
+        Ok(reassemble(status, version, headers, body))
+    }
+}
+
+/// Creates a response from its components, such as after reading its body.
+fn reassemble(status: StatusCode, version: Version, headers: HeaderMap, body: Bytes) -> Response {
+    let mut response = ::http::Response::new(body);
+    *response.status_mut() = status;
+    *response.version_mut() = version;
+    *response.headers_mut() = headers;
+    Response::from(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::fixture::{Fixture, Interaction, RecordedRequest, RecordedResponse};
+
+    const URL: &str = "http://127.0.0.1:9/resource";
+
+    /// Retries quickly, so that our tests don't.
+    const QUICK: RetryPolicy = RetryPolicy {
+        max_retries: 3,
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(5),
+    };
+
+    fn interaction(method: &str, status: u16, headers: &[(&str, &str)]) -> Interaction {
+        Interaction {
+            request: RecordedRequest {
+                method: method.to_string(),
+                url: URL.to_string(),
+                body: None,
+            },
+            response: RecordedResponse {
+                status,
+                headers: headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+                body: String::new(),
+            },
+        }
+    }
+
+    /// A client replaying the given interactions in order, alongside its replayer.
+    fn replaying(interactions: Vec<Interaction>) -> (HttpClient, Arc<Replayer>) {
+        let replayer = Arc::new(Replayer::new(Fixture { interactions }));
+        let http = HttpClient::new(&HttpConfig {
+            retry: QUICK,
+            ..HttpConfig::default()
+        })
+        .unwrap()
+        .with_replayer(Arc::clone(&replayer));
+        (http, replayer)
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried() {
+        let (http, replayer) = replaying(vec![
+            interaction("GET", 503, &[]),
+            interaction("GET", 429, &[("retry-after", "0")]),
+            interaction("GET", 200, &[]),
+        ]);
+        let response = http.send(http.get(URL)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(replayer.is_exhausted());
+    }
+
+    #[tokio::test]
+    async fn retries_are_bounded() {
+        let (http, replayer) = replaying(vec![
+            interaction("GET", 503, &[]),
+            interaction("GET", 503, &[]),
+            interaction("GET", 503, &[]),
+            interaction("GET", 502, &[]),
+            interaction("GET", 200, &[]),
+        ]);
+        let response = http.send(http.get(URL)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert!(!replayer.is_exhausted());
+    }
+
+    #[tokio::test]
+    async fn long_retry_after_is_returned_as_is() {
+        let (http, replayer) = replaying(vec![
+            interaction("GET", 429, &[("retry-after", "60")]),
+            interaction("GET", 200, &[]),
+        ]);
+        let response = http.send(http.get(URL)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "60");
+        assert!(!replayer.is_exhausted());
+    }
+
+    #[tokio::test]
+    async fn non_idempotent_requests_are_never_retried() {
+        let (http, replayer) = replaying(vec![
+            interaction("POST", 503, &[]),
+            interaction("POST", 200, &[]),
+        ]);
+        let response = http.send(http.post(URL)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!replayer.is_exhausted());
+    }
+
+    #[tokio::test]
+    async fn streaming_bodies_are_sent_once() {
+        let (http, replayer) = replaying(vec![
+            interaction("PUT", 503, &[]),
+            interaction("PUT", 200, &[]),
+        ]);
+        let chunks = futures_util::stream::iter([Ok::<_, std::io::Error>("chunk")]);
+        let request = http
+            .request(Method::PUT, URL)
+            .body(reqwest::Body::wrap_stream(chunks));
+        let response = http.send(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!replayer.is_exhausted());
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let policy = RetryPolicy {
+            max_retries: u32::MAX,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+        };
+        for retry in [0, 1, 2, 3, 4, 5, 10, 31, 32, 100, u32::MAX] {
+            let ceiling = Duration::from_millis(500)
+                .saturating_mul(2u32.saturating_pow(retry))
+                .min(policy.max_backoff);
+            for _ in 0..100 {
+                assert!(policy.backoff(retry) <= ceiling, "{retry}");
+            }
+        }
+        assert_eq!(RetryPolicy::NEVER.backoff(0), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn timeouts_are_retried() {
+        // A server that accepts connections, but never responds.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&accepted);
+        tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                streams.push(stream);
+            }
+        });
+
+        let http = HttpClient::new(&HttpConfig {
+            request_timeout: Duration::from_millis(100),
+            retry: QUICK,
+            ..HttpConfig::default()
+        })
+        .unwrap();
+        let url = format!("http://{address}/resource");
+        let err = http.send(http.get(&url)).await.unwrap_err();
+        assert!(err.is_timeout());
+        assert_eq!(accepted.load(Ordering::SeqCst), 4);
+
+        // Unlike anything non-idempotent.
+        accepted.store(0, Ordering::SeqCst);
+        let err = http.send(http.post(&url)).await.unwrap_err();
+        assert!(err.is_timeout());
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    }
+}
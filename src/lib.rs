@@ -0,0 +1,24 @@
+//! A client for Toyota's OneApp API, and the ForgeRock authentication in front of it.
+//!
+//! Most consumers will want to obtain the API gateway key via
+//! ``api::ensure_gateway_key``, and then log in via ``forgerock::login``
+//! to obtain an ``api::ApiClient``.
+
+pub mod api;
+pub mod audit;
+pub mod cache;
+pub mod capture;
+pub mod config;
+#[cfg(unix)]
+pub mod daemon;
+pub mod environment;
+pub mod fixture;
+pub mod forgerock;
+pub mod http;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod output;
+pub mod redact;
+pub mod safety;
+pub mod session;
+pub mod storage;
@@ -1,12 +1,470 @@
-mod api;
-mod forgerock;
+mod commands;
+
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::{engine::ArgValueCandidates, CompleteEnv};
+use std::{path::PathBuf, time::Duration};
+use toyotactl::{
+    api::{RemoteCommand, Units},
+    cache,
+    config::Config,
+    environment::Region,
+    http::DEFAULT_USER_AGENT,
+    output::OutputFormat,
+    redact::RedactingMakeWriter,
+};
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
+use url::Url;
+
+use commands::{completions, exit_with_error, Context};
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Log more verbosely: -v for each step and its timing, -vv for redacted bodies.
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// The format logs are written to stderr in.
+    #[arg(
+        long,
+        global = true,
+        env = "TOYOTACTL_LOG_FORMAT",
+        value_enum,
+        default_value_t
+    )]
+    log_format: LogFormat,
+
+    /// How to print results: table, json, yaml, csv, or template=<FORMAT> (such as template={vin}).
+    #[arg(
+        short,
+        long,
+        global = true,
+        env = "TOYOTACTL_OUTPUT",
+        default_value = "table"
+    )]
+    output: OutputFormat,
+
+    /// The configuration file to use, instead of the one within your config directory.
+    #[arg(long, global = true, env = "TOYOTACTL_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// The profile to use, allowing multiple accounts to coexist. [default: default]
+    #[arg(long, global = true, env = "TOYOTACTL_PROFILE")]
+    profile: Option<String>,
+
+    /// The brand and region of the account. [default: toyota-us]
+    #[arg(long, global = true, env = "TOYOTACTL_REGION")]
+    region: Option<Region>,
+
+    /// The vehicle to act upon, by VIN or alias, when a command is given none.
+    /// (-v is taken by --verbose, so think "car".)
+    #[arg(
+        short = 'c',
+        long,
+        global = true,
+        env = "TOYOTACTL_VEHICLE",
+        add = ArgValueCandidates::new(completions::vehicle_candidates)
+    )]
+    vehicle: Option<String>,
+
+    /// The units to present distances in. [default: reported]
+    #[arg(long, global = true, env = "TOYOTACTL_UNITS")]
+    units: Option<Units>,
+
+    /// Use this API gateway key instead of extracting one.
+    #[arg(
+        long,
+        global = true,
+        env = "TOYOTACTL_GATEWAY_KEY",
+        hide_env_values = true
+    )]
+    gateway_key: Option<String>,
+
+    /// Extract the API gateway key from this local toyota-na sdist tarball.
+    #[arg(long, global = true, env = "TOYOTACTL_GATEWAY_KEY_SDIST")]
+    gateway_key_sdist: Option<PathBuf>,
+
+    /// The expected SHA-256 digest of the local sdist, if it is not a release we already trust.
+    #[arg(long, global = true, env = "TOYOTACTL_GATEWAY_KEY_SDIST_SHA256")]
+    gateway_key_sdist_sha256: Option<String>,
+
+    /// Override the base URL of ForgeRock AM, such as for a local stand-in server.
+    #[arg(long, global = true, env = "TOYOTACTL_FORGEROCK_URL")]
+    forgerock_url: Option<Url>,
+
+    /// Override the ForgeRock realm.
+    #[arg(long, global = true, env = "TOYOTACTL_REALM")]
+    realm: Option<String>,
+
+    /// Override the base URL of the OneApp API.
+    #[arg(long, global = true, env = "TOYOTACTL_API_URL")]
+    api_url: Option<Url>,
+
+    /// Override where the toyota-na sdist is downloaded from.
+    #[arg(long, global = true, env = "TOYOTACTL_PACKAGE_URL")]
+    package_url: Option<Url>,
+
+    /// The User-Agent sent alongside every request.
+    #[arg(long, global = true, env = "TOYOTACTL_USER_AGENT", default_value = DEFAULT_USER_AGENT)]
+    user_agent: String,
+
+    /// Route all requests through this HTTP or SOCKS proxy.
+    #[arg(long, global = true, env = "TOYOTACTL_PROXY")]
+    proxy: Option<String>,
+
+    /// How many seconds to wait while establishing a connection.
+    #[arg(
+        long,
+        global = true,
+        env = "TOYOTACTL_CONNECT_TIMEOUT",
+        default_value_t = 10
+    )]
+    connect_timeout: u64,
+
+    /// How many seconds to wait for an entire request.
+    #[arg(long, global = true, env = "TOYOTACTL_TIMEOUT", default_value_t = 30)]
+    timeout: u64,
+
+    /// Record all requests and responses, with secrets redacted, to this HAR file.
+    #[arg(long, global = true, env = "TOYOTACTL_CAPTURE")]
+    capture: Option<PathBuf>,
+
+    /// Record all interactions to this fixture, with secrets scrubbed.
+    #[arg(long, global = true, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Serve all requests from this previously recorded fixture, never touching the network.
+    #[arg(long, global = true)]
+    replay: Option<PathBuf>,
+
+    /// Verify the signature of our access token against the realm's published keys.
+    #[arg(long, global = true, env = "TOYOTACTL_VERIFY_TOKENS")]
+    verify_tokens: bool,
+
+    /// How many times to retry idempotent requests after transient failures. [default: 3]
+    #[arg(long, global = true, env = "TOYOTACTL_RETRIES")]
+    retries: Option<u32>,
+
+    /// Use cached responses younger than this, such as 90s, 5m or 1h.
+    /// [default: 1d for the vehicle list, 1m for status and telemetry]
+    #[arg(long, global = true, env = "TOYOTACTL_MAX_AGE", value_parser = cache::parse_max_age)]
+    max_age: Option<Duration>,
+
+    /// Skip confirmation of remote commands, such as from scripts.
+    /// Policy (such as allowed hours) still applies.
+    #[arg(short, long, global = true)]
+    yes: bool,
+
+    /// Never touch the network, using cached responses regardless of age.
+    /// Only the vehicle list, status and telemetry are available offline.
+    #[arg(long, global = true, env = "TOYOTACTL_OFFLINE")]
+    offline: bool,
+
+    /// Log in and check a remote command as usual, but print its request rather than send it.
+    /// Secrets within the request are redacted.
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Where remote commands are recorded. [default: audit.jsonl within your data directory]
+    #[arg(long, global = true, env = "TOYOTACTL_AUDIT_LOG")]
+    audit_log: Option<PathBuf>,
+
+    /// Where the daemon listens. [default: toyotactl/<profile>.sock within your runtime directory]
+    #[arg(long, global = true, env = "TOYOTACTL_DAEMON_SOCKET")]
+    daemon_socket: Option<PathBuf>,
+
+    /// Log in directly, even if a daemon is running.
+    #[arg(long, global = true, env = "TOYOTACTL_NO_DAEMON")]
+    no_daemon: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manage the session stored on this machine.
+    #[command(subcommand)]
+    Session(SessionCommand),
+    /// Manage the API gateway key.
+    #[command(subcommand)]
+    GatewayKey(GatewayKeyCommand),
+    /// Read or modify the configuration file.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Show who you're logged in as, and when your tokens expire.
+    Whoami,
+    /// List all vehicles associated with your account.
+    Vehicles,
+    /// Show the remote status of a vehicle.
+    Status(Target),
+    /// Show telemetry for a vehicle, such as its fuel level.
+    Telemetry(Target),
+    /// Lock a vehicle's doors.
+    Lock(Target),
+    /// Unlock a vehicle's doors.
+    Unlock(Target),
+    /// Remotely start a vehicle's engine.
+    Start(Target),
+    /// Stop a vehicle's remotely started engine.
+    #[command(alias = "power-off")]
+    Stop(Target),
+    /// Turn a vehicle's hazard lights on or off.
+    Hazards {
+        state: HazardState,
+        #[command(flatten)]
+        target: Target,
+    },
+    /// Stay logged in, polling vehicles and answering other invocations over a Unix socket.
+    ///
+    /// Other invocations for the same profile use the daemon automatically while it runs.
+    #[cfg(unix)]
+    Daemon {
+        /// How often to poll every vehicle's status and telemetry, such as 5m. 0 disables polling.
+        #[arg(long, default_value = "5m", value_parser = cache::parse_max_age)]
+        poll_interval: Duration,
+    },
+    /// Publish vehicles to an MQTT broker, with discovery for Home Assistant, and accept commands.
+    ///
+    /// Commands are subject to your policy as though run from cron.
+    #[cfg(feature = "mqtt")]
+    Mqtt(MqttArgs),
+    /// Query the log of remote commands issued from this machine, oldest first.
+    Audit(AuditQuery),
+    /// Print a completion script for your shell.
+    ///
+    /// Vehicle aliases and VINs are completed too, once `toyotactl vehicles` has been run.
+    Completions { shell: Shell },
+    /// Print a man page.
+    Man,
+}
+
+/// Filters upon the audit log.
+#[derive(Args)]
+struct AuditQuery {
+    /// Only show commands issued to this VIN or alias.
+    #[arg(add = ArgValueCandidates::new(completions::vehicle_candidates))]
+    vin: Option<String>,
+    /// Only show this command, such as door-unlock.
+    #[arg(long = "command", value_enum)]
+    remote_command: Option<RemoteCommand>,
+    /// Only show commands issued within this long ago, such as 12h or 7d.
+    #[arg(long, value_parser = cache::parse_max_age)]
+    since: Option<Duration>,
+    /// Only show this many of the most recent entries.
+    #[arg(long)]
+    limit: Option<usize>,
+}
+
+/// How to reach the MQTT broker, and what to publish to it.
+#[cfg(feature = "mqtt")]
+#[derive(Args)]
+struct MqttArgs {
+    /// The broker to connect to, such as mqtt://localhost:1883, or mqtts:// for TLS.
+    #[arg(
+        long,
+        env = "TOYOTACTL_MQTT_BROKER",
+        default_value = "mqtt://localhost:1883"
+    )]
+    broker: Url,
+    #[arg(long, env = "TOYOTACTL_MQTT_USERNAME")]
+    username: Option<String>,
+    #[arg(long, env = "TOYOTACTL_MQTT_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+    /// The topic every vehicle is published beneath.
+    #[arg(long, default_value = "toyotactl")]
+    topic_prefix: String,
+    /// Where Home Assistant listens for discovery messages.
+    #[arg(long, default_value = "homeassistant")]
+    discovery_prefix: String,
+    /// Don't publish discovery messages for Home Assistant.
+    #[arg(long)]
+    no_discovery: bool,
+    /// How often to publish every vehicle's status and telemetry, such as 5m. 0 disables polling.
+    #[arg(long, default_value = "5m", value_parser = cache::parse_max_age)]
+    poll_interval: Duration,
+    /// Permit this command over MQTT, even though your policy requires confirmation. May be repeated.
+    #[arg(long = "allow", value_enum)]
+    allowed: Vec<RemoteCommand>,
+}
+
+/// The vehicle a command acts upon.
+#[derive(Args)]
+struct Target {
+    /// The vehicle's VIN or alias. [default: --vehicle, or default_vin within your config]
+    #[arg(add = ArgValueCandidates::new(completions::vehicle_candidates))]
+    vin: Option<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+#[derive(Subcommand)]
+enum SessionCommand {
+    /// Export the current session to a passphrase-encrypted bundle.
+    Export { path: PathBuf },
+    /// Import a session from a passphrase-encrypted bundle.
+    Import { path: PathBuf },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the value of a key, such as profiles.default.units.
+    Get { key: String },
+    /// Set the value of a key, creating the configuration file if necessary.
+    Set { key: String, value: String },
+    /// Remove a key.
+    Unset { key: String },
+    /// Print where the configuration file lives.
+    Path,
+}
+
+#[derive(Subcommand)]
+enum GatewayKeyCommand {
+    /// Obtain the API gateway key once more, replacing the stored one.
+    Refresh,
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, such as for ingestion elsewhere.
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum HazardState {
+    On,
+    Off,
+}
+
+impl HazardState {
+    /// The remote command turning hazard lights to this state.
+    fn command(self) -> RemoteCommand {
+        match self {
+            HazardState::On => RemoteCommand::HazardOn,
+            HazardState::Off => RemoteCommand::HazardOff,
+        }
+    }
+}
+
+/// Sets up logging to stderr. Logs never contain secrets.
+///
+/// `TOYOTACTL_LOG` (such as `TOYOTACTL_LOG=toyotactl=trace`) takes precedence over `-v`.
+fn init_logging(verbose: u8, format: LogFormat) {
+    let level = match verbose {
+        0 => "warn",
+        1 => "toyotactl=info",
+        2 => "toyotactl=debug",
+        _ => "toyotactl=trace",
+    };
+    let filter = EnvFilter::try_from_env("TOYOTACTL_LOG").unwrap_or_else(|_| EnvFilter::new(level));
+
+    // Once verbose, we'll log every span as it closes alongside its timing.
+    let span_events = if verbose > 0 {
+        FmtSpan::CLOSE
+    } else {
+        FmtSpan::NONE
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(span_events)
+        .with_writer(RedactingMakeWriter::new(std::io::stderr));
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    // Before anything else, let's ensure we have the API key available.
-    api::ensure_gateway_key().await;
+    // When invoked by a shell for completions, we'll respond and exit here.
+    CompleteEnv::with_factory(Cli::command).complete();
+    let mut cli = Cli::parse();
+
+    init_logging(cli.verbose, cli.log_format);
+
+    // Configuration commands operate on the file alone, even if it's invalid.
+    let config_path = cli.config.clone().or_else(Config::default_path);
+    // Reading is harmless, but we'll refuse to pretend anything else is a dry run.
+    let modifies_locally = matches!(
+        cli.command,
+        Some(
+            Command::Config(ConfigCommand::Set { .. } | ConfigCommand::Unset { .. })
+                | Command::Session(SessionCommand::Import { .. })
+                | Command::GatewayKey(GatewayKeyCommand::Refresh)
+        )
+    );
+    if cli.dry_run && modifies_locally {
+        exit_with_error(
+            "Unable to perform a dry run",
+            "only remote commands can be dry run",
+        );
+    }
+    let available_offline = matches!(
+        cli.command,
+        Some(
+            Command::Vehicles
+                | Command::Status(_)
+                | Command::Telemetry(_)
+                | Command::Session(_)
+                | Command::Audit(_)
+                | Command::Config(_)
+                | Command::Completions { .. }
+                | Command::Man
+        )
+    );
+    if cli.offline && !available_offline {
+        exit_with_error(
+            "Unable to continue offline",
+            "only vehicles, status and telemetry can be read from the cache",
+        );
+    }
 
-    // We can finally initialize our API client!
-    let client = forgerock::login().await.expect("should be able to log in");
-    println!("{:?}", client);
+    let command = cli.command.take();
+    // Everything but these local commands reads our configuration.
+    let context = || Context::load(cli, config_path.clone());
+    match command {
+        Some(Command::Config(command)) => commands::config::run(command, config_path),
+        Some(Command::Completions { shell }) => completions::run(shell),
+        Some(Command::Man) => completions::man(),
+        Some(Command::Audit(query)) => commands::audit::run(query, &context()),
+        Some(Command::Session(command)) => commands::session::run(command, &context()),
+        Some(Command::GatewayKey(command)) => commands::gateway_key::run(command, &context()).await,
+        Some(Command::Whoami) => commands::whoami::run(&context()).await,
+        Some(Command::Vehicles) => commands::vehicles::list(&context()).await,
+        Some(Command::Status(target)) => commands::vehicles::status(target, &context()).await,
+        Some(Command::Telemetry(target)) => commands::vehicles::telemetry(target, &context()).await,
+        Some(Command::Lock(target)) => {
+            commands::remote::run(RemoteCommand::DoorLock, target, &context()).await
+        }
+        Some(Command::Unlock(target)) => {
+            commands::remote::run(RemoteCommand::DoorUnlock, target, &context()).await
+        }
+        Some(Command::Start(target)) => {
+            commands::remote::run(RemoteCommand::EngineStart, target, &context()).await
+        }
+        Some(Command::Stop(target)) => {
+            commands::remote::run(RemoteCommand::EngineStop, target, &context()).await
+        }
+        Some(Command::Hazards { state, target }) => {
+            commands::remote::run(state.command(), target, &context()).await
+        }
+        #[cfg(unix)]
+        Some(Command::Daemon { poll_interval }) => {
+            commands::daemon::run(poll_interval, context()).await
+        }
+        #[cfg(feature = "mqtt")]
+        Some(Command::Mqtt(args)) => commands::mqtt::run(args, context()).await,
+        None => {
+            let client = context().api_client().await;
+            println!("{:?}", client);
+        }
+    }
 }
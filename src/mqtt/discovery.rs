@@ -0,0 +1,285 @@
+use serde_json::{json, Map, Value};
+
+use super::Topics;
+use crate::api::{RemoteCommand, Vehicle};
+
+/// A single Home Assistant discovery message, to be published (and retained) as-is.
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    pub topic: String,
+    pub payload: Value,
+}
+
+/// Describes the entities we provide for a vehicle, so that Home Assistant creates them for us.
+///
+/// Distance sensors are only given a unit (and as such a device class) if we know it,
+/// as Home Assistant refuses distances without one.
+pub fn discovery(
+    vehicle: &Vehicle,
+    manufacturer: &str,
+    topics: &Topics,
+    discovery_prefix: &str,
+    distance_unit: Option<&str>,
+) -> Vec<Discovery> {
+    let vin = &vehicle.vin;
+    let node = format!("toyotactl_{}", vin.to_lowercase());
+    let name = vehicle.nick_name.clone().unwrap_or_else(|| {
+        match (&vehicle.model_year, &vehicle.model_name) {
+            (Some(year), Some(model)) => format!("{year} {model}"),
+            (None, Some(model)) => model.clone(),
+            _ => vin.clone(),
+        }
+    });
+    let mut device = json!({
+        "identifiers": [node],
+        "name": name,
+        "manufacturer": manufacturer,
+        "serial_number": vin,
+    });
+    if let Some(model) = &vehicle.model_name {
+        device["model"] = json!(model);
+    }
+    let command_topic = topics.command(vin);
+    let telemetry_topic = topics.telemetry(vin);
+
+    let distance = |template: &str, state_class: &str| {
+        let mut config = json!({
+            "state_topic": telemetry_topic,
+            "value_template": template,
+            "state_class": state_class,
+        });
+        if let Some(unit) = distance_unit {
+            config["device_class"] = json!("distance");
+            config["unit_of_measurement"] = json!(unit);
+        }
+        config
+    };
+    let button = |command: RemoteCommand, icon: &str| {
+        json!({
+            "command_topic": command_topic,
+            "payload_press": command.as_str(),
+            "icon": icon,
+        })
+    };
+
+    let entities = [
+        (
+            "lock",
+            "doors",
+            "Doors",
+            json!({
+                "state_topic": topics.lock(vin),
+                "command_topic": command_topic,
+                "payload_lock": RemoteCommand::DoorLock.as_str(),
+                "payload_unlock": RemoteCommand::DoorUnlock.as_str(),
+                "state_locked": "LOCKED",
+                "state_unlocked": "UNLOCKED",
+                // We'll only know once the vehicle reports back.
+                "optimistic": false,
+            }),
+        ),
+        (
+            "sensor",
+            "fuel",
+            "Fuel",
+            json!({
+                "state_topic": telemetry_topic,
+                "value_template": "{{ value_json.fuelLevel }}",
+                "unit_of_measurement": "%",
+                "state_class": "measurement",
+                "icon": "mdi:gas-station",
+            }),
+        ),
+        (
+            "sensor",
+            "odometer",
+            "Odometer",
+            distance("{{ value_json.odometer.value }}", "total_increasing"),
+        ),
+        (
+            "sensor",
+            "range",
+            "Range",
+            distance("{{ value_json.distanceToEmpty.value }}", "measurement"),
+        ),
+        (
+            "device_tracker",
+            "location",
+            "Location",
+            json!({
+                "json_attributes_topic": topics.location(vin),
+                "source_type": "gps",
+            }),
+        ),
+        (
+            "button",
+            "engine_start",
+            "Start engine",
+            button(RemoteCommand::EngineStart, "mdi:engine"),
+        ),
+        (
+            "button",
+            "engine_stop",
+            "Stop engine",
+            button(RemoteCommand::EngineStop, "mdi:engine-off"),
+        ),
+        (
+            "button",
+            "hazard_on",
+            "Hazards on",
+            button(RemoteCommand::HazardOn, "mdi:car-light-alert"),
+        ),
+        (
+            "button",
+            "hazard_off",
+            "Hazards off",
+            button(RemoteCommand::HazardOff, "mdi:car-light-dimmed"),
+        ),
+    ];
+
+    entities
+        .into_iter()
+        .map(|(component, object, entity_name, config)| {
+            let mut payload = Map::new();
+            payload.insert("name".to_string(), json!(entity_name));
+            payload.insert("unique_id".to_string(), json!(format!("{node}_{object}")));
+            payload.insert(
+                "availability_topic".to_string(),
+                json!(topics.availability()),
+            );
+            payload.insert("device".to_string(), device.clone());
+            payload.insert(
+                "origin".to_string(),
+                json!({
+                    "name": "toyotactl",
+                    "sw_version": env!("CARGO_PKG_VERSION"),
+                }),
+            );
+            if let Value::Object(config) = config {
+                payload.extend(config);
+            }
+            Discovery {
+                topic: format!("{discovery_prefix}/{component}/{node}/{object}/config"),
+                payload: Value::Object(payload),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIN: &str = "JTMOCK00000000001";
+
+    fn vehicle(value: Value) -> Vehicle {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn find<'a>(messages: &'a [Discovery], topic: &str) -> &'a Value {
+        &messages
+            .iter()
+            .find(|message| message.topic == topic)
+            .unwrap_or_else(|| panic!("no discovery message upon {topic}"))
+            .payload
+    }
+
+    #[test]
+    fn entities() {
+        let vehicle = vehicle(json!({
+            "vin": VIN,
+            "nickName": "Tacoma",
+            "modelName": "Tacoma",
+            "modelYear": "2024",
+        }));
+        let topics = Topics::new("toyotactl");
+        let messages = discovery(&vehicle, "Toyota", &topics, "homeassistant", Some("mi"));
+        assert_eq!(messages.len(), 9);
+
+        let node = "toyotactl_jtmock00000000001";
+        let lock = find(
+            &messages,
+            &format!("homeassistant/lock/{node}/doors/config"),
+        );
+        assert_eq!(
+            *lock,
+            json!({
+                "name": "Doors",
+                "unique_id": format!("{node}_doors"),
+                "availability_topic": "toyotactl/availability",
+                "device": {
+                    "identifiers": [node],
+                    "name": "Tacoma",
+                    "manufacturer": "Toyota",
+                    "model": "Tacoma",
+                    "serial_number": VIN,
+                },
+                "origin": {
+                    "name": "toyotactl",
+                    "sw_version": env!("CARGO_PKG_VERSION"),
+                },
+                "state_topic": "toyotactl/JTMOCK00000000001/lock",
+                "command_topic": "toyotactl/JTMOCK00000000001/command",
+                "payload_lock": "door-lock",
+                "payload_unlock": "door-unlock",
+                "state_locked": "LOCKED",
+                "state_unlocked": "UNLOCKED",
+                "optimistic": false,
+            })
+        );
+
+        let odometer = find(
+            &messages,
+            &format!("homeassistant/sensor/{node}/odometer/config"),
+        );
+        assert_eq!(
+            odometer["state_topic"],
+            "toyotactl/JTMOCK00000000001/telemetry"
+        );
+        assert_eq!(
+            odometer["value_template"],
+            "{{ value_json.odometer.value }}"
+        );
+        assert_eq!(odometer["device_class"], "distance");
+        assert_eq!(odometer["unit_of_measurement"], "mi");
+
+        let start = find(
+            &messages,
+            &format!("homeassistant/button/{node}/engine_start/config"),
+        );
+        assert_eq!(
+            start["command_topic"],
+            "toyotactl/JTMOCK00000000001/command"
+        );
+        assert_eq!(start["payload_press"], "engine-start");
+
+        // Every entity belongs to the same device, under its own unique ID.
+        let unique_ids: std::collections::HashSet<&str> = messages
+            .iter()
+            .filter_map(|message| message.payload["unique_id"].as_str())
+            .collect();
+        assert_eq!(unique_ids.len(), messages.len());
+        assert!(messages
+            .iter()
+            .all(|message| message.payload["device"] == lock["device"]));
+    }
+
+    #[test]
+    fn distances_without_units() {
+        let vehicle = vehicle(json!({ "vin": VIN }));
+        let topics = Topics::new("toyotactl");
+        let messages = discovery(&vehicle, "Lexus", &topics, "homeassistant", None);
+
+        let node = "toyotactl_jtmock00000000001";
+        let range = find(
+            &messages,
+            &format!("homeassistant/sensor/{node}/range/config"),
+        );
+        assert!(range.get("device_class").is_none());
+        assert!(range.get("unit_of_measurement").is_none());
+        // Without a nickname or model, we'll fall back upon the VIN.
+        assert_eq!(range["device"]["name"], VIN);
+        assert_eq!(range["device"]["manufacturer"], "Lexus");
+        assert!(range["device"].get("model").is_none());
+    }
+}
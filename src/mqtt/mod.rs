@@ -0,0 +1,674 @@
+//! Bridges every vehicle on an account to an MQTT broker, such as for Home Assistant.
+//!
+//! For each vehicle, we'll publish (and retain) beneath `<prefix>/<VIN>/`:
+//! - `status` and `telemetry`, following the serde shapes of our API types as with `--output json`
+//! - `lock`, either `LOCKED` or `UNLOCKED`
+//! - `location`, an object with `latitude` and `longitude`
+//!
+//! Publishing a command's name (such as `door-lock`) to `<prefix>/<VIN>/command` issues it,
+//! subject to the profile's policy, and its outcome is published to `<prefix>/<VIN>/command/result`.
+//! `<prefix>/availability` is `online` for as long as we're connected.
+//!
+//! We'll also publish discovery messages, so that Home Assistant creates entities for each vehicle.
+
+mod discovery;
+
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, QoS, Transport};
+use serde::Serialize;
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+use tokio::time::MissedTickBehavior;
+use url::Url;
+
+use crate::{
+    api::{ApiClient, RemoteCommand, SharedClient, Units, Vehicle},
+    audit::AuditLog,
+    cache::{Cache, CAPABILITIES_MAX_AGE},
+    redact,
+    safety::{self, Origin, Policy, Refusal},
+};
+
+pub use discovery::{discovery, Discovery};
+
+/// How long we'll wait before reconnecting to the broker.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// How long we'll give the vehicle to carry out a command before asking for its status.
+const COMMAND_SETTLE_DELAY: Duration = Duration::from_secs(15);
+
+/// Possible errors while setting up the bridge.
+#[derive(Debug)]
+pub enum MqttError {
+    /// The broker's URL wasn't one we know how to connect to.
+    InvalidBroker(String),
+}
+
+impl fmt::Display for MqttError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MqttError::InvalidBroker(reason) => write!(f, "invalid broker: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for MqttError {}
+
+/// The topics we publish and subscribe to, all beneath a single prefix.
+#[derive(Debug, Clone)]
+pub struct Topics {
+    prefix: String,
+}
+
+impl Topics {
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn availability(&self) -> String {
+        format!("{}/availability", self.prefix)
+    }
+
+    pub fn status(&self, vin: &str) -> String {
+        format!("{}/{vin}/status", self.prefix)
+    }
+
+    pub fn telemetry(&self, vin: &str) -> String {
+        format!("{}/{vin}/telemetry", self.prefix)
+    }
+
+    pub fn lock(&self, vin: &str) -> String {
+        format!("{}/{vin}/lock", self.prefix)
+    }
+
+    pub fn location(&self, vin: &str) -> String {
+        format!("{}/{vin}/location", self.prefix)
+    }
+
+    pub fn command(&self, vin: &str) -> String {
+        format!("{}/{vin}/command", self.prefix)
+    }
+
+    pub fn command_result(&self, vin: &str) -> String {
+        format!("{}/{vin}/command/result", self.prefix)
+    }
+
+    /// Matches the command topic of every vehicle.
+    pub fn commands(&self) -> String {
+        format!("{}/+/command", self.prefix)
+    }
+
+    /// The VIN a command was published for, if the topic is a command topic at all.
+    pub fn command_vin<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        topic
+            .strip_prefix(&self.prefix)?
+            .strip_prefix('/')?
+            .strip_suffix("/command")
+            .filter(|vin| !vin.contains('/'))
+    }
+}
+
+/// How to connect to the broker, and what to publish.
+#[derive(Clone)]
+pub struct BridgeConfig {
+    /// Such as `mqtt://localhost:1883`, or `mqtts://` for TLS.
+    pub broker: Url,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub client_id: String,
+    pub topics: Topics,
+    /// Where Home Assistant listens for discovery messages, or `None` to publish none.
+    pub discovery_prefix: Option<String>,
+    pub poll_interval: Duration,
+    pub units: Units,
+    /// Commands which may be issued despite requiring confirmation, as nobody can confirm over MQTT.
+    pub allowed: Vec<RemoteCommand>,
+}
+
+/// The outcome of a command received over MQTT.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+enum CommandOutcome {
+    Accepted {
+        command: RemoteCommand,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+    Refused {
+        command: String,
+        reason: String,
+    },
+    Failed {
+        command: RemoteCommand,
+        error: String,
+    },
+}
+
+/// Publishes vehicles to a broker, and issues the commands it relays to us.
+pub struct Bridge {
+    profile: String,
+    client: SharedClient,
+    cache: Option<Cache>,
+    audit: AuditLog,
+    policy: Policy,
+    config: BridgeConfig,
+    mqtt: AsyncClient,
+    /// The vehicles we last saw on the account. Commands for any others are refused.
+    vehicles: Mutex<Vec<Vehicle>>,
+    /// Held while polling, so that a slow poll isn't joined by another.
+    polling: tokio::sync::Mutex<()>,
+}
+
+impl Bridge {
+    /// Creates a bridge around the given, already logged in, client.
+    ///
+    /// Nothing happens until the returned event loop is passed to ``Bridge::run``.
+    pub fn new(
+        profile: &str,
+        client: SharedClient,
+        cache: Option<Cache>,
+        audit: AuditLog,
+        policy: Policy,
+        config: BridgeConfig,
+    ) -> Result<(Self, EventLoop), MqttError> {
+        let broker = &config.broker;
+        let (transport, default_port) = match broker.scheme() {
+            "mqtt" | "tcp" => (Transport::tcp(), 1883),
+            "mqtts" | "ssl" => (Transport::tls_with_default_config(), 8883),
+            scheme => {
+                return Err(MqttError::InvalidBroker(format!(
+                    "unsupported scheme {scheme}, rather than mqtt or mqtts"
+                )))
+            }
+        };
+        // We log the broker's URL, so it's no place for credentials.
+        if !broker.username().is_empty() || broker.password().is_some() {
+            return Err(MqttError::InvalidBroker(
+                "credentials belong within --username and --password, rather than the URL"
+                    .to_string(),
+            ));
+        }
+        let Some(host) = broker.host_str() else {
+            return Err(MqttError::InvalidBroker("missing host".to_string()));
+        };
+        let port = broker.port().unwrap_or(default_port);
+
+        let mut options = MqttOptions::new(&config.client_id, host, port);
+        options.set_transport(transport);
+        options.set_keep_alive(Duration::from_secs(30));
+        // Commands sent while we were away may well be stale by now.
+        options.set_clean_session(true);
+        options.set_last_will(LastWill::new(
+            config.topics.availability(),
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if let Some(username) = &config.username {
+            let password = config.password.as_deref().unwrap_or_default();
+            options.set_credentials(username, password);
+        }
+
+        let (mqtt, eventloop) = AsyncClient::new(options, 64);
+        let bridge = Self {
+            profile: profile.to_string(),
+            client,
+            cache,
+            audit,
+            policy,
+            config,
+            mqtt,
+            vehicles: Mutex::new(vec![]),
+            polling: tokio::sync::Mutex::new(()),
+        };
+        Ok((bridge, eventloop))
+    }
+
+    /// Runs the bridge forever, reconnecting to the broker whenever we lose it.
+    pub async fn run(self: Arc<Self>, mut eventloop: EventLoop) {
+        let mut ticker = tokio::time::interval(self.config.poll_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // We'll poll once connected instead.
+        ticker.reset();
+        loop {
+            tokio::select! {
+                event = eventloop.poll() => match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        tracing::info!(broker = %self.config.broker, "connected to broker");
+                        let bridge = self.clone();
+                        tokio::spawn(async move { bridge.connected().await });
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        // Retained commands would be issued anew upon every reconnection.
+                        if publish.retain {
+                            tracing::warn!(topic = %publish.topic, "ignoring retained command");
+                            continue;
+                        }
+                        let bridge = self.clone();
+                        tokio::spawn(async move {
+                            bridge.command(&publish.topic, &publish.payload).await
+                        });
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        tracing::warn!(broker = %self.config.broker, "lost connection to broker: {err}");
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                    }
+                },
+                _ = ticker.tick(), if !self.config.poll_interval.is_zero() => {
+                    let bridge = self.clone();
+                    tokio::spawn(async move { bridge.poll(false).await });
+                }
+            }
+        }
+    }
+
+    /// Announces ourselves upon (re)connecting, and publishes everything anew.
+    async fn connected(&self) {
+        let topics = &self.config.topics;
+        self.publish(topics.availability(), "online", true).await;
+        // Commands are never retried, so we'd rather miss one than receive it twice.
+        if let Err(err) = self
+            .mqtt
+            .subscribe(topics.commands(), QoS::AtMostOnce)
+            .await
+        {
+            tracing::warn!("unable to subscribe to commands: {err}");
+        }
+        self.poll(true).await;
+    }
+
+    /// Publishes every vehicle's status and telemetry, alongside their discovery messages if asked.
+    #[tracing::instrument(skip(self))]
+    async fn poll(&self, announce: bool) {
+        let Ok(_polling) = self.polling.try_lock() else {
+            tracing::debug!("still polling, so skipping");
+            return;
+        };
+        let Some(client) = self.client().await else {
+            return;
+        };
+        let vehicles = match client.vehicles().await {
+            Ok(vehicles) => vehicles,
+            Err(err) => {
+                tracing::warn!("unable to poll vehicles: {err}");
+                return;
+            }
+        };
+        if let Some(cache) = &self.cache {
+            cache.store_vehicles(&vehicles);
+        }
+        // VINs become topics, so we can't have them containing wildcards or separators.
+        let vehicles: Vec<Vehicle> = vehicles
+            .into_iter()
+            .filter(|vehicle| {
+                let valid = !vehicle.vin.is_empty()
+                    && vehicle.vin.chars().all(|c| c.is_ascii_alphanumeric());
+                if !valid {
+                    tracing::warn!(vin = %vehicle.vin, "skipping vehicle with unusual VIN");
+                }
+                valid
+            })
+            .collect();
+        *self.vehicles() = vehicles.clone();
+
+        for vehicle in &vehicles {
+            let vin = &vehicle.vin;
+            let telemetry = match client.telemetry(vin).await {
+                Ok(telemetry) => {
+                    if let Some(cache) = &self.cache {
+                        cache.store_telemetry(vin, &telemetry);
+                    }
+                    Some(telemetry.convert(self.config.units))
+                }
+                Err(err) => {
+                    tracing::warn!(%vin, "unable to poll telemetry: {err}");
+                    None
+                }
+            };
+
+            let discovery_prefix = self.config.discovery_prefix.as_deref();
+            if let Some(discovery_prefix) = discovery_prefix.filter(|_| announce) {
+                let distance_unit = telemetry
+                    .as_ref()
+                    .and_then(|telemetry| telemetry.odometer.as_ref())
+                    .map(|odometer| odometer.unit.as_str())
+                    .or(match self.config.units {
+                        Units::Metric => Some("km"),
+                        Units::Imperial => Some("mi"),
+                        Units::Reported => None,
+                    });
+                let manufacturer = match client.environment().brand.as_str() {
+                    "L" => "Lexus",
+                    _ => "Toyota",
+                };
+                let topics = &self.config.topics;
+                for message in discovery(
+                    vehicle,
+                    manufacturer,
+                    topics,
+                    discovery_prefix,
+                    distance_unit,
+                ) {
+                    self.publish_json(message.topic, &message.payload, true)
+                        .await;
+                }
+            }
+
+            if let Some(telemetry) = telemetry {
+                let topics = &self.config.topics;
+                if let Some(location) = &telemetry.vehicle_location {
+                    self.publish_json(topics.location(vin), location, true)
+                        .await;
+                }
+                self.publish_json(topics.telemetry(vin), &telemetry, true)
+                    .await;
+            }
+            self.publish_status(&client, vin).await;
+        }
+    }
+
+    /// Publishes the given vehicle's status, and whether it's locked.
+    async fn publish_status(&self, client: &ApiClient, vin: &str) {
+        let status = match client.vehicle_status(vin).await {
+            Ok(status) => status,
+            Err(err) => {
+                tracing::warn!(%vin, "unable to poll status: {err}");
+                return;
+            }
+        };
+        if let Some(cache) = &self.cache {
+            cache.store_status(vin, &status);
+        }
+
+        let topics = &self.config.topics;
+        if let Some(locked) = status.locked() {
+            let state = if locked { "LOCKED" } else { "UNLOCKED" };
+            self.publish(topics.lock(vin), state, true).await;
+        }
+        self.publish_json(topics.status(vin), &status, true).await;
+    }
+
+    /// Issues a command received upon the given topic, publishing its outcome.
+    #[tracing::instrument(skip(self, payload))]
+    async fn command(&self, topic: &str, payload: &[u8]) {
+        let Some(vin) = self.config.topics.command_vin(topic) else {
+            tracing::debug!("ignoring message upon unexpected topic");
+            return;
+        };
+        let name = String::from_utf8_lossy(payload).trim().to_string();
+        let outcome = self.issue(vin, &name).await;
+        match &outcome {
+            CommandOutcome::Accepted { command, .. } => {
+                tracing::info!(%vin, "issued {command}")
+            }
+            CommandOutcome::Refused { command, reason } => {
+                tracing::warn!(%vin, "refused {command}: {reason}")
+            }
+            CommandOutcome::Failed { command, error } => {
+                tracing::warn!(%vin, "unable to issue {command}: {error}")
+            }
+        }
+        let topic = self.config.topics.command_result(vin);
+        self.publish_json(topic, &outcome, false).await;
+
+        // Let the vehicle catch up, so that our next status reflects the command.
+        if let CommandOutcome::Accepted { .. } = outcome {
+            tokio::time::sleep(COMMAND_SETTLE_DELAY).await;
+            if let Some(client) = self.client().await {
+                self.publish_status(&client, vin).await;
+            }
+        }
+    }
+
+    /// Applies our policy to the named command and, should it pass, issues it.
+    async fn issue(&self, vin: &str, name: &str) -> CommandOutcome {
+        let refuse = |reason: String| CommandOutcome::Refused {
+            command: name.to_string(),
+            reason,
+        };
+        let command: RemoteCommand = match name.parse() {
+            Ok(command) => command,
+            Err(err) => return refuse(err),
+        };
+        let cached = || {
+            let entry = self.cache.as_ref()?.capabilities(vin)?;
+            (entry.age() < CAPABILITIES_MAX_AGE).then_some(entry.value)
+        };
+        let eligible = safety::check_eligible(&self.vehicles(), vin, command, cached);
+        if let Err(refusal) = eligible {
+            return refuse(refusal.to_string());
+        }
+        // Nobody is at a terminal to confirm anything, so the policy must permit it as is.
+        if let Err(refusal) = self.policy.check(command, Origin::Automation) {
+            return refuse(refusal.to_string());
+        }
+        if self.policy.needs_confirmation(command) && !self.config.allowed.contains(&command) {
+            let refusal = Refusal::Unconfirmed(command);
+            return refuse(format!(
+                "{refusal}; pass --allow {command} to permit it over MQTT"
+            ));
+        }
+
+        let Some(client) = self.client().await else {
+            return CommandOutcome::Failed {
+                command,
+                error: "unable to refresh session".to_string(),
+            };
+        };
+        let issued = self.audit.issue(&client, &self.profile, vin, command).await;
+        // Whatever status we had cached is now out of date.
+        if let Some(cache) = &self.cache {
+            cache.invalidate(vin);
+        }
+        match issued {
+            Ok(result) => CommandOutcome::Accepted {
+                command,
+                request_id: result.app_request_no,
+            },
+            Err(err) => CommandOutcome::Failed {
+                command,
+                error: redact::redact(&err.to_string()),
+            },
+        }
+    }
+
+    /// The vehicles we last saw on the account.
+    ///
+    /// We only ever replace them wholesale, so a panic elsewhere while they
+    /// were held can't have left them half-written.
+    fn vehicles(&self) -> MutexGuard<'_, Vec<Vehicle>> {
+        self.vehicles.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Our client, refreshed as necessary.
+    async fn client(&self) -> Option<tokio::sync::RwLockReadGuard<'_, ApiClient>> {
+        match self.client.get().await {
+            Ok(client) => Some(client),
+            Err(err) => {
+                tracing::error!("unable to refresh session, so please log in via toyotactl: {err}");
+                None
+            }
+        }
+    }
+
+    async fn publish_json(&self, topic: String, value: &impl Serialize, retain: bool) {
+        match serde_json::to_vec(value) {
+            Ok(payload) => self.publish(topic, payload, retain).await,
+            Err(err) => tracing::warn!(%topic, "unable to serialize message: {err}"),
+        }
+    }
+
+    async fn publish(&self, topic: String, payload: impl Into<Vec<u8>>, retain: bool) {
+        let result = self
+            .mqtt
+            .publish(&topic, QoS::AtLeastOnce, retain, payload)
+            .await;
+        if let Err(err) = result {
+            tracing::warn!(%topic, "unable to publish: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::ApiClient,
+        environment::Environment,
+        fixture::{Fixture, Replayer},
+        http::{HttpClient, HttpConfig},
+        storage::MemoryStore,
+    };
+
+    const VIN: &str = "JTMOCK00000000001";
+
+    #[test]
+    fn command_vin() {
+        let topics = Topics::new("toyotactl/");
+        assert_eq!(topics.commands(), "toyotactl/+/command");
+        assert_eq!(topics.command_vin(&topics.command(VIN)), Some(VIN));
+        assert_eq!(
+            topics.command_vin("toyotactl/JTMOCK00000000001/command"),
+            Some(VIN)
+        );
+
+        // Nothing else beneath our prefix is a command.
+        assert_eq!(topics.command_vin(&topics.command_result(VIN)), None);
+        assert_eq!(topics.command_vin(&topics.status(VIN)), None);
+        assert_eq!(topics.command_vin("toyotactl/a/b/command"), None);
+        // Nor is anything beneath another prefix, even one sharing ours.
+        assert_eq!(
+            topics.command_vin("toyotactl2/JTMOCK00000000001/command"),
+            None
+        );
+        assert_eq!(topics.command_vin("other/JTMOCK00000000001/command"), None);
+    }
+
+    /// A bridge which never connects, whose client can reach nothing.
+    struct TestBridge {
+        bridge: Bridge,
+        audit_path: std::path::PathBuf,
+    }
+
+    impl TestBridge {
+        fn new(name: &str, policy: Policy, allowed: Vec<RemoteCommand>) -> Self {
+            let http = HttpClient::new(&HttpConfig::default())
+                .unwrap()
+                .with_replayer(Replayer::new(Fixture::default()));
+            let client = ApiClient::new(
+                http,
+                Environment::production(),
+                "access-token".to_string(),
+                "guid".to_string(),
+                Arc::new(MemoryStore::new()),
+            );
+            let audit_path = std::env::temp_dir().join(format!(
+                "toyotactl-mqtt-{}-{name}.jsonl",
+                std::process::id()
+            ));
+            let config = BridgeConfig {
+                broker: "mqtt://127.0.0.1:9".parse().unwrap(),
+                username: None,
+                password: None,
+                client_id: "toyotactl-test".to_string(),
+                topics: Topics::new("toyotactl"),
+                discovery_prefix: None,
+                poll_interval: Duration::ZERO,
+                units: Units::Reported,
+                allowed,
+            };
+            let (bridge, _) = Bridge::new(
+                "default",
+                SharedClient::new(client),
+                None,
+                AuditLog::open(&audit_path).unwrap(),
+                policy,
+                config,
+            )
+            .unwrap();
+            *bridge.vehicles() =
+                vec![serde_json::from_value(serde_json::json!({ "vin": VIN })).unwrap()];
+            Self { bridge, audit_path }
+        }
+    }
+
+    impl Drop for TestBridge {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.audit_path);
+        }
+    }
+
+    fn refusal(outcome: CommandOutcome) -> String {
+        match outcome {
+            CommandOutcome::Refused { reason, .. } => reason,
+            outcome => panic!("expected a refusal, not {outcome:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_commands_are_refused() {
+        let test = TestBridge::new("unconfirmed", Policy::default(), vec![]);
+        // Unlocking requires confirmation by default, which nobody can give over MQTT.
+        let reason = refusal(test.bridge.issue(VIN, "door-unlock").await);
+        assert!(reason.contains("--allow door-unlock"), "{reason}");
+
+        let reason = refusal(test.bridge.issue(VIN, "unlock-everything").await);
+        assert!(reason.contains("unknown remote command"), "{reason}");
+        let reason = refusal(test.bridge.issue("JTOTHER0000000000", "door-lock").await);
+        assert!(reason.contains("not a vehicle on this account"), "{reason}");
+        // Nothing refused is recorded, as nothing was issued.
+        assert!(AuditLog::entries(&test.audit_path).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn allowed_commands_are_issued() {
+        let test = TestBridge::new(
+            "allowed",
+            Policy::default(),
+            vec![RemoteCommand::DoorUnlock],
+        );
+        // Our client can reach nothing, so the attempt itself fails, rather than being refused.
+        let outcome = test.bridge.issue(VIN, "door-unlock").await;
+        assert!(
+            matches!(outcome, CommandOutcome::Failed { .. }),
+            "{outcome:?}"
+        );
+        // Commands never requiring confirmation needn't be allowed.
+        let outcome = test.bridge.issue(VIN, "door-lock").await;
+        assert!(
+            matches!(outcome, CommandOutcome::Failed { .. }),
+            "{outcome:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn policy_applies_despite_allowed() {
+        let policy = Policy {
+            interactive_only: vec![RemoteCommand::EngineStart],
+            ..Policy::default()
+        };
+        let test = TestBridge::new("policy", policy, vec![RemoteCommand::EngineStart]);
+        let reason = refusal(test.bridge.issue(VIN, "engine-start").await);
+        assert!(!reason.contains("--allow"), "{reason}");
+    }
+
+    #[tokio::test]
+    async fn poisoned_vehicles_remain_usable() {
+        let test = Arc::new(TestBridge::new("poisoned", Policy::default(), vec![]));
+        let poisoner = test.clone();
+        let _ = std::thread::spawn(move || {
+            let _vehicles = poisoner.bridge.vehicles();
+            panic!("poisoning our vehicles");
+        })
+        .join();
+        assert!(test.bridge.vehicles.is_poisoned());
+
+        let reason = refusal(test.bridge.issue("JTOTHER0000000000", "door-lock").await);
+        assert!(reason.contains("not a vehicle on this account"), "{reason}");
+    }
+}
@@ -0,0 +1,515 @@
+//! Rendering results for humans and scripts alike.
+//!
+//! JSON and YAML follow the serde shapes of our API types, which we'll keep stable.
+//! Tables and CSV share the same columns, and templates substitute `{field}`
+//! (or `{nested.field}`) with values from the JSON shape. For example:
+//! ```sh
+//! toyotactl --output 'template=⛽ {fuelLevel}%' telemetry <VIN>
+//! ```
+
+use serde::Serialize;
+use serde_json::Value;
+use std::{fmt, str::FromStr};
+
+use crate::{
+    api::{PreparedRequest, RemoteCommandResult, Telemetry, Vehicle, VehicleStatus},
+    audit::AuditEntry,
+};
+
+/// How results should be printed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Aligned columns, for humans.
+    #[default]
+    Table,
+    Json,
+    Yaml,
+    Csv,
+    /// A user-supplied format string, rendered once per item.
+    Template(String),
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "yaml" => Ok(OutputFormat::Yaml),
+            "csv" => Ok(OutputFormat::Csv),
+            "template" => Err("template requires a format, such as template={vin}".to_string()),
+            _ => match s.strip_prefix("template=") {
+                Some(template) => Ok(OutputFormat::Template(template.to_string())),
+                None => Err(format!(
+                    "unknown output format: {s} (expected table, json, yaml, csv or template=...)"
+                )),
+            },
+        }
+    }
+}
+
+/// Possible errors while rendering output.
+#[derive(Debug)]
+pub enum OutputError {
+    Json(serde_json::Error),
+    Yaml(serde_yaml::Error),
+    Csv(csv::Error),
+    /// The template could not be parsed.
+    Template(String),
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputError::Json(err) => write!(f, "unable to serialize JSON: {err}"),
+            OutputError::Yaml(err) => write!(f, "unable to serialize YAML: {err}"),
+            OutputError::Csv(err) => write!(f, "unable to write CSV: {err}"),
+            OutputError::Template(reason) => write!(f, "invalid template: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for OutputError {}
+
+/// A result that can be laid out as rows and columns.
+pub trait Tabular: Serialize {
+    /// The name of every column.
+    fn headers() -> Vec<&'static str>;
+    /// Every row, with one value per column.
+    fn rows(&self) -> Vec<Vec<String>>;
+}
+
+impl<T: Tabular> Tabular for Vec<T> {
+    fn headers() -> Vec<&'static str> {
+        T::headers()
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.iter().flat_map(Tabular::rows).collect()
+    }
+}
+
+/// Renders the given value in the given format, ready to be printed.
+pub fn render<T: Tabular>(value: &T, format: &OutputFormat) -> Result<String, OutputError> {
+    match format {
+        OutputFormat::Table => Ok(table(&T::headers(), &value.rows())),
+        OutputFormat::Json => serde_json::to_string_pretty(value).map_err(OutputError::Json),
+        OutputFormat::Yaml => serde_yaml::to_string(value)
+            .map(|yaml| yaml.trim_end().to_string())
+            .map_err(OutputError::Yaml),
+        OutputFormat::Csv => csv(&T::headers(), &value.rows()),
+        OutputFormat::Template(template) => {
+            let value = serde_json::to_value(value).map_err(OutputError::Json)?;
+            // Lists are rendered one item per line.
+            let items = match value {
+                Value::Array(items) => items,
+                value => vec![value],
+            };
+            let lines = items
+                .iter()
+                .map(|item| render_template(template, item))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(lines.join("\n"))
+        }
+    }
+}
+
+/// Lays out rows beneath their headers, with columns padded to align.
+fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers
+        .iter()
+        .map(|header| header.chars().count())
+        .collect();
+    for row in rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+
+    let line = |values: Vec<&str>| {
+        let padded: Vec<String> = values
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{value:width$}"))
+            .collect();
+        padded.join("  ").trim_end().to_string()
+    };
+    let mut lines = vec![line(headers.to_vec())];
+    lines.extend(
+        rows.iter()
+            .map(|row| line(row.iter().map(String::as_str).collect())),
+    );
+    lines.join("\n")
+}
+
+fn csv(headers: &[&str], rows: &[Vec<String>]) -> Result<String, OutputError> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(headers).map_err(OutputError::Csv)?;
+    for row in rows {
+        writer.write_record(row).map_err(OutputError::Csv)?;
+    }
+    let contents = writer
+        .into_inner()
+        .map_err(|err| OutputError::Csv(err.into_error().into()))?;
+    Ok(String::from_utf8_lossy(&contents).trim_end().to_string())
+}
+
+/// Substitutes every `{path}` within the template with its value.
+///
+/// Missing values are left empty, as most fields are optional. `{{` and `}}`
+/// produce literal braces.
+fn render_template(template: &str, value: &Value) -> Result<String, OutputError> {
+    let mut rendered = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                rendered.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                rendered.push('}');
+            }
+            '{' => {
+                let mut path = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => path.push(c),
+                        None => {
+                            return Err(OutputError::Template(format!("unclosed {{{path}")));
+                        }
+                    }
+                }
+                if path.trim().is_empty() || path.contains('{') {
+                    return Err(OutputError::Template(format!(
+                        "expected a field name within {{{path}}}"
+                    )));
+                }
+                rendered.push_str(&lookup(value, path.trim()));
+            }
+            '}' => {
+                return Err(OutputError::Template(
+                    "unmatched }, use }} for a literal brace".to_string(),
+                ))
+            }
+            c => rendered.push(c),
+        }
+    }
+    Ok(rendered)
+}
+
+/// Resolves a dotted path, such as `odometer.value` or `vehicleStatus.0.category`.
+fn lookup(value: &Value, path: &str) -> String {
+    let found = path.split('.').try_fold(value, |value, key| match value {
+        Value::Array(items) => key.parse::<usize>().ok().and_then(|index| items.get(index)),
+        value => value.get(key),
+    });
+    match found {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(string)) => string.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
+/// Formats an optional value, leaving it blank if absent.
+fn optional<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(ToString::to_string).unwrap_or_default()
+}
+
+impl Tabular for Vehicle {
+    fn headers() -> Vec<&'static str> {
+        vec!["VIN", "NICKNAME", "MODEL", "YEAR"]
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![vec![
+            self.vin.clone(),
+            optional(&self.nick_name),
+            optional(&self.model_name),
+            optional(&self.model_year),
+        ]]
+    }
+}
+
+impl Tabular for VehicleStatus {
+    fn headers() -> Vec<&'static str> {
+        vec!["CATEGORY", "SECTION", "VALUE", "ATTENTION"]
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![];
+        for category in &self.vehicle_status {
+            for section in &category.sections {
+                for value in &section.values {
+                    rows.push(vec![
+                        category.category.clone(),
+                        section.section.clone(),
+                        value.value.clone(),
+                        if value.status == 0 { "no" } else { "yes" }.to_string(),
+                    ]);
+                }
+            }
+        }
+        rows
+    }
+}
+
+impl Tabular for Telemetry {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "FUEL",
+            "ODOMETER",
+            "RANGE",
+            "LATITUDE",
+            "LONGITUDE",
+            "UPDATED",
+        ]
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let measurement = |measurement: &Option<crate::api::Measurement>| {
+            measurement
+                .as_ref()
+                .map(|measurement| format!("{} {}", measurement.value, measurement.unit))
+                .unwrap_or_default()
+        };
+        vec![vec![
+            self.fuel_level
+                .map(|level| format!("{level}%"))
+                .unwrap_or_default(),
+            measurement(&self.odometer),
+            measurement(&self.distance_to_empty),
+            optional(&self.vehicle_location.as_ref().map(|l| l.latitude)),
+            optional(&self.vehicle_location.as_ref().map(|l| l.longitude)),
+            optional(&self.last_timestamp),
+        ]]
+    }
+}
+
+impl Tabular for RemoteCommandResult {
+    fn headers() -> Vec<&'static str> {
+        vec!["REQUEST", "RETURN CODE"]
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![vec![
+            optional(&self.app_request_no),
+            optional(&self.return_code),
+        ]]
+    }
+}
+
+impl Tabular for PreparedRequest {
+    fn headers() -> Vec<&'static str> {
+        vec!["FIELD", "VALUE"]
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![
+            vec!["method".to_string(), self.method.clone()],
+            vec!["url".to_string(), self.url.clone()],
+        ];
+        rows.extend(
+            self.headers
+                .iter()
+                .map(|(name, value)| vec![format!("header {name}"), value.clone()]),
+        );
+        if let Some(body) = &self.body {
+            rows.push(vec!["body".to_string(), body.to_string()]);
+        }
+        rows
+    }
+}
+
+impl Tabular for AuditEntry {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "TIME", "PROFILE", "VIN", "COMMAND", "OUTCOME", "REQUEST", "USER", "HOST",
+        ]
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![vec![
+            self.timestamp
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            self.profile.clone(),
+            self.vin.clone(),
+            self.command.to_string(),
+            self.outcome.to_string(),
+            optional(&self.request_id),
+            optional(&self.user),
+            optional(&self.host),
+        ]]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+    use serde_json::json;
+
+    /// Parses the given API response, and renders it back out as JSON.
+    fn json_shape<T: Tabular + DeserializeOwned>(response: Value) -> Value {
+        let value: T = serde_json::from_value(response).unwrap();
+        serde_json::from_str(&render(&value, &OutputFormat::Json).unwrap()).unwrap()
+    }
+
+    fn telemetry() -> Value {
+        json!({
+            "fuelLevel": 75.0,
+            "odometer": { "value": 12345.0, "unit": "mi" },
+            "distanceToEmpty": { "value": 375.0, "unit": "mi" },
+            "vehicleLocation": { "latitude": 33.0841, "longitude": -96.8381 },
+            "lastTimestamp": "2024-06-01T12:00:00Z",
+        })
+    }
+
+    #[test]
+    fn template_substitution() {
+        let value = telemetry();
+        assert_eq!(
+            render_template(
+                "{fuelLevel}% ({distanceToEmpty.value} {distanceToEmpty.unit})",
+                &value
+            )
+            .unwrap(),
+            "75.0% (375.0 mi)"
+        );
+        assert_eq!(
+            render_template("{ vehicleLocation.latitude }", &value).unwrap(),
+            "33.0841"
+        );
+        // Objects render as JSON, and arrays are indexed.
+        assert_eq!(
+            render_template("{odometer}", &value).unwrap(),
+            r#"{"unit":"mi","value":12345.0}"#
+        );
+        let list = json!({ "items": [{ "name": "first" }, { "name": "second" }] });
+        assert_eq!(render_template("{items.1.name}", &list).unwrap(), "second");
+    }
+
+    #[test]
+    fn template_missing_fields_and_braces() {
+        let value = telemetry();
+        assert_eq!(render_template("[{missing}]", &value).unwrap(), "[]");
+        assert_eq!(
+            render_template("[{odometer.missing.deeper}]", &value).unwrap(),
+            "[]"
+        );
+        assert_eq!(
+            render_template("[{items.0}]", &json!({ "items": [] })).unwrap(),
+            "[]"
+        );
+        assert_eq!(
+            render_template("[{value}]", &json!({ "value": null })).unwrap(),
+            "[]"
+        );
+        assert_eq!(
+            render_template("{{literal}} {fuelLevel}", &value).unwrap(),
+            "{literal} 75.0"
+        );
+        assert_eq!(render_template("no fields", &value).unwrap(), "no fields");
+    }
+
+    #[test]
+    fn template_errors() {
+        let value = telemetry();
+        for invalid in ["{fuelLevel", "{}", "{ }", "{a{b}", "fuel}", "{fuelLevel}}"] {
+            assert!(
+                matches!(
+                    render_template(invalid, &value),
+                    Err(OutputError::Template(_))
+                ),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn templates_render_per_item() {
+        let vehicles: Vec<Vehicle> = serde_json::from_value(json!([
+            { "vin": "VIN1", "nickName": "Tacoma" },
+            { "vin": "VIN2" },
+        ]))
+        .unwrap();
+        let format = OutputFormat::Template("{vin}={nickName}".to_string());
+        assert_eq!(render(&vehicles, &format).unwrap(), "VIN1=Tacoma\nVIN2=");
+    }
+
+    #[test]
+    fn csv_quoting() {
+        let rows = vec![
+            vec!["plain".to_string(), "with, comma".to_string()],
+            vec![r#"with "quotes""#.to_string(), "with\nnewline".to_string()],
+            vec![String::new(), "trailing ".to_string()],
+        ];
+        assert_eq!(
+            csv(&["A", "B"], &rows).unwrap(),
+            "A,B\nplain,\"with, comma\"\n\"with \"\"quotes\"\"\",\"with\nnewline\"\n,trailing"
+        );
+    }
+
+    #[test]
+    fn table_alignment() {
+        let rows = vec![
+            vec!["JTMOCK00000000001".to_string(), "Tacoma".to_string()],
+            vec!["SHORT".to_string(), String::new()],
+        ];
+        assert_eq!(
+            table(&["VIN", "NICKNAME"], &rows),
+            "VIN                NICKNAME\nJTMOCK00000000001  Tacoma\nSHORT"
+        );
+    }
+
+    #[test]
+    fn vehicle_shape() {
+        let vehicle = json!({
+            "vin": "JTMOCK00000000001",
+            "nickName": "Tacoma",
+            "modelName": "Tacoma",
+            "modelYear": "2024",
+            "remoteServiceCapabilities": {
+                "dlockUnlockCapable": true,
+                "estartStopCapable": false,
+                "hazardCapable": true,
+            },
+        });
+        assert_eq!(json_shape::<Vehicle>(vehicle.clone()), vehicle);
+
+        // Absent fields are omitted, rather than null.
+        let sparse = json!({ "vin": "JTMOCK00000000001" });
+        assert_eq!(json_shape::<Vehicle>(sparse.clone()), sparse);
+    }
+
+    #[test]
+    fn status_shape() {
+        let status = json!({
+            "occurrenceDate": "2024-06-01T12:00:00Z",
+            "latitude": 33.0841,
+            "longitude": -96.8381,
+            "vehicleStatus": [{
+                "category": "Driver Side",
+                "sections": [{
+                    "section": "Door",
+                    "values": [{ "value": "Locked", "status": 0 }],
+                }],
+            }],
+        });
+        assert_eq!(json_shape::<VehicleStatus>(status.clone()), status);
+    }
+
+    #[test]
+    fn telemetry_shape() {
+        assert_eq!(json_shape::<Telemetry>(telemetry()), telemetry());
+    }
+
+    #[test]
+    fn command_result_shape() {
+        let result = json!({ "appRequestNo": "request", "returnCode": "000000" });
+        assert_eq!(json_shape::<RemoteCommandResult>(result.clone()), result);
+    }
+}
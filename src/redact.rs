@@ -0,0 +1,481 @@
+//! Redaction of secrets within anything we log.
+//!
+//! Secrets are recognized in two ways. Some are recognizable by shape alone,
+//! such as JWTs or our `iPlanetDirectoryPro` cookie. Others, such as passwords,
+//! one-time passwords or the API gateway key, look like any other string.
+//! We'll have those registered via ``register_secret`` as soon as we know them.
+//!
+//! We hold a single value of each kind of secret, replacing it whenever that
+//! secret changes, such as once our tokens are refreshed. A long-running daemon
+//! or bridge would otherwise accumulate every token it ever held, checking each
+//! against every line it logs.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use serde_json::Value;
+use std::{
+    io,
+    sync::{PoisonError, RwLock},
+};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// What every secret is replaced with.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// JSON keys whose values are always secret.
+const SECRET_KEYS: [&str; 9] = [
+    "password",
+    "access_token",
+    "refresh_token",
+    "id_token",
+    "tokenId",
+    "authId",
+    "code",
+    "x-api-key",
+    "authorization",
+];
+
+/// HTTP headers whose values are secret in their entirety.
+const SECRET_HEADERS: [&str; 4] = ["authorization", "x-api-key", "cookie", "set-cookie"];
+
+/// Callback types whose inputs are always secret.
+const SECRET_CALLBACKS: [&str; 2] = ["PasswordCallback", "HiddenValueCallback"];
+
+/// The name of the cookie ForgeRock stores its session token within.
+const SESSION_COOKIE: &str = "iPlanetDirectoryPro";
+
+/// When scrubbing fixtures, JWTs are given this expiry (2100-01-01)
+/// so that they remain usable when replayed.
+const SCRUBBED_EXPIRY: u64 = 4_102_444_800;
+
+/// What JWT signatures are replaced with when scrubbing fixtures.
+const SCRUBBED_SIGNATURE: &str = "c2NydWJiZWQ";
+
+/// How we redact secrets.
+#[derive(Clone, Copy)]
+enum Mode {
+    /// For logs and captures: every secret is replaced by ``REDACTED``.
+    Logs,
+    /// For fixtures: JWTs keep their claims, as we'd otherwise be unable to log in.
+    /// Their signatures are discarded, and their expiry pushed far into the future.
+    Fixtures,
+}
+
+/// The kinds of secret we learn at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Secret {
+    Password,
+    OneTimePassword,
+    /// ForgeRock's session token, i.e. our `iPlanetDirectoryPro` cookie.
+    SessionToken,
+    AuthorizationCode,
+    AccessToken,
+    RefreshToken,
+    IdToken,
+    GatewayKey,
+    /// The password for the MQTT broker we bridge to.
+    BrokerPassword,
+}
+
+/// Secrets learned at runtime, at most one per kind.
+#[derive(Debug, Default)]
+struct Secrets(Vec<(Secret, String)>);
+
+impl Secrets {
+    const fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Registers the given secret, replacing whatever secret of the same kind came before it.
+    ///
+    /// Extremely short values would result in redacting unrelated output, so we ignore
+    /// them entirely. Our previous secret of that kind remains redacted.
+    fn register(&mut self, kind: Secret, secret: &str) {
+        if secret.len() < 4 {
+            return;
+        }
+        self.0.retain(|(existing, _)| *existing != kind);
+        self.0.push((kind, secret.to_string()));
+    }
+
+    /// Replaces every registered secret within the given text.
+    fn redact(&self, mut text: String) -> String {
+        for (_, secret) in &self.0 {
+            if text.contains(secret.as_str()) {
+                text = text.replace(secret.as_str(), REDACTED);
+            }
+        }
+        text
+    }
+}
+
+static SECRETS: RwLock<Secrets> = RwLock::new(Secrets::new());
+
+/// Ensures the given value will be redacted wherever it appears,
+/// replacing whatever secret of the same kind was registered before it.
+pub fn register_secret(kind: Secret, secret: &str) {
+    SECRETS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .register(kind, secret);
+}
+
+/// Whether the given string appears to be a JWT.
+///
+/// JWTs are three base64url components separated by periods,
+/// and their JSON header always begins with `eyJ` once encoded.
+fn is_jwt(candidate: &str) -> bool {
+    let parts: Vec<&str> = candidate.split('.').collect();
+    parts.len() == 3
+        && parts[0].starts_with("eyJ")
+        && parts.iter().all(|part| {
+            !part.is_empty()
+                && part
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+        })
+}
+
+/// Replaces the given JWT per our mode.
+fn replace_jwt(jwt: &str, mode: Mode) -> String {
+    match mode {
+        Mode::Logs => REDACTED.to_string(),
+        Mode::Fixtures => scrub_jwt(jwt).unwrap_or_else(|| REDACTED.to_string()),
+    }
+}
+
+/// Re-encodes the given JWT with an unusable signature and far-future expiry.
+fn scrub_jwt(jwt: &str) -> Option<String> {
+    let mut components = jwt.split('.');
+    let header = components.next()?;
+    let payload = URL_SAFE_NO_PAD.decode(components.next()?).ok()?;
+
+    let mut claims: serde_json::Map<String, Value> = serde_json::from_slice(&payload).ok()?;
+    if claims.contains_key("exp") {
+        claims.insert("exp".to_string(), SCRUBBED_EXPIRY.into());
+    }
+    let payload = URL_SAFE_NO_PAD.encode(Value::Object(claims).to_string());
+    Some(format!("{header}.{payload}.{SCRUBBED_SIGNATURE}"))
+}
+
+/// Redacts all secrets within the given text.
+pub fn redact(text: &str) -> String {
+    redact_text(text, Mode::Logs)
+}
+
+fn redact_text(text: &str, mode: Mode) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+
+    // We'll walk through the text token by token, redacting JWTs
+    // and the value following our session cookie.
+    let is_token_byte = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+    while let Some(start) = rest.find(is_token_byte) {
+        redacted.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_token_byte(c)).unwrap_or(rest.len());
+        let token = &rest[..end];
+
+        if is_jwt(token) {
+            redacted.push_str(&replace_jwt(token, mode));
+        } else if token == SESSION_COOKIE && rest[end..].starts_with('=') {
+            // Keep the cookie's name, and skip past its value.
+            redacted.push_str(SESSION_COOKIE);
+            redacted.push('=');
+            redacted.push_str(REDACTED);
+            let value = &rest[end + 1..];
+            let value_end = value.find([';', ' ', '"', '\n']).unwrap_or(value.len());
+            rest = &value[value_end..];
+            continue;
+        } else {
+            redacted.push_str(token);
+        }
+        rest = &rest[end..];
+    }
+    redacted.push_str(rest);
+
+    // Lastly, let's remove anything we've been told is secret.
+    SECRETS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .redact(redacted)
+}
+
+/// Redacts all secrets within the given JSON, preserving its structure.
+pub fn redact_json(value: &Value) -> Value {
+    redact_value(value, Mode::Logs)
+}
+
+fn redact_value(value: &Value, mode: Mode) -> Value {
+    match value {
+        Value::Object(object) => {
+            let is_secret_callback = object
+                .get("type")
+                .and_then(Value::as_str)
+                .is_some_and(|callback_type| SECRET_CALLBACKS.contains(&callback_type));
+
+            let mut redacted = object.clone();
+            for (key, value) in redacted.iter_mut() {
+                *value = if is_secret_key(key) {
+                    redact_leaves(value, mode)
+                } else if is_secret_callback && key == "input" {
+                    redact_input_values(value, mode)
+                } else {
+                    redact_value(value, mode)
+                };
+            }
+            Value::Object(redacted)
+        }
+        Value::Array(array) => Value::Array(
+            array
+                .iter()
+                .map(|value| redact_value(value, mode))
+                .collect(),
+        ),
+        Value::String(string) => Value::String(redact_text(string, mode)),
+        other => other.clone(),
+    }
+}
+
+/// Redacts every non-empty string within the given value.
+fn redact_leaves(value: &Value, mode: Mode) -> Value {
+    match value {
+        Value::String(string) if string.is_empty() => value.clone(),
+        Value::String(string) if is_jwt(string) => Value::String(replace_jwt(string, mode)),
+        Value::String(_) => Value::String(REDACTED.to_string()),
+        Value::Array(array) => Value::Array(
+            array
+                .iter()
+                .map(|value| redact_leaves(value, mode))
+                .collect(),
+        ),
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .map(|(key, value)| (key.clone(), redact_leaves(value, mode)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Redacts the `value` of every callback input, keeping its `name`.
+fn redact_input_values(inputs: &Value, mode: Mode) -> Value {
+    let Value::Array(inputs) = inputs else {
+        return redact_leaves(inputs, mode);
+    };
+
+    let redacted = inputs
+        .iter()
+        .map(|input| {
+            let mut input = redact_value(input, mode);
+            if let Some(value) = input.get_mut("value") {
+                *value = redact_leaves(value, mode);
+            }
+            input
+        })
+        .collect();
+    Value::Array(redacted)
+}
+
+/// Whether the given HTTP header's value is always secret.
+pub fn is_secret_header(name: &str) -> bool {
+    SECRET_HEADERS
+        .iter()
+        .any(|secret_header| secret_header.eq_ignore_ascii_case(name))
+}
+
+/// Whether the given JSON key or query parameter is always secret.
+fn is_secret_key(key: &str) -> bool {
+    SECRET_KEYS
+        .iter()
+        .any(|secret_key| secret_key.eq_ignore_ascii_case(key))
+}
+
+/// Redacts the given query parameter's value, should it be secret.
+pub fn redact_query_value(name: &str, value: &str) -> String {
+    redact_query_value_as(name, value, Mode::Logs)
+}
+
+fn redact_query_value_as(name: &str, value: &str, mode: Mode) -> String {
+    if is_secret_key(name) && !value.is_empty() {
+        REDACTED.to_string()
+    } else {
+        redact_text(value, mode)
+    }
+}
+
+/// Redacts all secrets within the given URL, including within its query.
+///
+/// Our OAuth2 redirect URI uses a custom scheme, so we can't assume this is HTTP.
+pub fn redact_url(url: &str) -> String {
+    redact_url_as(url, Mode::Logs)
+}
+
+fn redact_url_as(url: &str, mode: Mode) -> String {
+    let Ok(mut parsed) = url::Url::parse(url) else {
+        return redact_text(url, mode);
+    };
+    if parsed.query().is_some() {
+        let pairs: Vec<(String, String)> = parsed
+            .query_pairs()
+            .map(|(name, value)| {
+                let value = redact_query_value_as(&name, &value, mode);
+                (name.into_owned(), value)
+            })
+            .collect();
+        parsed.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    // Form encoding will have escaped our brackets, which we'll revert for legibility.
+    let redacted = parsed.as_str().replace("%5BREDACTED%5D", REDACTED);
+    redact_text(&redacted, mode)
+}
+
+/// Redacts the given text should it be JSON, or otherwise as plain text.
+pub fn redact_body(body: &str) -> String {
+    redact_body_as(body, Mode::Logs)
+}
+
+/// Scrubs secrets from the given body for use within a fixture.
+///
+/// Unlike ``redact_body``, JWTs keep their claims so that they remain usable.
+pub fn scrub_body(body: &str) -> String {
+    redact_body_as(body, Mode::Fixtures)
+}
+
+/// Scrubs secrets from the given URL for use within a fixture.
+pub fn scrub_url(url: &str) -> String {
+    redact_url_as(url, Mode::Fixtures)
+}
+
+fn redact_body_as(body: &str, mode: Mode) -> String {
+    match serde_json::from_str::<Value>(body) {
+        Ok(value) => redact_value(&value, mode).to_string(),
+        Err(_) => redact_text(body, mode),
+    }
+}
+
+/// Wraps a writer used for logging, redacting everything written through it.
+///
+/// This is our last line of defense: even if something secret is logged
+/// by accident, it should never make it to the terminal or a log file.
+#[derive(Debug, Clone)]
+pub struct RedactingMakeWriter<M> {
+    inner: M,
+}
+
+impl<M> RedactingMakeWriter<M> {
+    pub fn new(inner: M) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for RedactingMakeWriter<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter {
+            inner: self.inner.make_writer(),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+/// A writer that redacts its contents once flushed or dropped.
+///
+/// We buffer, as a secret may otherwise be split across multiple writes.
+pub struct RedactingWriter<W: io::Write> {
+    inner: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: io::Write> io::Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let contents = String::from_utf8_lossy(&self.buffer);
+            self.inner.write_all(redact(&contents).as_bytes())?;
+            self.buffer.clear();
+        }
+        self.inner.flush()
+    }
+}
+
+impl<W: io::Write> Drop for RedactingWriter<W> {
+    fn drop(&mut self) {
+        let _ = io::Write::flush(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // Our secrets are global, and our tests run in parallel,
+    // so we'll register secrets within our own ``Secrets`` instead.
+
+    #[test]
+    fn rotated_secrets_are_replaced() {
+        let mut secrets = Secrets::default();
+        secrets.register(Secret::BrokerPassword, "first-password");
+        secrets.register(Secret::BrokerPassword, "second-password");
+        secrets.register(Secret::Password, "hunter22");
+
+        assert_eq!(
+            secrets.redact("first-password, second-password, hunter22".to_string()),
+            "first-password, [REDACTED], [REDACTED]"
+        );
+    }
+
+    #[test]
+    fn short_secrets_are_ignored() {
+        let mut secrets = Secrets::default();
+        secrets.register(Secret::OneTimePassword, "12");
+        assert_eq!(secrets.redact("123456 and 12".to_string()), "123456 and 12");
+
+        // A short secret doesn't replace the one before it, either.
+        secrets.register(Secret::OneTimePassword, "123456");
+        secrets.register(Secret::OneTimePassword, "12");
+        assert_eq!(
+            secrets.redact("123456 and 12".to_string()),
+            "[REDACTED] and 12"
+        );
+    }
+
+    #[test]
+    fn jwts_and_session_cookie() {
+        let jwt = "eyJhbGciOiJub25lIn0.eyJzdWIiOiJndWlkIn0.c2ln";
+        assert_eq!(redact(&format!("Bearer {jwt}")), "Bearer [REDACTED]");
+        assert_eq!(
+            redact("Cookie: iPlanetDirectoryPro=abc.def; other=1"),
+            "Cookie: iPlanetDirectoryPro=[REDACTED]; other=1"
+        );
+    }
+
+    #[test]
+    fn secret_keys_and_callbacks() {
+        let body = json!({
+            "access_token": "opaque",
+            "nickName": "Tacoma",
+            "callbacks": [{
+                "type": "PasswordCallback",
+                "input": [{"name": "IDToken2", "value": "hunter22"}],
+            }],
+        });
+        assert_eq!(
+            redact_json(&body),
+            json!({
+                "access_token": REDACTED,
+                "nickName": "Tacoma",
+                "callbacks": [{
+                    "type": "PasswordCallback",
+                    "input": [{"name": "IDToken2", "value": REDACTED}],
+                }],
+            })
+        );
+    }
+}
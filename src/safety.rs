@@ -0,0 +1,446 @@
+//! Interlocks between a request and a vehicle actually doing something.
+//!
+//! Remote commands have real-world consequences, so each profile may restrict them:
+//! ```toml
+//! [profiles.default.policy]
+//! # Commands requiring confirmation. By default, everything but locking and hazards off.
+//! confirm = ["door-unlock", "engine-start", "engine-stop", "hazard-on"]
+//! # Commands never allowed without a person at a terminal.
+//! interactive_only = ["door-unlock"]
+//!
+//! [profiles.default.policy.allowed_hours]
+//! # Local time. Ranges may wrap past midnight, such as 22:00-06:00.
+//! engine-start = "06:00-22:00"
+//! ```
+
+use chrono::Timelike;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use crate::api::{RemoteCapabilities, RemoteCommand, Vehicle};
+
+/// Commands requiring confirmation when a policy doesn't specify otherwise.
+pub const DEFAULT_CONFIRM: [RemoteCommand; 4] = [
+    RemoteCommand::DoorUnlock,
+    RemoteCommand::EngineStart,
+    RemoteCommand::EngineStop,
+    RemoteCommand::HazardOn,
+];
+
+/// Where a command originated from.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Origin {
+    /// A person at a terminal, whom we can ask to confirm.
+    Interactive,
+    /// Scripts, daemon clients and MQTT: anything without a person to ask.
+    Automation,
+}
+
+/// A range of local time, such as `06:00-22:00`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HourRange {
+    /// Minutes past midnight, inclusive.
+    start: u32,
+    /// Minutes past midnight, exclusive.
+    end: u32,
+}
+
+impl HourRange {
+    /// Whether the given number of minutes past midnight falls within this range.
+    pub fn contains(&self, minutes: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minutes)
+        } else {
+            // We wrap past midnight.
+            minutes >= self.start || minutes < self.end
+        }
+    }
+}
+
+/// Parses `HH:MM` into minutes past midnight.
+fn parse_time(value: &str) -> Option<u32> {
+    let (hours, minutes) = value.trim().split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    // We'll allow 24:00 as the end of the day.
+    (hours < 24 && minutes < 60 || hours == 24 && minutes == 0).then_some(hours * 60 + minutes)
+}
+
+fn format_time(minutes: u32) -> String {
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+impl FromStr for HourRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid hours: {s} (expected e.g. 06:00-22:00)");
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        Ok(HourRange {
+            start: parse_time(start).ok_or_else(invalid)?,
+            end: parse_time(end).ok_or_else(invalid)?,
+        })
+    }
+}
+
+impl fmt::Display for HourRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", format_time(self.start), format_time(self.end))
+    }
+}
+
+impl Serialize for HourRange {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for HourRange {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Why a command was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Refusal {
+    /// This command is only allowed with a person at a terminal.
+    InteractiveOnly(RemoteCommand),
+    /// This command is only allowed during certain hours.
+    OutsideHours {
+        command: RemoteCommand,
+        hours: HourRange,
+        /// The local time, in minutes past midnight, at which we refused.
+        now: u32,
+    },
+    /// This command requires confirmation, and nobody was able to give it.
+    Unconfirmed(RemoteCommand),
+    /// The user declined to confirm.
+    Declined(RemoteCommand),
+    /// No vehicle with this VIN is associated with the account.
+    UnknownVehicle(String),
+    /// The vehicle reports that it doesn't support this command.
+    Unsupported(RemoteCommand),
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refusal::InteractiveOnly(command) => write!(
+                f,
+                "{command} is only allowed interactively, per this profile's policy"
+            ),
+            Refusal::OutsideHours {
+                command,
+                hours,
+                now,
+            } => write!(
+                f,
+                "{command} is only allowed between {} and {} local time, per this profile's policy (it is currently {})",
+                format_time(hours.start),
+                format_time(hours.end),
+                format_time(*now)
+            ),
+            Refusal::Unconfirmed(command) => write!(
+                f,
+                "{command} requires confirmation, but there is nobody to ask"
+            ),
+            Refusal::Declined(command) => write!(f, "{command} was not confirmed"),
+            Refusal::UnknownVehicle(vin) => write!(f, "{vin} is not a vehicle on this account"),
+            Refusal::Unsupported(command) => {
+                write!(f, "this vehicle does not support {command}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Refusal {}
+
+/// Restrictions upon remote commands for a single profile.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Commands requiring confirmation, or ``DEFAULT_CONFIRM`` if unspecified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirm: Option<Vec<RemoteCommand>>,
+    /// Commands never allowed from automation.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub interactive_only: Vec<RemoteCommand>,
+    /// Commands only allowed during these local hours.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub allowed_hours: BTreeMap<RemoteCommand, HourRange>,
+}
+
+impl Policy {
+    /// Whether the given command may be issued right now from the given origin.
+    ///
+    /// Confirmation is left to the caller, per ``Policy::needs_confirmation``.
+    pub fn check(&self, command: RemoteCommand, origin: Origin) -> Result<(), Refusal> {
+        let now = chrono::Local::now();
+        self.check_at(command, origin, now.hour() * 60 + now.minute())
+    }
+
+    /// As with ``Policy::check``, at the given minutes past local midnight.
+    pub fn check_at(
+        &self,
+        command: RemoteCommand,
+        origin: Origin,
+        now: u32,
+    ) -> Result<(), Refusal> {
+        if origin == Origin::Automation && self.interactive_only.contains(&command) {
+            return Err(Refusal::InteractiveOnly(command));
+        }
+        if let Some(hours) = self.allowed_hours.get(&command) {
+            if !hours.contains(now) {
+                return Err(Refusal::OutsideHours {
+                    command,
+                    hours: *hours,
+                    now,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a person should confirm the given command prior to issuing it.
+    pub fn needs_confirmation(&self, command: RemoteCommand) -> bool {
+        match &self.confirm {
+            Some(commands) => commands.contains(&command),
+            None => DEFAULT_CONFIRM.contains(&command),
+        }
+    }
+}
+
+/// Whether the given vehicle may receive the given command, as the API would decide.
+///
+/// The vehicle must belong to the account, and must not report lacking support for the
+/// command. We'll prefer the capabilities within the vehicle list, falling back to those
+/// given (such as from our cache), and proceed with a warning should neither be known.
+pub fn check_eligible(
+    vehicles: &[Vehicle],
+    vin: &str,
+    command: RemoteCommand,
+    fallback: impl FnOnce() -> Option<RemoteCapabilities>,
+) -> Result<(), Refusal> {
+    let Some(vehicle) = vehicles.iter().find(|vehicle| vehicle.vin == vin) else {
+        return Err(Refusal::UnknownVehicle(vin.to_string()));
+    };
+    let capabilities = vehicle
+        .remote_service_capabilities
+        .clone()
+        .or_else(fallback);
+    match capabilities.and_then(|capabilities| capabilities.supports(command)) {
+        Some(true) => Ok(()),
+        Some(false) => Err(Refusal::Unsupported(command)),
+        None => {
+            tracing::warn!("unable to determine whether {vin} supports {command}");
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(value: &str) -> HourRange {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn hours_within_a_day() {
+        let hours = range("06:00-22:00");
+        assert!(!hours.contains(5 * 60 + 59));
+        assert!(hours.contains(6 * 60));
+        assert!(hours.contains(21 * 60 + 59));
+        assert!(!hours.contains(22 * 60));
+        assert_eq!(hours.to_string(), "06:00-22:00");
+    }
+
+    #[test]
+    fn hours_wrap_past_midnight() {
+        let hours = range("22:00-06:00");
+        assert!(hours.contains(22 * 60));
+        assert!(hours.contains(23 * 60 + 59));
+        assert!(hours.contains(0));
+        assert!(hours.contains(5 * 60 + 59));
+        assert!(!hours.contains(6 * 60));
+        assert!(!hours.contains(12 * 60));
+    }
+
+    #[test]
+    fn end_of_day() {
+        assert_eq!(parse_time("24:00"), Some(24 * 60));
+        assert!(range("18:00-24:00").contains(23 * 60 + 59));
+        assert_eq!(range("18:00-24:00").to_string(), "18:00-24:00");
+    }
+
+    #[test]
+    fn invalid_times() {
+        for value in [
+            "", "24:01", "25:00", "12:60", "-1:00", "12", "12:", ":30", "noon", "12:30:00",
+        ] {
+            assert_eq!(parse_time(value), None, "{value:?}");
+        }
+        for value in ["", "06:00", "06:00-", "06:00-25:00", "6-22"] {
+            assert!(value.parse::<HourRange>().is_err(), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn interactive_only() {
+        let policy = Policy {
+            interactive_only: vec![RemoteCommand::DoorUnlock],
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.check_at(RemoteCommand::DoorUnlock, Origin::Automation, 0),
+            Err(Refusal::InteractiveOnly(RemoteCommand::DoorUnlock))
+        );
+        assert_eq!(
+            policy.check_at(RemoteCommand::DoorUnlock, Origin::Interactive, 0),
+            Ok(())
+        );
+        assert_eq!(
+            policy.check_at(RemoteCommand::DoorLock, Origin::Automation, 0),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn outside_hours() {
+        let policy = Policy {
+            allowed_hours: [(RemoteCommand::EngineStart, range("22:00-06:00"))].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.check_at(RemoteCommand::EngineStart, Origin::Interactive, 23 * 60),
+            Ok(())
+        );
+        assert_eq!(
+            policy.check_at(RemoteCommand::EngineStart, Origin::Interactive, 12 * 60),
+            Err(Refusal::OutsideHours {
+                command: RemoteCommand::EngineStart,
+                hours: range("22:00-06:00"),
+                now: 12 * 60,
+            })
+        );
+    }
+
+    #[test]
+    fn needs_confirmation() {
+        let policy = Policy::default();
+        for command in RemoteCommand::ALL {
+            assert_eq!(
+                policy.needs_confirmation(command),
+                DEFAULT_CONFIRM.contains(&command),
+                "{command}"
+            );
+        }
+        assert!(!policy.needs_confirmation(RemoteCommand::DoorLock));
+        assert!(!policy.needs_confirmation(RemoteCommand::HazardOff));
+
+        let policy = Policy {
+            confirm: Some(vec![RemoteCommand::DoorLock]),
+            ..Default::default()
+        };
+        assert!(policy.needs_confirmation(RemoteCommand::DoorLock));
+        assert!(!policy.needs_confirmation(RemoteCommand::DoorUnlock));
+
+        let policy = Policy {
+            confirm: Some(Vec::new()),
+            ..Default::default()
+        };
+        assert!(!policy.needs_confirmation(RemoteCommand::EngineStart));
+    }
+
+    fn vehicle(vin: &str, capabilities: Option<RemoteCapabilities>) -> Vehicle {
+        Vehicle {
+            vin: vin.to_string(),
+            nick_name: None,
+            model_name: None,
+            model_year: None,
+            remote_service_capabilities: capabilities,
+        }
+    }
+
+    fn locks_only() -> RemoteCapabilities {
+        RemoteCapabilities {
+            dlock_unlock_capable: Some(true),
+            estart_stop_capable: Some(false),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unknown_vehicles_are_ineligible() {
+        let vehicles = [vehicle("JTKNOWN0000000000", Some(locks_only()))];
+        assert_eq!(
+            check_eligible(
+                &vehicles,
+                "JTOTHER0000000000",
+                RemoteCommand::DoorLock,
+                || None
+            ),
+            Err(Refusal::UnknownVehicle("JTOTHER0000000000".to_string()))
+        );
+        assert_eq!(
+            check_eligible(&[], "JTKNOWN0000000000", RemoteCommand::DoorLock, || None),
+            Err(Refusal::UnknownVehicle("JTKNOWN0000000000".to_string()))
+        );
+    }
+
+    #[test]
+    fn unsupported_commands_are_ineligible() {
+        let vin = "JTKNOWN0000000000";
+        let vehicles = [vehicle(vin, Some(locks_only()))];
+        assert_eq!(
+            check_eligible(&vehicles, vin, RemoteCommand::DoorUnlock, || None),
+            Ok(())
+        );
+        assert_eq!(
+            check_eligible(&vehicles, vin, RemoteCommand::EngineStart, || None),
+            Err(Refusal::Unsupported(RemoteCommand::EngineStart))
+        );
+
+        // Capabilities from elsewhere apply when the vehicle list lacks them,
+        // but never override what the vehicle list reports.
+        let vehicles = [vehicle(vin, None)];
+        assert_eq!(
+            check_eligible(&vehicles, vin, RemoteCommand::EngineStart, || Some(
+                locks_only()
+            )),
+            Err(Refusal::Unsupported(RemoteCommand::EngineStart))
+        );
+        let vehicles = [vehicle(vin, Some(locks_only()))];
+        let everything = || {
+            Some(RemoteCapabilities {
+                dlock_unlock_capable: Some(true),
+                estart_stop_capable: Some(true),
+                hazard_capable: Some(true),
+            })
+        };
+        assert_eq!(
+            check_eligible(&vehicles, vin, RemoteCommand::EngineStart, everything),
+            Err(Refusal::Unsupported(RemoteCommand::EngineStart))
+        );
+    }
+
+    #[test]
+    fn unknown_capabilities_proceed() {
+        let vin = "JTKNOWN0000000000";
+        // Neither the vehicle list nor our fallback know, so we'll warn and let the API decide.
+        let vehicles = [vehicle(vin, None)];
+        assert_eq!(
+            check_eligible(&vehicles, vin, RemoteCommand::EngineStart, || None),
+            Ok(())
+        );
+        // Nor does a vehicle reporting other capabilities tell us about this one.
+        let vehicles = [vehicle(vin, Some(locks_only()))];
+        assert_eq!(
+            check_eligible(&vehicles, vin, RemoteCommand::HazardOn, || None),
+            Ok(())
+        );
+    }
+}
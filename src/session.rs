@@ -0,0 +1,538 @@
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, io, path::Path};
+
+use crate::{
+    forgerock::{CredentialStorage, DeviceProfile, ForgeRockError, Jwt, JwtError},
+    storage::{CredentialStore, StorageError, StorageKey},
+};
+
+/// The current version of our exported bundle format.
+const BUNDLE_VERSION: u32 = 1;
+
+/// Possible errors while exporting or importing a session.
+#[derive(Debug)]
+pub enum SessionError {
+    /// There is no session stored on this machine to export.
+    NoSession,
+    Storage(StorageError),
+    Io(io::Error),
+    Parse(serde_json::Error),
+    /// The bundle is of a version we do not understand.
+    UnsupportedVersion(u32),
+    /// The bundle could not be decrypted, most likely due to an incorrect passphrase.
+    Decrypt,
+    /// The bundle lacks a refresh token, so its session could never be resumed.
+    MissingRefreshToken,
+    /// The tokens within the bundle are unusable.
+    InvalidToken(ForgeRockError),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::NoSession => write!(f, "no session is stored on this machine"),
+            SessionError::Storage(err) => write!(f, "{err}"),
+            SessionError::Io(err) => write!(f, "unable to access bundle: {err}"),
+            SessionError::Parse(err) => write!(f, "unable to parse bundle: {err}"),
+            SessionError::UnsupportedVersion(version) => {
+                write!(f, "unsupported bundle version {version}")
+            }
+            SessionError::Decrypt => write!(f, "unable to decrypt bundle (wrong passphrase?)"),
+            SessionError::MissingRefreshToken => write!(f, "bundle contains no refresh token"),
+            SessionError::InvalidToken(err) => write!(f, "bundle contains unusable tokens: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+/// Everything necessary to resume a session on another machine.
+#[derive(Deserialize, Serialize)]
+struct SessionBundle {
+    credentials: CredentialStorage,
+    device_profile: DeviceProfile,
+    /// The gateway key may not have been obtained yet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gateway_key: Option<String>,
+}
+
+/// The on-disk format of an exported session.
+///
+/// Our bundle is serialized as JSON, and encrypted with XChaCha20-Poly1305
+/// using a key derived from the user's passphrase via Argon2id.
+#[derive(Deserialize, Serialize)]
+struct EncryptedBundle {
+    version: u32,
+    /// Base64-encoded salt for Argon2.
+    salt: String,
+    /// Base64-encoded nonce for XChaCha20-Poly1305.
+    nonce: String,
+    /// Base64-encoded, encrypted ``SessionBundle``.
+    ciphertext: String,
+}
+
+/// Derives our symmetric key from the given passphrase and salt.
+fn derive_cipher(passphrase: &str, salt: &[u8]) -> Result<XChaCha20Poly1305, SessionError> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|_| SessionError::Decrypt)?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+/// Exports the session within the given store to the given path,
+/// encrypted with the given passphrase.
+pub fn export(
+    store: &dyn CredentialStore,
+    path: &Path,
+    passphrase: &str,
+) -> Result<(), SessionError> {
+    // We must have credentials to begin with.
+    let credential_contents = store
+        .load(StorageKey::Credentials)
+        .map_err(SessionError::Storage)?
+        .ok_or(SessionError::NoSession)?;
+    let credentials: CredentialStorage =
+        serde_json::from_str(&credential_contents).map_err(SessionError::Parse)?;
+
+    let bundle = SessionBundle {
+        credentials,
+        device_profile: DeviceProfile::load_or_create(store),
+        gateway_key: store
+            .load(StorageKey::GatewayKey)
+            .map_err(SessionError::Storage)?,
+    };
+    let plaintext = serde_json::to_vec(&bundle).map_err(SessionError::Parse)?;
+
+    // Encrypt with a fresh salt and nonce.
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = derive_cipher(passphrase, &salt)?
+        .encrypt(&nonce, plaintext.as_slice())
+        .map_err(|_| SessionError::Decrypt)?;
+
+    let encrypted = EncryptedBundle {
+        version: BUNDLE_VERSION,
+        salt: STANDARD.encode(salt),
+        nonce: STANDARD.encode(nonce),
+        ciphertext: STANDARD.encode(ciphertext),
+    };
+    let contents = serde_json::to_string_pretty(&encrypted).map_err(SessionError::Parse)?;
+
+    // While encrypted, our bundle should be no more readable than our keyring.
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path).map_err(SessionError::Io)?;
+    // An existing file keeps its permissions, so we'll narrow them as well.
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))
+        .map_err(SessionError::Io)?;
+    io::Write::write_all(&mut file, contents.as_bytes()).map_err(SessionError::Io)
+}
+
+/// Ensures the given token is a JWT with a subject.
+///
+/// An expired token is fine, as we'll refresh it upon login.
+fn validate_token(token: &str) -> Result<(), SessionError> {
+    let invalid = |err| SessionError::InvalidToken(ForgeRockError::InvalidToken(err));
+    let jwt = Jwt::decode(token).map_err(invalid)?;
+    match jwt.validate_time() {
+        Ok(()) | Err(JwtError::Expired) => {}
+        Err(err) => return Err(invalid(err)),
+    }
+    match jwt.claims.sub {
+        Some(_) => Ok(()),
+        None => Err(invalid(JwtError::MissingClaim("sub"))),
+    }
+}
+
+/// Imports a session from the given path, replacing any within the given store.
+pub fn import(
+    store: &dyn CredentialStore,
+    path: &Path,
+    passphrase: &str,
+) -> Result<(), SessionError> {
+    let contents = fs::read_to_string(path).map_err(SessionError::Io)?;
+    let encrypted: EncryptedBundle =
+        serde_json::from_str(&contents).map_err(SessionError::Parse)?;
+    if encrypted.version != BUNDLE_VERSION {
+        return Err(SessionError::UnsupportedVersion(encrypted.version));
+    }
+
+    // Any malformed base64 is as good as an incorrect passphrase to us.
+    let salt = STANDARD
+        .decode(encrypted.salt)
+        .map_err(|_| SessionError::Decrypt)?;
+    let nonce = STANDARD
+        .decode(encrypted.nonce)
+        .map_err(|_| SessionError::Decrypt)?;
+    let ciphertext = STANDARD
+        .decode(encrypted.ciphertext)
+        .map_err(|_| SessionError::Decrypt)?;
+    if nonce.len() != 24 {
+        return Err(SessionError::Decrypt);
+    }
+
+    let plaintext = derive_cipher(passphrase, &salt)?
+        .decrypt(XNonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| SessionError::Decrypt)?;
+    let bundle: SessionBundle = serde_json::from_slice(&plaintext).map_err(SessionError::Parse)?;
+
+    // Refresh tokens may be opaque, so we can only ensure there is one.
+    // Our access and ID tokens are JWTs, though, and must identify an account.
+    if bundle.credentials.refresh_token.is_empty() {
+        return Err(SessionError::MissingRefreshToken);
+    }
+    validate_token(&bundle.credentials.access_token)?;
+    if let Some(id_token) = &bundle.credentials.id_token {
+        validate_token(id_token)?;
+    }
+
+    // Everything appears valid, so we'll persist it all at once.
+    // A bundle without a gateway key must not leave another account's behind.
+    let device_profile =
+        serde_json::to_string(&bundle.device_profile).map_err(SessionError::Parse)?;
+    let items = [
+        (StorageKey::DeviceProfile, Some(device_profile)),
+        (StorageKey::GatewayKey, bundle.gateway_key),
+        (StorageKey::Credentials, Some(bundle.credentials.to_json())),
+    ];
+    let previous = items
+        .iter()
+        .map(|(key, _)| store.load(*key))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(SessionError::Storage)?;
+
+    for (written, (key, value)) in items.iter().enumerate() {
+        if let Err(err) = replace(store, *key, value.as_deref()) {
+            // Restore whatever we've replaced thus far, leaving the previous session intact.
+            for ((key, _), value) in items.iter().zip(&previous).take(written + 1) {
+                if let Err(err) = replace(store, *key, value.as_deref()) {
+                    tracing::warn!("unable to restore {}: {err}", key.name());
+                }
+            }
+            return Err(SessionError::Storage(err));
+        }
+    }
+
+    Ok(())
+}
+
+/// Stores the given value, or deletes the item entirely if there is none.
+fn replace(
+    store: &dyn CredentialStore,
+    key: StorageKey,
+    value: Option<&str>,
+) -> Result<(), StorageError> {
+    match value {
+        Some(value) => store.store(key, value),
+        None => store.delete(key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use serde_json::json;
+    use std::{io, path::PathBuf, sync::Arc};
+
+    use crate::{
+        environment::Environment,
+        fixture::{Fixture, Interaction, RecordedRequest, RecordedResponse, Replayer},
+        forgerock::{self, Prompt, PromptKind},
+        http::{HttpClient, HttpConfig},
+        storage::MemoryStore,
+    };
+
+    /// An unsigned JWT, which is all we need for decoding.
+    fn token(sub: &str, exp: i64) -> String {
+        let header = URL_SAFE_NO_PAD.encode(json!({"alg": "none"}).to_string());
+        let claims = URL_SAFE_NO_PAD.encode(json!({"sub": sub, "exp": exp}).to_string());
+        format!("{header}.{claims}.")
+    }
+
+    fn now() -> i64 {
+        chrono::Utc::now().timestamp()
+    }
+
+    /// A unique path within our temporary directory, removed once dropped.
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!(
+                "toyotactl-session-{}-{name}.bundle",
+                std::process::id()
+            )))
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn store_with(credentials: &CredentialStorage) -> MemoryStore {
+        let store = MemoryStore::new();
+        store
+            .store(StorageKey::Credentials, &credentials.to_json())
+            .unwrap();
+        store.store(StorageKey::GatewayKey, "gateway-key").unwrap();
+        store
+    }
+
+    fn credentials() -> CredentialStorage {
+        CredentialStorage {
+            access_token: token("guid", now() + 3600),
+            // ForgeRock may well hand us an opaque refresh token.
+            refresh_token: "opaque-refresh-token".to_string(),
+            id_token: Some(token("guid", now() + 3600)),
+        }
+    }
+
+    /// Rewrites the exported bundle on disk.
+    fn tamper(path: &Path, change: impl FnOnce(&mut EncryptedBundle)) {
+        let mut bundle: EncryptedBundle =
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        change(&mut bundle);
+        fs::write(path, serde_json::to_string(&bundle).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = TempPath::new("round-trip");
+        let source = store_with(&credentials());
+        let profile = DeviceProfile::load_or_create(&source);
+        export(&source, &path.0, "passphrase").unwrap();
+
+        let destination = MemoryStore::new();
+        import(&destination, &path.0, "passphrase").unwrap();
+        for key in [StorageKey::Credentials, StorageKey::GatewayKey] {
+            assert_eq!(destination.load(key).unwrap(), source.load(key).unwrap());
+        }
+        let imported = DeviceProfile::load_or_create(&destination);
+        assert_eq!(imported.hardware_id, profile.hardware_id);
+    }
+
+    #[test]
+    fn expired_access_token() {
+        let path = TempPath::new("expired");
+        let mut expired = credentials();
+        expired.access_token = token("guid", now() - 3600);
+        export(&store_with(&expired), &path.0, "passphrase").unwrap();
+
+        import(&MemoryStore::new(), &path.0, "passphrase").unwrap();
+    }
+
+    #[test]
+    fn no_session() {
+        let path = TempPath::new("no-session");
+        let result = export(&MemoryStore::new(), &path.0, "passphrase");
+        assert!(matches!(result, Err(SessionError::NoSession)));
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let path = TempPath::new("wrong-passphrase");
+        export(&store_with(&credentials()), &path.0, "passphrase").unwrap();
+
+        let destination = MemoryStore::new();
+        let result = import(&destination, &path.0, "not the passphrase");
+        assert!(matches!(result, Err(SessionError::Decrypt)));
+        assert_eq!(destination.load(StorageKey::Credentials).unwrap(), None);
+    }
+
+    #[test]
+    fn corrupted_ciphertext() {
+        let path = TempPath::new("corrupted");
+        export(&store_with(&credentials()), &path.0, "passphrase").unwrap();
+        tamper(&path.0, |bundle| {
+            let mut ciphertext = STANDARD.decode(&bundle.ciphertext).unwrap();
+            ciphertext[0] ^= 1;
+            bundle.ciphertext = STANDARD.encode(ciphertext);
+        });
+
+        let result = import(&MemoryStore::new(), &path.0, "passphrase");
+        assert!(matches!(result, Err(SessionError::Decrypt)));
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let path = TempPath::new("version");
+        export(&store_with(&credentials()), &path.0, "passphrase").unwrap();
+        tamper(&path.0, |bundle| bundle.version = BUNDLE_VERSION + 1);
+
+        let result = import(&MemoryStore::new(), &path.0, "passphrase");
+        assert!(matches!(
+            result,
+            Err(SessionError::UnsupportedVersion(version)) if version == BUNDLE_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn invalid_tokens() {
+        let mut malformed = credentials();
+        malformed.access_token = "not a jwt".to_string();
+        let mut no_refresh = credentials();
+        no_refresh.refresh_token = String::new();
+
+        for (name, credentials) in [("malformed", malformed), ("no-refresh", no_refresh)] {
+            let path = TempPath::new(name);
+            export(&store_with(&credentials), &path.0, "passphrase").unwrap();
+
+            let destination = MemoryStore::new();
+            let result = import(&destination, &path.0, "passphrase");
+            assert!(matches!(
+                result,
+                Err(SessionError::InvalidToken(_) | SessionError::MissingRefreshToken)
+            ));
+            assert_eq!(destination.load(StorageKey::Credentials).unwrap(), None);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn export_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = TempPath::new("private");
+        fs::write(&path.0, "").unwrap();
+        fs::set_permissions(&path.0, fs::Permissions::from_mode(0o644)).unwrap();
+        export(&store_with(&credentials()), &path.0, "passphrase").unwrap();
+
+        let mode = fs::metadata(&path.0).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn missing_gateway_key_is_cleared() {
+        let path = TempPath::new("no-gateway-key");
+        let source = store_with(&credentials());
+        source.delete(StorageKey::GatewayKey).unwrap();
+        export(&source, &path.0, "passphrase").unwrap();
+
+        // Our destination has a key from some other session.
+        let destination = store_with(&credentials());
+        import(&destination, &path.0, "passphrase").unwrap();
+        assert_eq!(destination.load(StorageKey::GatewayKey).unwrap(), None);
+    }
+
+    /// A store refusing to persist credentials, as if the keyring were locked midway.
+    #[derive(Debug)]
+    struct FailingStore(MemoryStore);
+
+    impl CredentialStore for FailingStore {
+        fn load(&self, key: StorageKey) -> Result<Option<String>, StorageError> {
+            self.0.load(key)
+        }
+
+        fn store(&self, key: StorageKey, value: &str) -> Result<(), StorageError> {
+            if key == StorageKey::Credentials {
+                return Err(StorageError::Io(io::Error::other("locked")));
+            }
+            self.0.store(key, value)
+        }
+
+        fn delete(&self, key: StorageKey) -> Result<(), StorageError> {
+            self.0.delete(key)
+        }
+    }
+
+    #[test]
+    fn failed_import_keeps_previous_session() {
+        let path = TempPath::new("failed");
+        let source = store_with(&credentials());
+        DeviceProfile::load_or_create(&source);
+        source.delete(StorageKey::GatewayKey).unwrap();
+        export(&source, &path.0, "passphrase").unwrap();
+
+        let destination = FailingStore(store_with(&credentials()));
+        let profile = DeviceProfile::load_or_create(&destination.0);
+        let result = import(&destination, &path.0, "passphrase");
+        assert!(matches!(result, Err(SessionError::Storage(_))));
+
+        let kept = DeviceProfile::load_or_create(&destination);
+        assert_eq!(kept.hardware_id, profile.hardware_id);
+        assert_eq!(
+            destination.load(StorageKey::GatewayKey).unwrap().as_deref(),
+            Some("gateway-key")
+        );
+    }
+
+    /// Refreshing must never prompt.
+    struct NoPrompt;
+
+    impl Prompt for NoPrompt {
+        fn prompt(&self, kind: PromptKind) -> io::Result<String> {
+            panic!("unexpectedly prompted for {kind:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn opaque_refresh_token_is_refreshed() {
+        let path = TempPath::new("opaque-refresh");
+        let mut expired = credentials();
+        expired.access_token = token("guid", now() - 3600);
+        export(&store_with(&expired), &path.0, "passphrase").unwrap();
+
+        let store = Arc::new(MemoryStore::new());
+        import(store.as_ref(), &path.0, "passphrase").unwrap();
+
+        // ForgeRock exchanges our opaque refresh token for another, twice.
+        let environment = Environment::production();
+        let refreshed = |refresh_token: &str| Interaction {
+            request: RecordedRequest {
+                method: "POST".to_string(),
+                url: environment.access_token_endpoint(),
+                body: None,
+            },
+            response: RecordedResponse {
+                status: 200,
+                headers: vec![],
+                body: json!({
+                    "access_token": token("guid", now() + 3600),
+                    "refresh_token": refresh_token,
+                })
+                .to_string(),
+            },
+        };
+        let replayer = Arc::new(Replayer::new(Fixture {
+            interactions: vec![refreshed("second"), refreshed("third")],
+        }));
+        let http = HttpClient::new(&HttpConfig::default())
+            .unwrap()
+            .with_replayer(Arc::clone(&replayer));
+
+        // Logging in with an expired access token refreshes...
+        let client = forgerock::login(&http, &environment, store.clone(), &NoPrompt)
+            .await
+            .unwrap();
+        assert_eq!(client.guid(), "guid");
+
+        // ...as does explicitly refreshing, despite our new refresh token being opaque, too.
+        expired.access_token = token("guid", now() - 3600);
+        expired.refresh_token = "second".to_string();
+        expired.save(store.as_ref()).unwrap();
+        let client = forgerock::refresh(&http, &environment, store.clone())
+            .await
+            .unwrap();
+        assert_eq!(client.guid(), "guid");
+        assert!(replayer.is_exhausted());
+
+        let stored = forgerock::CredentialStorage::load(store.as_ref())
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.refresh_token, "third");
+    }
+}
@@ -0,0 +1,267 @@
+use keyring::Entry;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Items we persist across invocations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageKey {
+    /// Our serialized ``CredentialStorage``.
+    Credentials,
+    /// Our serialized ``DeviceProfile``.
+    DeviceProfile,
+    /// The API gateway key extracted from `toyota-na`.
+    GatewayKey,
+}
+
+impl StorageKey {
+    /// The name this item is stored under.
+    pub fn name(&self) -> &'static str {
+        match self {
+            StorageKey::Credentials => "OAuth2 Credentials",
+            StorageKey::DeviceProfile => "Device Profile",
+            StorageKey::GatewayKey => "API Gateway Key",
+        }
+    }
+}
+
+/// Possible errors while accessing storage.
+#[derive(Debug)]
+pub enum StorageError {
+    Keyring(keyring::Error),
+    Io(io::Error),
+    Parse(serde_json::Error),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Keyring(err) => write!(f, "unable to access keyring: {err}"),
+            StorageError::Io(err) => write!(f, "unable to access credential file: {err}"),
+            StorageError::Parse(err) => write!(f, "unable to parse credential file: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+/// Somewhere to persist our secrets.
+///
+/// By default, we use the user's keyring via ``KeyringStore``.
+/// Embedders can provide their own, or use ``MemoryStore`` to persist nothing.
+pub trait CredentialStore: fmt::Debug + Send + Sync {
+    /// Loads the given item, returning `None` if it has not yet been stored.
+    fn load(&self, key: StorageKey) -> Result<Option<String>, StorageError>;
+    /// Stores the given item, replacing any previous value.
+    fn store(&self, key: StorageKey, value: &str) -> Result<(), StorageError>;
+    /// Deletes the given item. Deleting an item not stored is not an error.
+    fn delete(&self, key: StorageKey) -> Result<(), StorageError>;
+}
+
+/// The name of the profile used when none is specified.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Whether the given profile name is usable.
+///
+/// Profile names become file and directory names, such as for our cache,
+/// so we'll refuse anything that could escape them: only ASCII letters,
+/// digits, `-` and `_` are allowed.
+pub fn is_valid_profile(profile: &str) -> bool {
+    !profile.is_empty()
+        && profile
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The name of the given item for the given profile, or `None` for the default.
+fn item_name(profile: Option<&str>, key: StorageKey) -> String {
+    match (profile, key) {
+        (Some(profile), StorageKey::Credentials | StorageKey::DeviceProfile) => {
+            format!("{} ({profile})", key.name())
+        }
+        _ => key.name().to_string(),
+    }
+}
+
+/// Stores items within the user's keyring, under the `toyotactl` service.
+///
+/// Multiple accounts can coexist via profiles, each with their own items.
+/// The API gateway key is shared across all profiles.
+#[derive(Debug, Default)]
+pub struct KeyringStore {
+    /// The profile this store is for, or `None` for the default.
+    profile: Option<String>,
+}
+
+impl KeyringStore {
+    /// Creates a store for the default profile.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a store for the given profile.
+    pub fn for_profile(profile: &str) -> Self {
+        // The default profile predates profiles entirely,
+        // so we continue to store its items under their plain names.
+        if profile == DEFAULT_PROFILE {
+            return Self::new();
+        }
+
+        Self {
+            profile: Some(profile.to_string()),
+        }
+    }
+
+    fn entry(&self, key: StorageKey) -> Result<Entry, StorageError> {
+        Entry::new("toyotactl", &item_name(self.profile.as_deref(), key))
+            .map_err(StorageError::Keyring)
+    }
+}
+
+impl CredentialStore for KeyringStore {
+    fn load(&self, key: StorageKey) -> Result<Option<String>, StorageError> {
+        match self.entry(key)?.get_password() {
+            Ok(value) => Ok(Some(value)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(err) => Err(StorageError::Keyring(err)),
+        }
+    }
+
+    fn store(&self, key: StorageKey, value: &str) -> Result<(), StorageError> {
+        self.entry(key)?
+            .set_password(value)
+            .map_err(StorageError::Keyring)
+    }
+
+    fn delete(&self, key: StorageKey) -> Result<(), StorageError> {
+        match self.entry(key)?.delete_password() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(err) => Err(StorageError::Keyring(err)),
+        }
+    }
+}
+
+/// Stores items in memory, discarding them once dropped.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    items: Mutex<HashMap<StorageKey, String>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CredentialStore for MemoryStore {
+    fn load(&self, key: StorageKey) -> Result<Option<String>, StorageError> {
+        let items = self
+            .items
+            .lock()
+            .expect("memory store should not be poisoned");
+        Ok(items.get(&key).cloned())
+    }
+
+    fn store(&self, key: StorageKey, value: &str) -> Result<(), StorageError> {
+        let mut items = self
+            .items
+            .lock()
+            .expect("memory store should not be poisoned");
+        items.insert(key, value.to_string());
+        Ok(())
+    }
+
+    fn delete(&self, key: StorageKey) -> Result<(), StorageError> {
+        let mut items = self
+            .items
+            .lock()
+            .expect("memory store should not be poisoned");
+        items.remove(&key);
+        Ok(())
+    }
+}
+
+/// Stores items within a plain JSON file, such as on headless machines without a keyring.
+///
+/// Items are named as they are within the keyring, so that multiple profiles can share a file.
+/// They are not encrypted whatsoever: we only ensure the file is readable by its owner alone.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    profile: Option<String>,
+    /// Serializes our read-modify-write cycles.
+    lock: Mutex<()>,
+}
+
+impl FileStore {
+    /// Creates a store for the given profile, backed by the file at the given path.
+    pub fn new(path: &Path, profile: &str) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            profile: (profile != DEFAULT_PROFILE).then(|| profile.to_string()),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Where we store credentials by default, within the user's data directory.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(dirs::data_dir()?.join("toyotactl").join("credentials.json"))
+    }
+
+    fn read(&self) -> Result<BTreeMap<String, String>, StorageError> {
+        match fs::read(&self.path) {
+            Ok(contents) => serde_json::from_slice(&contents).map_err(StorageError::Parse),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(err) => Err(StorageError::Io(err)),
+        }
+    }
+
+    fn write(&self, items: &BTreeMap<String, String>) -> Result<(), StorageError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(StorageError::Io)?;
+        }
+        let contents = serde_json::to_vec_pretty(items).map_err(StorageError::Parse)?;
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&self.path).map_err(StorageError::Io)?;
+        io::Write::write_all(&mut file, &contents).map_err(StorageError::Io)
+    }
+
+    /// Reads our file, applies the given change, and writes it back.
+    fn modify(
+        &self,
+        change: impl FnOnce(&mut BTreeMap<String, String>),
+    ) -> Result<(), StorageError> {
+        let _guard = self.lock.lock().unwrap_or_else(|err| err.into_inner());
+        let mut items = self.read()?;
+        change(&mut items);
+        self.write(&items)
+    }
+}
+
+impl CredentialStore for FileStore {
+    fn load(&self, key: StorageKey) -> Result<Option<String>, StorageError> {
+        let items = self.read()?;
+        Ok(items.get(&item_name(self.profile.as_deref(), key)).cloned())
+    }
+
+    fn store(&self, key: StorageKey, value: &str) -> Result<(), StorageError> {
+        let name = item_name(self.profile.as_deref(), key);
+        self.modify(|items| {
+            items.insert(name, value.to_string());
+        })
+    }
+
+    fn delete(&self, key: StorageKey) -> Result<(), StorageError> {
+        let name = item_name(self.profile.as_deref(), key);
+        self.modify(|items| {
+            items.remove(&name);
+        })
+    }
+}
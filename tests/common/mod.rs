@@ -0,0 +1,23 @@
+//! Helpers shared across our integration tests.
+
+use std::{io, sync::Mutex};
+
+use toyotactl::forgerock::{Prompt, PromptKind};
+
+/// Answers prompts as the mock's default account would, remembering what was asked.
+#[derive(Default)]
+pub struct ScriptedPrompt {
+    pub asked: Mutex<Vec<PromptKind>>,
+}
+
+impl Prompt for ScriptedPrompt {
+    fn prompt(&self, kind: PromptKind) -> io::Result<String> {
+        self.asked.lock().unwrap().push(kind);
+        let answer = match kind {
+            PromptKind::Username => "user@example.com",
+            PromptKind::Password => "password",
+            PromptKind::OneTimePassword => "123456",
+        };
+        Ok(answer.to_string())
+    }
+}
@@ -0,0 +1,297 @@
+//! Driving ``ApiClient`` against `toyotactl-mock`.
+#![cfg(feature = "mock")]
+
+mod common;
+
+use std::{
+    io::{BufRead, BufReader},
+    process::{Child, Command, Stdio},
+    sync::Arc,
+    time::Duration,
+};
+
+use common::ScriptedPrompt;
+use toyotactl::{
+    api::{self, ApiClient, GatewayKeyConfig, RemoteCommand, VehicleStatus},
+    audit::{AuditLog, Outcome},
+    environment::Environment,
+    forgerock::{self, CredentialStorage, JwksCache, JwtError},
+    http::{HttpClient, HttpConfig},
+    storage::{CredentialStore, MemoryStore},
+};
+
+const VIN: &str = "JTMOCK00000000001";
+
+/// A running mock, stopped once dropped.
+struct Mock {
+    child: Child,
+    environment: Environment,
+}
+
+impl Mock {
+    /// Starts the mock on any available port, waiting until it's listening.
+    fn start(args: &[&str]) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_toyotactl-mock"))
+            .args(["--listen", "127.0.0.1:0"])
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("mock should start");
+
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let base = line
+            .trim()
+            .strip_prefix("Listening on ")
+            .unwrap_or_else(|| panic!("unexpected output from mock: {line}"));
+
+        let mut environment = Environment::production();
+        environment.forgerock_base = format!("{base}/").parse().unwrap();
+        environment.api_gateway_base = format!("{base}/oneapi/").parse().unwrap();
+        environment.command_status_path = Some("mock/remote/command/status".to_string());
+        Self { child, environment }
+    }
+
+    /// Logs in, returning our client alongside the store holding our tokens.
+    async fn login(&self, http: &HttpClient) -> (ApiClient, Arc<dyn CredentialStore>) {
+        let store: Arc<dyn CredentialStore> = Arc::new(MemoryStore::new());
+        let config = GatewayKeyConfig {
+            key: Some("mock-gateway-key".to_string()),
+            ..GatewayKeyConfig::default()
+        };
+        api::ensure_gateway_key(&config, http, &self.environment, store.as_ref())
+            .await
+            .unwrap();
+        let client = forgerock::login(
+            http,
+            &self.environment,
+            store.clone(),
+            &ScriptedPrompt::default(),
+        )
+        .await
+        .expect("login should succeed");
+        (client.with_gateway_key_config(config), store)
+    }
+}
+
+impl Drop for Mock {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn http() -> HttpClient {
+    HttpClient::new(&HttpConfig::default()).unwrap()
+}
+
+/// The value of the given status section, such as "Engine".
+fn section(status: &VehicleStatus, name: &str) -> String {
+    status
+        .vehicle_status
+        .iter()
+        .flat_map(|category| &category.sections)
+        .find(|section| section.section == name)
+        .and_then(|section| section.values.first())
+        .map(|value| value.value.clone())
+        .unwrap_or_else(|| panic!("status should report {name}"))
+}
+
+#[tokio::test]
+async fn vehicles_status_and_commands() {
+    let mock = Mock::start(&[]);
+    let http = http();
+    let (client, _) = mock.login(&http).await;
+
+    let vehicles = client.vehicles().await.unwrap();
+    assert_eq!(vehicles.len(), 1);
+    assert_eq!(vehicles[0].vin, VIN);
+
+    assert_eq!(
+        client.vehicle_status(VIN).await.unwrap().locked(),
+        Some(true)
+    );
+    let result = client
+        .remote_command(VIN, RemoteCommand::DoorUnlock)
+        .await
+        .unwrap();
+    assert_eq!(result.return_code.as_deref(), Some("000000"));
+    assert_eq!(
+        client.vehicle_status(VIN).await.unwrap().locked(),
+        Some(false)
+    );
+
+    let telemetry = client.telemetry(VIN).await.unwrap();
+    assert_eq!(telemetry.fuel_level, Some(75.0));
+}
+
+#[tokio::test]
+async fn engine_stop_cancels_pending_start() {
+    let mock = Mock::start(&["--engine-delay", "1"]);
+    let http = http();
+    let (client, _) = mock.login(&http).await;
+
+    client
+        .remote_command(VIN, RemoteCommand::EngineStart)
+        .await
+        .unwrap();
+    client
+        .remote_command(VIN, RemoteCommand::EngineStop)
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let status = client.vehicle_status(VIN).await.unwrap();
+    assert_eq!(section(&status, "Engine"), "Off");
+
+    // Left alone, the engine does start.
+    client
+        .remote_command(VIN, RemoteCommand::EngineStart)
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let status = client.vehicle_status(VIN).await.unwrap();
+    assert_eq!(section(&status, "Engine"), "Running");
+}
+
+#[tokio::test]
+async fn tokens_verify_against_jwks() {
+    let mock = Mock::start(&[]);
+    let http = http();
+    let (_, store) = mock.login(&http).await;
+    let credentials = CredentialStorage::load(store.as_ref()).unwrap().unwrap();
+
+    let path =
+        std::env::temp_dir().join(format!("toyotactl-mock-jwks-{}.json", std::process::id()));
+    let cache = JwksCache::new(path.clone());
+    let verified = cache
+        .verify(&http, &mock.environment, &credentials.access_token)
+        .await;
+    let id_token = credentials.id_token.as_deref().unwrap();
+    let verified_id = cache.verify(&http, &mock.environment, id_token).await;
+
+    // Pairing one token's signature with another's claims must fail.
+    let tampered = {
+        let (signing_input, _) = credentials.access_token.rsplit_once('.').unwrap();
+        let (_, signature) = id_token.rsplit_once('.').unwrap();
+        format!("{signing_input}.{signature}")
+    };
+    let rejected = cache.verify(&http, &mock.environment, &tampered).await;
+    let _ = std::fs::remove_file(&path);
+
+    assert!(verified.is_ok(), "{verified:?}");
+    assert!(verified_id.is_ok(), "{verified_id:?}");
+    assert!(matches!(rejected, Err(JwtError::InvalidSignature)));
+}
+
+#[tokio::test]
+async fn audit_records_final_outcomes() {
+    let mock = Mock::start(&["--engine-delay", "1"]);
+    let http = http();
+    let (client, _) = mock.login(&http).await;
+
+    let path =
+        std::env::temp_dir().join(format!("toyotactl-mock-audit-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let audit = AuditLog::open(&path)
+        .unwrap()
+        .with_polling(Duration::from_millis(100), Duration::from_secs(5));
+
+    audit
+        .issue(&client, "default", VIN, RemoteCommand::DoorUnlock)
+        .await
+        .unwrap();
+    audit
+        .issue(&client, "default", VIN, RemoteCommand::EngineStart)
+        .await
+        .unwrap();
+
+    // A start stopped before the engine runs never completes.
+    let (cancelled, _) = tokio::join!(
+        audit.issue(&client, "default", VIN, RemoteCommand::EngineStart),
+        async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            client
+                .remote_command(VIN, RemoteCommand::EngineStop)
+                .await
+                .unwrap();
+        }
+    );
+    cancelled.unwrap();
+
+    // Nor do we wait forever.
+    let impatient = AuditLog::open(&path)
+        .unwrap()
+        .with_polling(Duration::from_millis(100), Duration::from_millis(300));
+    impatient
+        .issue(&client, "default", VIN, RemoteCommand::EngineStart)
+        .await
+        .unwrap();
+
+    // Each command is recorded once accepted, and again once we learn how it turned out.
+    let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+    let entries = AuditLog::entries(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(lines, 8);
+    let outcomes: Vec<_> = entries.iter().map(|entry| &entry.outcome).collect();
+    assert!(matches!(
+        outcomes[..],
+        [
+            Outcome::Completed { .. },
+            Outcome::Completed { .. },
+            Outcome::Failed { .. },
+            Outcome::TimedOut { .. },
+        ]
+    ));
+    assert!(entries.iter().all(|entry| entry.request_id.is_some()));
+    assert_eq!(
+        entries[0].parameters,
+        Some(serde_json::json!({ "command": "door-unlock" }))
+    );
+}
+
+#[tokio::test]
+async fn audit_records_accepted_when_polling_fails() {
+    let mut mock = Mock::start(&[]);
+    let http = http();
+
+    let path = std::env::temp_dir().join(format!(
+        "toyotactl-mock-audit-accepted-{}.jsonl",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    let audit = AuditLog::open(&path)
+        .unwrap()
+        .with_polling(Duration::from_millis(100), Duration::from_secs(30));
+
+    // Without anywhere to ask, we never poll.
+    mock.environment.command_status_path = None;
+    let (client, _) = mock.login(&http).await;
+    audit
+        .issue(&client, "default", VIN, RemoteCommand::DoorLock)
+        .await
+        .unwrap();
+
+    // Nor do we keep polling once the API tells us it never will answer.
+    mock.environment.command_status_path = Some("mock/nowhere".to_string());
+    let (client, _) = mock.login(&http).await;
+    let started = std::time::Instant::now();
+    audit
+        .issue(&client, "default", VIN, RemoteCommand::DoorLock)
+        .await
+        .unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+    let entries = AuditLog::entries(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(lines, 2);
+    assert!(matches!(
+        entries
+            .iter()
+            .map(|entry| &entry.outcome)
+            .collect::<Vec<_>>()[..],
+        [Outcome::Accepted { .. }, Outcome::Accepted { .. }]
+    ));
+}
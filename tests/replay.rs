@@ -0,0 +1,56 @@
+//! Logging in against a recorded fixture, without a network.
+
+mod common;
+
+use std::{path::Path, sync::Arc};
+
+use common::ScriptedPrompt;
+use toyotactl::{
+    environment::Environment,
+    fixture::Replayer,
+    forgerock::{self, CredentialStorage, PromptKind},
+    http::{HttpClient, HttpConfig},
+    storage::{CredentialStore, MemoryStore},
+};
+
+/// Recorded against `toyotactl-mock` via `--record`: the locale and username
+/// (`NameCallback`), password and one-time password (`PasswordCallback`) and device
+/// fingerprint (`HiddenValueCallback`) stages, then authorization and our token exchange.
+fn fixture() -> Replayer {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/login.json");
+    Replayer::load(&path).expect("fixture should load")
+}
+
+#[tokio::test]
+async fn replayed_login() {
+    let replayer = Arc::new(fixture());
+    let http = HttpClient::new(&HttpConfig::default())
+        .unwrap()
+        .with_replayer(Arc::clone(&replayer));
+    // Replaying only compares paths, so production hosts are never contacted.
+    let environment = Environment::production();
+    let store: Arc<dyn CredentialStore> = Arc::new(MemoryStore::new());
+    let prompt = ScriptedPrompt::default();
+
+    let client = forgerock::login(&http, &environment, store.clone(), &prompt)
+        .await
+        .expect("replayed login should succeed");
+
+    assert_eq!(client.guid(), "00000000-0000-4000-8000-000000000000");
+    assert_eq!(
+        *prompt.asked.lock().unwrap(),
+        [
+            PromptKind::Username,
+            PromptKind::Password,
+            PromptKind::OneTimePassword
+        ]
+    );
+    assert!(replayer.is_exhausted(), "every interaction should be used");
+
+    // Our scrubbed tokens were saved to the store we were given, and remain usable.
+    let credentials = CredentialStorage::load(store.as_ref()).unwrap().unwrap();
+    assert_eq!(
+        forgerock::get_sub(&credentials.access_token).unwrap(),
+        client.guid()
+    );
+}